@@ -0,0 +1,348 @@
+use std::collections::VecDeque;
+
+use crate::path::{Path, PathError};
+use crate::{can_step, get_grid_neighbors, get_neighbors, node_index, Connectivity, Node, Topology};
+use crate::{MAP_HEIGHT, MAP_WIDTH};
+
+
+// how many nodes a second an agent covers on plain ground. Rougher terrain slows it down by its cost.
+const AGENT_SPEED: f32 = 3.0;
+// how far from its middle an agent keeps the others, in nodes
+pub const AGENT_RADIUS: f32 = 0.3;
+// how many seconds an agent waits for another one to get out of its way before it looks for a way around it
+const MAX_WAIT: f32 = 1.5;
+
+
+/**
+Something walking along a path over time. Its position is continuous, in nodes like the hybrid poses, so it glides
+from node to node instead of jumping.
+ */
+pub struct Agent {
+    pub x: f32,
+    pub y: f32,
+    pub z: i32,
+    pub goal: usize,
+    // the nodes still ahead, the next one first, and whether we get to each one through a portal rather than walking
+    route: VecDeque<(usize, bool)>,
+    // set when the last plan didn't get all the way to the goal. We plan again once the map changes.
+    pub stuck: bool,
+    // how long we have been waiting for the agents in our way since we last got to a node
+    waited: f32,
+}
+
+impl Agent {
+    pub fn new(start: usize, goal: usize, nodes: &[Node]) -> Agent {
+        let node = &nodes[start];
+        Agent { x: node.x as f32, y: node.y as f32, z: node.z, goal, route: VecDeque::new(), stuck: false, waited: 0.0 }
+    }
+
+    /**
+    The node the agent is standing in.
+     */
+    pub fn node(&self) -> usize {
+        let x = ((self.x + 0.5).floor() as i32).clamp(0, MAP_WIDTH - 1);
+        let y = ((self.y + 0.5).floor() as i32).clamp(0, MAP_HEIGHT - 1);
+        node_index(x, y, self.z)
+    }
+
+    pub fn arrived(&self) -> bool {
+        self.route.is_empty() && self.node() == self.goal
+    }
+
+    /**
+    Follows `path` from now on. A path that stops short of the goal, or no path at all, leaves the agent stuck.
+     */
+    pub fn set_path(&mut self, path: Result<Path, PathError>, nodes: &[Node], topology: Topology) {
+        self.route.clear();
+        match path {
+            Ok(path) => {
+                self.stuck = path.partial.is_some();
+                for step in path.nodes.windows(2) {
+                    let walked = get_grid_neighbors(step[0], nodes, topology).contains(&step[1]);
+                    self.route.push_back((step[1], !walked));
+                }
+            }
+            Err(_) => self.stuck = true,
+        }
+    }
+
+    /**
+    Whether one of the steps still ahead can't be taken any more, because an obstacle or a wall turned up on it, an exit
+    on the way was closed or a portal we meant to take is gone.
+     */
+    pub fn route_blocked(&self, nodes: &[Node], connectivity: &Connectivity) -> bool {
+        let mut from = self.node();
+        for &(to, _) in &self.route {
+            // halfway to the next node we are already standing in it
+            if to != from && !get_neighbors(from, nodes, connectivity).iter().any(|&(index, _)| index == to) {
+                return true;
+            }
+            from = to;
+        }
+        false
+    }
+
+    /**
+    Whether `other` is close enough ahead of us on our way to the next node that we would walk into it.
+     */
+    fn is_in_the_way(&self, other: &Agent, nodes: &[Node], topology: Topology) -> bool {
+        let next = match self.route.front() {
+            Some(&(next, false)) => &nodes[next],
+            _ => return false,
+        };
+        let (to_next_x, to_next_y) = (wrapped_delta(self.x, next.x as f32, MAP_WIDTH, topology),
+                                      wrapped_delta(self.y, next.y as f32, MAP_HEIGHT, topology));
+        let (to_other_x, to_other_y) = (wrapped_delta(self.x, other.x, MAP_WIDTH, topology),
+                                        wrapped_delta(self.y, other.y, MAP_HEIGHT, topology));
+        other.z == self.z
+            && to_other_x.hypot(to_other_y) < 2.0 * AGENT_RADIUS
+            && to_next_x * to_other_x + to_next_y * to_other_y > 0.0
+    }
+
+    /**
+    Moves the agent along its route for `elapsed_time` seconds. It walks at the speed of the terrain it's heading onto,
+    so one frame can take it across several nodes at different speeds, and it goes through portals in no time at all.
+     */
+    fn advance(&mut self, elapsed_time: f32, nodes: &[Node], topology: Topology) {
+        let mut time_left = elapsed_time;
+        while let Some(&(next, through_portal)) = self.route.front() {
+            let target = &nodes[next];
+            if through_portal {
+                (self.x, self.y, self.z) = (target.x as f32, target.y as f32, target.z);
+                self.route.pop_front();
+                continue;
+            }
+            if time_left <= 0.0 {
+                break;
+            }
+
+            // on a toroidal map the next node can be across the edge, so we head for wherever it is closest
+            let dx = wrapped_delta(self.x, target.x as f32, MAP_WIDTH, topology);
+            let dy = wrapped_delta(self.y, target.y as f32, MAP_HEIGHT, topology);
+            let distance = dx.hypot(dy);
+            let speed = AGENT_SPEED / target.terrain.cost() as f32;
+
+            if distance <= speed * time_left {
+                (self.x, self.y) = (target.x as f32, target.y as f32);
+                time_left -= distance / speed;
+                self.route.pop_front();
+                self.waited = 0.0;
+            } else {
+                let scale = speed * time_left / distance;
+                self.x = wrap(self.x + dx * scale, MAP_WIDTH, topology);
+                self.y = wrap(self.y + dy * scale, MAP_HEIGHT, topology);
+                time_left = 0.0;
+            }
+        }
+    }
+}
+
+
+/**
+Moves every agent along its route for one frame, unless another one is in its way, then pushes apart the ones that
+ended up too close and drops the ones that got to their goal. An agent whose route runs into a new obstacle or wall asks
+`plan` for a new path from the node it's in, and so does a stuck agent when `map_changed` says the way might be open
+now. `plan` also gets the nodes the path has to keep out of, which are the ones other agents hold up for too long.
+ */
+pub fn update_agents(agents: &mut Vec<Agent>,
+                     elapsed_time: f32,
+                     nodes: &[Node],
+                     connectivity: &Connectivity,
+                     map_changed: bool,
+                     mut plan: impl FnMut(usize, usize, &[usize]) -> Result<Path, PathError>) {
+    let topology = connectivity.topology;
+    for index in 0..agents.len() {
+        let agent = &mut agents[index];
+        if agent.route_blocked(nodes, connectivity) || (agent.stuck && map_changed) {
+            let path = plan(agent.node(), agent.goal, &[]);
+            agent.set_path(path, nodes, topology);
+        }
+
+        // an agent waits for the ones that came before it to get out of its way, which queues up agents that share a
+        // route. Only ever waiting for earlier agents means two of them can't end up waiting for each other.
+        let blockers: Vec<usize> = agents[..index].iter()
+            .filter(|other| agents[index].is_in_the_way(other, nodes, topology))
+            .map(|other| other.node())
+            .collect();
+        if blockers.is_empty() {
+            agents[index].advance(elapsed_time, nodes, topology);
+            continue;
+        }
+
+        // one that waits too long, say behind an agent that is stuck itself, looks for a way around the nodes the
+        // agents in its way are on and the node it was heading for. If there's none it keeps waiting and tries again
+        // later.
+        let agent = &mut agents[index];
+        agent.waited += elapsed_time;
+        if agent.waited > MAX_WAIT {
+            agent.waited = 0.0;
+            let (here, goal) = (agent.node(), agent.goal);
+            let mut occupied = blockers;
+            occupied.extend(agent.route.front().map(|&(next, _)| next));
+            occupied.retain(|&index| index != here && index != goal);
+            if let Ok(path) = plan(here, goal, &occupied) {
+                if path.partial.is_none() {
+                    agent.set_path(Ok(path), nodes, topology);
+                }
+            }
+        }
+    }
+
+    separate(agents, nodes, topology);
+    agents.retain(|agent| !agent.arrived());
+}
+
+/**
+Local avoidance: every pair of agents on the same layer that overlap gets pushed apart along the line between them,
+each by half the overlap. A push that would shove an agent into an obstacle, or over into a node it couldn't step to,
+is left out, and the agents walk back onto their routes from wherever they end up.
+ */
+fn separate(agents: &mut [Agent], nodes: &[Node], topology: Topology) {
+    for first in 0..agents.len() {
+        for second in first + 1..agents.len() {
+            if agents[first].z != agents[second].z {
+                continue;
+            }
+            let dx = wrapped_delta(agents[first].x, agents[second].x, MAP_WIDTH, topology);
+            let dy = wrapped_delta(agents[first].y, agents[second].y, MAP_HEIGHT, topology);
+            let distance = dx.hypot(dy);
+            let overlap = 2.0 * AGENT_RADIUS - distance;
+            if overlap <= 0.0 {
+                continue;
+            }
+
+            // agents right on top of each other, like two spawned on the same node, get split up along the x axis
+            let (push_x, push_y) = if distance > 1e-4 {
+                (dx / distance * overlap / 2.0, dy / distance * overlap / 2.0)
+            } else {
+                (overlap / 2.0, 0.0)
+            };
+            push(&mut agents[first], -push_x, -push_y, nodes, topology);
+            push(&mut agents[second], push_x, push_y, nodes, topology);
+        }
+    }
+}
+
+fn push(agent: &mut Agent, dx: f32, dy: f32, nodes: &[Node], topology: Topology) {
+    let x = wrap(agent.x + dx, MAP_WIDTH, topology);
+    let y = wrap(agent.y + dy, MAP_HEIGHT, topology);
+    let (node_x, node_y) = ((x + 0.5).floor() as i32, (y + 0.5).floor() as i32);
+    if !(0..MAP_WIDTH).contains(&node_x) || !(0..MAP_HEIGHT).contains(&node_y) {
+        return;
+    }
+
+    // staying in the same node is always fine. Going over into the next one has to be a step we could take, so no
+    // pushing through walls, closed exits or the corners between nodes.
+    let (from, to) = (agent.node(), node_index(node_x, node_y, agent.z));
+    let allowed = to == from || (get_grid_neighbors(from, nodes, topology).contains(&to)
+        && can_step(&nodes[from], &nodes[to], topology));
+    if allowed {
+        (agent.x, agent.y) = (x, y);
+    }
+}
+
+/**
+How far it is from `from` to `to` along one axis, the short way around on a toroidal map.
+ */
+fn wrapped_delta(from: f32, to: f32, size: i32, topology: Topology) -> f32 {
+    let delta = to - from;
+    match topology {
+        Topology::Bounded => delta,
+        Topology::Toroidal => delta - (delta / size as f32).round() * size as f32,
+    }
+}
+
+/**
+Brings a position that walked off one edge of a toroidal map back in on the other.
+ */
+fn wrap(value: f32, size: i32, topology: Topology) -> f32 {
+    match topology {
+        Topology::Bounded => value,
+        Topology::Toroidal => (value + 0.5).rem_euclid(size as f32) - 0.5,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::SearchLimits;
+    use crate::{set_wall, TestMap, EXIT_EAST};
+
+    fn plan(map: &TestMap, start: usize, goal: usize, occupied: &[usize]) -> Result<Path, PathError> {
+        map.sliced(start, goal, &SearchLimits::default())
+            .excluding(occupied, &[])
+            .finish(&map.nodes, &map.connectivity)
+    }
+
+    #[test]
+    fn agents_walk_slower_on_rough_ground_and_jump_through_portals() {
+        let map = TestMap::new(&["",
+                                 "",
+                                 "...s"]);
+        let nodes = &map.nodes;
+        let (start, goal) = (node_index(0, 2, 0), node_index(3, 2, 0));
+        let mut agent = Agent::new(start, goal, nodes);
+        agent.set_path(plan(&map, start, goal, &[]), nodes, Topology::Bounded);
+
+        // a tenth of a second on plain ground covers a tenth of the speed
+        agent.advance(0.1, nodes, Topology::Bounded);
+        assert!((agent.x - AGENT_SPEED * 0.1).abs() < 1e-4);
+        // up to the swamp, where the same time covers a quarter of that
+        agent.advance((2.0 - agent.x) / AGENT_SPEED, nodes, Topology::Bounded);
+        assert!((agent.x - 2.0).abs() < 1e-4);
+        agent.advance(0.1, nodes, Topology::Bounded);
+        assert!((agent.x - (2.0 + AGENT_SPEED * 0.1 / 4.0)).abs() < 1e-4);
+
+        // a teleporter takes no time at all, however far away it leads
+        let (from, to) = (node_index(3, 2, 0), node_index(12, 9, 1));
+        let mut agent = Agent::new(from, to, nodes);
+        agent.set_path(Ok(Path { nodes: vec![from, to], cost: 1, partial: None }), nodes, Topology::Bounded);
+        agent.advance(0.0, nodes, Topology::Bounded);
+        assert_eq!((agent.x, agent.y, agent.z), (12.0, 9.0, 1));
+        assert!(agent.arrived());
+    }
+
+    #[test]
+    fn a_new_wall_on_the_route_makes_the_agent_plan_again() {
+        let mut map = TestMap::new(&[]);
+        let (start, goal) = (node_index(2, 8, 0), node_index(6, 8, 0));
+        let mut agent = Agent::new(start, goal, &map.nodes);
+        agent.set_path(plan(&map, start, goal, &[]), &map.nodes, Topology::Bounded);
+        assert!(!agent.route_blocked(&map.nodes, &map.connectivity));
+
+        set_wall(node_index(4, 8, 0), EXIT_EAST, true, &mut map.nodes, Topology::Bounded);
+        assert!(agent.route_blocked(&map.nodes, &map.connectivity));
+    }
+
+    #[test]
+    fn pushes_never_go_through_walls() {
+        let mut map = TestMap::new(&[]);
+        set_wall(node_index(4, 8, 0), EXIT_EAST, true, &mut map.nodes, Topology::Bounded);
+        let mut agent = Agent::new(node_index(4, 8, 0), node_index(4, 8, 0), &map.nodes);
+        agent.x = 4.4;
+        push(&mut agent, 0.3, 0.0, &map.nodes, Topology::Bounded);
+        assert_eq!(agent.node(), node_index(4, 8, 0));
+        push(&mut agent, -0.3, 0.0, &map.nodes, Topology::Bounded);
+        assert!((agent.x - 4.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn agents_get_around_one_that_is_stuck() {
+        // the first agent stands right in the way and can't get to its own goal, the second wants to walk through it
+        let map = TestMap::new(&["#"]);
+        let TestMap { nodes, connectivity, .. } = &map;
+        let goal = node_index(10, 8, 0);
+        let mut agents = vec![Agent::new(node_index(5, 8, 0), node_index(0, 0, 0), nodes),
+                              Agent::new(node_index(2, 8, 0), goal, nodes)];
+        agents[0].set_path(Err(PathError::GoalBlocked), nodes, Topology::Bounded);
+        agents[1].set_path(plan(&map, node_index(2, 8, 0), goal, &[]), nodes, Topology::Bounded);
+
+        for _ in 0..600 {
+            update_agents(&mut agents, 1.0 / 60.0, nodes, connectivity, false,
+                          |start, goal, occupied| plan(&map, start, goal, occupied));
+        }
+        assert_eq!(agents.len(), 1);
+        assert!(agents[0].stuck);
+    }
+}
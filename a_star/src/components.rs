@@ -0,0 +1,285 @@
+use crate::{can_step, get_grid_neighbors, Connectivity, Node};
+
+
+/**
+Labels every free node with the connected component it belongs to, so we can tell in O(1) whether two nodes could
+possibly be joined by a path. Grid links and portals both count as connections. A one way teleporter or grid edge is
+treated as if it went both ways, so two nodes in the same component aren't always reachable from each other, but two
+nodes in different components never are. Only a wall, or an edge closed from both sides, cuts two neighbors apart.
+
+The labels are kept up to date one obstacle or edge at a time by `obstacle_added`, `obstacle_removed` and
+`edge_changed`, which only touch the components around what changed. Anything bigger, like adding a portal, should just
+build a new `Components`.
+ */
+#[derive(Clone, Default)]
+pub struct Components {
+    // the component each node belongs to, None for obstacles
+    labels: Vec<Option<usize>>,
+    // how many nodes carry each label. Labels that aren't used any more have a size of 0, and wait in `free_labels`
+    // to be given out again, so splitting and joining components over and over doesn't keep adding labels.
+    sizes: Vec<usize>,
+    free_labels: Vec<usize>,
+}
+
+impl Components {
+    pub fn new(nodes: &[Node], connectivity: &Connectivity) -> Components {
+        let mut components = Components { labels: vec![None; nodes.len()], sizes: Vec::new(), free_labels: Vec::new() };
+
+        for index in 0..nodes.len() {
+            if !nodes[index].obstacle && components.labels[index].is_none() {
+                let label = components.new_label();
+                components.flood(index, label, nodes, connectivity, |label| label.is_none());
+            }
+        }
+
+        components
+    }
+
+    /**
+    The component of the node at `index`, or None if it's an obstacle.
+     */
+    pub fn label(&self, index: usize) -> Option<usize> {
+        self.labels[index]
+    }
+
+    /**
+    Whether the nodes at `a` and `b` are both free and in the same component. If this is false there is no path
+    between them.
+     */
+    pub fn same_component(&self, a: usize, b: usize) -> bool {
+        match (self.labels[a], self.labels[b]) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /**
+    Updates the labels after the node at `index` turned into an obstacle. That can split its component into as many
+    pieces as it had neighbors, so every neighbor that isn't reached by the flood fill of an earlier neighbor starts a
+    new component.
+     */
+    pub fn obstacle_added(&mut self, index: usize, nodes: &[Node], connectivity: &Connectivity) {
+        let old_label = match self.labels[index].take() {
+            Some(label) => label,
+            None => return,
+        };
+        self.release(old_label);
+
+        for neighbor_index in undirected_neighbors(index, nodes, connectivity) {
+            if self.labels[neighbor_index] == Some(old_label) {
+                let label = self.new_label();
+                self.flood(neighbor_index, label, nodes, connectivity, |label| label == Some(old_label));
+            }
+        }
+    }
+
+    /**
+    Updates the labels after the node at `index` stopped being an obstacle. It joins all the components around it into
+    one. We keep the label of the biggest of them and only relabel the others, which keeps the work small when a
+    node connects a big open area to a small room.
+     */
+    pub fn obstacle_removed(&mut self, index: usize, nodes: &[Node], connectivity: &Connectivity) {
+        let mut neighbor_labels: Vec<usize> = undirected_neighbors(index, nodes, connectivity)
+            .into_iter()
+            .filter_map(|neighbor_index| self.labels[neighbor_index])
+            .collect();
+        neighbor_labels.sort();
+        neighbor_labels.dedup();
+
+        let label = match neighbor_labels.iter().max_by_key(|&&label| self.sizes[label]) {
+            Some(&label) => label,
+            None => self.new_label(),
+        };
+        self.flood(index, label, nodes, connectivity, |other| {
+            other.is_some_and(|other| other != label && neighbor_labels.contains(&other))
+        });
+    }
+
+    /**
+    Updates the labels after the grid edge between the neighbors `a` and `b` was opened or closed, by a wall or by the
+    exits on its sides. Opening it joins their components, keeping the label of the bigger one like `obstacle_removed`
+    does. Closing it can split their component in two, so like `obstacle_added` we give what we can still reach from
+    `b` a new label. That's the whole component again when `a` is still connected to `b` some other way.
+     */
+    pub fn edge_changed(&mut self, a: usize, b: usize, nodes: &[Node], connectivity: &Connectivity) {
+        let (a_label, b_label) = match (self.labels[a], self.labels[b]) {
+            (Some(a_label), Some(b_label)) if a != b => (a_label, b_label),
+            _ => return,
+        };
+        let linked = undirected_neighbors(a, nodes, connectivity).contains(&b);
+
+        if linked && a_label != b_label {
+            let (start, kept, replaced) = if self.sizes[a_label] >= self.sizes[b_label] {
+                (b, a_label, b_label)
+            } else {
+                (a, b_label, a_label)
+            };
+            self.flood(start, kept, nodes, connectivity, |label| label == Some(replaced));
+        } else if !linked && a_label == b_label {
+            let label = self.new_label();
+            self.flood(b, label, nodes, connectivity, |label| label == Some(a_label));
+        }
+    }
+
+    fn new_label(&mut self) -> usize {
+        if let Some(label) = self.free_labels.pop() {
+            return label;
+        }
+        self.sizes.push(0);
+        self.sizes.len() - 1
+    }
+
+    /**
+    Takes one node away from `label`, which frees the label once no node carries it any more. A label only gets back
+    into use through `new_label`, so one that's waiting to be given out again never has any nodes.
+     */
+    fn release(&mut self, label: usize) {
+        self.sizes[label] -= 1;
+        if self.sizes[label] == 0 {
+            self.free_labels.push(label);
+        }
+    }
+
+    fn set_label(&mut self, index: usize, label: usize) {
+        if let Some(old_label) = self.labels[index] {
+            self.release(old_label);
+        }
+        self.labels[index] = Some(label);
+        self.sizes[label] += 1;
+    }
+
+    /**
+    Gives `label` to the node at `start` and to every node we can reach from it through nodes whose current label
+    `should_relabel` accepts.
+     */
+    fn flood(&mut self,
+             start: usize,
+             label: usize,
+             nodes: &[Node],
+             connectivity: &Connectivity,
+             should_relabel: impl Fn(Option<usize>) -> bool) {
+        self.set_label(start, label);
+        let mut stack = vec![start];
+
+        while let Some(index) = stack.pop() {
+            for neighbor_index in undirected_neighbors(index, nodes, connectivity) {
+                if should_relabel(self.labels[neighbor_index]) {
+                    self.set_label(neighbor_index, label);
+                    stack.push(neighbor_index);
+                }
+            }
+        }
+    }
+}
+
+
+/**
+The free nodes linked to `index` by the grid or by a portal, ignoring which way a portal or a one-way edge goes.
+ */
+fn undirected_neighbors(index: usize, nodes: &[Node], connectivity: &Connectivity) -> Vec<usize> {
+    let topology = connectivity.topology;
+    let mut neighbors = get_grid_neighbors(index, nodes, topology);
+    neighbors.retain(|&neighbor_index| {
+        let (node, neighbor) = (&nodes[index], &nodes[neighbor_index]);
+        can_step(node, neighbor, topology) || can_step(neighbor, node, topology)
+    });
+
+    for portal in &connectivity.portals {
+        let other = if portal.from == index {
+            portal.to
+        } else if portal.to == index {
+            portal.from
+        } else {
+            continue;
+        };
+
+        if !nodes[other].obstacle {
+            neighbors.push(other);
+        }
+    }
+
+    neighbors
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{exit_offset, neighbor_at, node_index, open_map, set_wall, Topology};
+    use crate::{EXIT_EAST, EXIT_NORTH, EXIT_SOUTH, EXIT_WEST};
+    use std::collections::HashMap;
+
+    /**
+    Whether `a` and `b` split the nodes into the same components, whatever labels they gave them.
+     */
+    fn same_partition(a: &Components, b: &Components) -> bool {
+        let mut a_to_b = HashMap::new();
+        let mut b_to_a = HashMap::new();
+        a.labels.iter().zip(&b.labels).all(|pair| match pair {
+            (None, None) => true,
+            (Some(a_label), Some(b_label)) => {
+                *a_to_b.entry(a_label).or_insert(b_label) == b_label
+                    && *b_to_a.entry(b_label).or_insert(a_label) == a_label
+            }
+            _ => false,
+        })
+    }
+
+    #[test]
+    fn edge_updates_match_a_full_rebuild() {
+        let mut nodes = open_map();
+        let connectivity = Connectivity { topology: Topology::Toroidal, portals: Vec::new() };
+        let mut components = Components::new(&nodes, &connectivity);
+
+        // walls and closed exits around the corner node and its neighbors, some across the edges of the map. Toggling
+        // them one at a time in gray code order walks through every way of setting them, which cuts pieces off and
+        // joins them up again all the time.
+        let edges = [(0, 0, EXIT_NORTH, true), (0, 0, EXIT_WEST, true), (0, 0, EXIT_EAST, true),
+                     (0, 0, EXIT_SOUTH, true), (0, 0, EXIT_EAST, false), (1, 0, EXIT_WEST, false),
+                     (0, 0, EXIT_SOUTH, false), (0, 1, EXIT_NORTH, false), (1, 0, EXIT_SOUTH, true),
+                     (0, 1, EXIT_EAST, true), (1, 1, EXIT_NORTH, false), (1, 1, EXIT_WEST, false)];
+
+        for step in 1..1u32 << edges.len() {
+            let (x, y, exit, is_wall) = edges[step.trailing_zeros() as usize];
+            let index = node_index(x, y, 0);
+            if is_wall {
+                let wall = nodes[index].walls & exit == 0;
+                set_wall(index, exit, wall, &mut nodes, Topology::Toroidal);
+            } else {
+                nodes[index].closed_exits ^= exit;
+            }
+            let (dx, dy) = exit_offset(exit);
+            let neighbor_index = neighbor_at(&nodes[index], dx, dy, Topology::Toroidal).unwrap();
+            components.edge_changed(index, neighbor_index, &nodes, &connectivity);
+            assert!(same_partition(&components, &Components::new(&nodes, &connectivity)));
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_a_full_rebuild() {
+        let mut nodes = open_map();
+        let connectivity = Connectivity { topology: Topology::Bounded, portals: Vec::new() };
+        let mut components = Components::new(&nodes, &connectivity);
+
+        // toggling one node at a time in gray code order walks through every way of blocking a 4 by 3 corner of the
+        // map, which splits pieces off it and joins them up again all the time
+        for step in 1..1u32 << 12 {
+            let bit = step.trailing_zeros() as i32;
+            let index = node_index(bit % 4, bit / 4, 0);
+            nodes[index].obstacle = !nodes[index].obstacle;
+            if nodes[index].obstacle {
+                components.obstacle_added(index, &nodes, &connectivity);
+            } else {
+                components.obstacle_removed(index, &nodes, &connectivity);
+            }
+            assert!(same_partition(&components, &Components::new(&nodes, &connectivity)));
+        }
+
+        // the labels that have been freed get used again, so there are never more of them than nodes
+        assert!(components.sizes.len() <= nodes.len());
+        for (label, &size) in components.sizes.iter().enumerate() {
+            assert_eq!(size, components.labels.iter().filter(|&&other| other == Some(label)).count());
+            assert_eq!(size == 0, components.free_labels.contains(&label));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::terrain::Terrain;
+use crate::Node;
+
+
+/**
+A rectangle of nodes on one layer, from (min_x, min_y) to (max_x, max_y) with both corners included.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Zone {
+    pub layer: i32,
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl Zone {
+    /**
+    The zone between two opposite corners, given in any order.
+     */
+    pub fn new(layer: i32, (x0, y0): (i32, i32), (x1, y1): (i32, i32)) -> Zone {
+        Zone { layer, min_x: x0.min(x1), min_y: y0.min(y1), max_x: x0.max(x1), max_y: y0.max(y1) }
+    }
+
+    pub fn contains(&self, node: &Node) -> bool {
+        node.z == self.layer
+            && (self.min_x..=self.max_x).contains(&node.x)
+            && (self.min_y..=self.max_y).contains(&node.y)
+    }
+}
+
+
+/**
+Rules a single query follows on top of the map, without anyone having to edit the map for it. The search never
+steps into a no-go zone, pays extra for every step into a penalty zone (say near an enemy), and multiplies what every
+step into a node costs by the multiplier for its terrain. Multipliers below 1 count as 1, so a step never gets
+cheaper than the heuristic expects.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryConstraints {
+    pub no_go: Vec<Zone>,
+    pub penalties: Vec<(Zone, i32)>,
+    pub terrain_multipliers: [f32; Terrain::ALL.len()],
+}
+
+impl Default for QueryConstraints {
+    fn default() -> Self {
+        QueryConstraints { no_go: Vec::new(), penalties: Vec::new(), terrain_multipliers: [1.0; Terrain::ALL.len()] }
+    }
+}
+
+impl QueryConstraints {
+    /**
+    Whether the query may step into `node` at all.
+     */
+    pub fn allows(&self, node: &Node) -> bool {
+        !self.no_go.iter().any(|zone| zone.contains(node))
+    }
+
+    /**
+    What a step into `node` costs this query, given what it costs on the plain map.
+     */
+    pub fn step_cost(&self, cost: i32, node: &Node) -> i32 {
+        let multiplier = self.terrain_multipliers[node.terrain.index()].max(1.0);
+        let penalty: i32 = self.penalties.iter()
+            .filter(|(zone, _)| zone.contains(node))
+            .map(|&(_, penalty)| penalty.max(0))
+            .sum();
+        (cost as f32 * multiplier).round() as i32 + penalty
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::SearchLimits;
+    use crate::sliced::SlicedSearch;
+    use crate::{node_index, open_map, TestMap};
+
+    #[test]
+    fn steps_cost_what_the_constraints_say() {
+        let nodes = open_map();
+        let mut forest = nodes[0].clone();
+        forest.terrain = Terrain::Forest;
+        let mut constraints = QueryConstraints {
+            penalties: vec![(Zone::new(0, (0, 0), (3, 3)), 5), (Zone::new(0, (2, 2), (6, 6)), -9)],
+            ..QueryConstraints::default()
+        };
+        constraints.terrain_multipliers[Terrain::Plain.index()] = 0.5;
+        constraints.terrain_multipliers[Terrain::Forest.index()] = 2.5;
+
+        // multipliers below 1 count as 1 and so do penalties below 0, which would make a step cheaper too
+        assert_eq!(constraints.step_cost(1, &nodes[node_index(2, 2, 0)]), 6);
+        assert_eq!(constraints.step_cost(2, &forest), 10);
+        assert_eq!(constraints.step_cost(1, &nodes[node_index(5, 5, 0)]), 1);
+    }
+
+    #[test]
+    fn the_search_goes_around_no_go_and_penalty_zones() {
+        let map = TestMap::new(&[]);
+        let TestMap { nodes, connectivity, components } = &map;
+        // a wall of no-go from the top down and a penalty zone from the bottom up, with a gap between them
+        let constraints = QueryConstraints {
+            no_go: vec![Zone::new(0, (6, 0), (6, 9))],
+            penalties: vec![(Zone::new(0, (6, 11), (6, 15)), 100)],
+            ..QueryConstraints::default()
+        };
+
+        let path = SlicedSearch::new(node_index(2, 2, 0), node_index(10, 2, 0), nodes, connectivity, components,
+                                     &SearchLimits::default(), &constraints)
+            .finish(nodes, connectivity)
+            .unwrap();
+        assert!(path.nodes.iter().all(|&index| constraints.allows(&nodes[index])));
+        assert!(path.nodes.contains(&node_index(6, 10, 0)));
+        // 8 steps across and down to row 10 and back up, every one of them on plain ground
+        assert_eq!(path.cost, 8 + 2 * 8);
+    }
+}
@@ -0,0 +1,184 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::{can_step, get_neighbors, get_grid_neighbors, Connectivity, Node, MAP_HEIGHT, MAP_WIDTH};
+
+
+/**
+Works out what it costs to walk from every node to the nearest of `sources`, the map roguelikes call a Dijkstra map.
+Nodes that can't reach any source, obstacles included, get i32::MAX.
+ */
+pub fn distance_field(sources: &[usize], nodes: &[Node], connectivity: &Connectivity) -> Vec<i32> {
+    let mut field = vec![i32::MAX; nodes.len()];
+    for &source in sources {
+        if source < nodes.len() && !nodes[source].obstacle {
+            field[source] = 0;
+        }
+    }
+    relax(&mut field, nodes, connectivity);
+    field
+}
+
+
+/**
+Turns a distance field into one for running away from its sources. Walking downhill on the plain inverse of a field
+leads into the nearest dead end, so we scale it by more than -1 and let it settle again. That makes paths through an
+open area cheaper than the corner right next to us, and a fleeing monster ends up running past us to get away. A
+`scale` of about 1.2 works well.
+ */
+pub fn flee_field(field: &[i32], scale: f32, nodes: &[Node], connectivity: &Connectivity) -> Vec<i32> {
+    let mut flee: Vec<i32> = field.iter()
+        .map(|&value| if value == i32::MAX { i32::MAX } else { (value as f32 * -scale).round() as i32 })
+        .collect();
+    relax(&mut flee, nodes, connectivity);
+    flee
+}
+
+
+/**
+Adds up fields weighted by how much each of them matters, so a monster can for example want to get to the player
+and away from the fire at the same time. A node one of the fields can't reach stays unreachable.
+ */
+pub fn combine_fields(fields: &[(&[i32], f32)]) -> Vec<i32> {
+    let length = fields.first().map_or(0, |(field, _)| field.len());
+    (0..length).map(|index| {
+        let mut total = 0.0;
+        for (field, weight) in fields {
+            if field[index] == i32::MAX {
+                return i32::MAX;
+            }
+            total += field[index] as f32 * weight;
+        }
+        total.round() as i32
+    }).collect()
+}
+
+
+/**
+The node we can move to from `index` where the move there plus the value in `field` there comes out lowest, as long
+as its value is lower than the value at `index`. Counting the move keeps us off rough terrain the field only looks
+lower across, so following this from anywhere walks the cheapest path to a source of the field, or away from them on
+a flee field. Returns None once there's nowhere lower to go.
+ */
+pub fn downhill(index: usize, field: &[i32], nodes: &[Node], connectivity: &Connectivity) -> Option<usize> {
+    get_neighbors(index, nodes, connectivity)
+        .into_iter()
+        .filter(|&(neighbor_index, _)| !nodes[neighbor_index].obstacle && field[neighbor_index] != i32::MAX)
+        .min_by_key(|&(neighbor_index, cost)| field[neighbor_index] + cost)
+        .map(|(neighbor_index, _)| neighbor_index)
+        .filter(|&neighbor_index| field[neighbor_index] < field[index])
+}
+
+
+/**
+Writes a field to a CSV file with one line per row of the map. The layers follow each other with an empty line in
+between, and nodes that are unreachable are left empty.
+ */
+pub fn save_field_csv(field: &[i32], file_name: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_name)?);
+
+    for (row_number, row) in field.chunks(MAP_WIDTH as usize).enumerate() {
+        if row_number > 0 && row_number % MAP_HEIGHT as usize == 0 {
+            writeln!(writer)?;
+        }
+        let cells: Vec<String> = row.iter()
+            .map(|&value| if value == i32::MAX { String::new() } else { value.to_string() })
+            .collect();
+        writeln!(writer, "{}", cells.join(","))?;
+    }
+
+    writer.flush()
+}
+
+
+/**
+Lowers every value in `field` until no node is more than a move above a node it can move to. The nodes that already
+have a value are where the walk ends. This is Dijkstra run from all of them at once, and since we want the cost of
+walking *to* them we follow every move backwards.
+ */
+fn relax(field: &mut [i32], nodes: &[Node], connectivity: &Connectivity) {
+    let mut open_set: BinaryHeap<Reverse<(i32, usize)>> = (0..field.len())
+        .filter(|&index| field[index] != i32::MAX)
+        .map(|index| Reverse((field[index], index)))
+        .collect();
+
+    while let Some(Reverse((value, index))) = open_set.pop() {
+        if value > field[index] {
+            continue;
+        }
+
+        for (from_index, cost) in incoming_neighbors(index, nodes, connectivity) {
+            if value + cost < field[from_index] {
+                field[from_index] = value + cost;
+                open_set.push(Reverse((field[from_index], from_index)));
+            }
+        }
+    }
+}
+
+
+/**
+The free nodes that can move to the node at `index`, and what that move costs. A one-way edge on the grid only
+counts from the node it leads away from, and a portal only from the end it can be walked through.
+ */
+fn incoming_neighbors(index: usize, nodes: &[Node], connectivity: &Connectivity) -> Vec<(usize, i32)> {
+    // a step on the grid costs what the terrain of the node we step onto costs
+    let mut neighbors: Vec<(usize, i32)> = get_grid_neighbors(index, nodes, connectivity.topology)
+        .into_iter()
+        .filter(|&neighbor_index| can_step(&nodes[neighbor_index], &nodes[index], connectivity.topology))
+        .map(|neighbor_index| (neighbor_index, nodes[index].terrain.cost()))
+        .collect();
+
+    for (from, to, cost) in connectivity.portal_links() {
+        if to == index && !nodes[from].obstacle {
+            neighbors.push((from, cost));
+        }
+    }
+
+    neighbors
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node_index, TestMap, EXIT_NORTH};
+
+    #[test]
+    fn the_field_holds_the_cost_of_the_cheapest_path_to_the_source() {
+        // the source is in a pocket we can only get into through the swamp above it, since its exit to the north is
+        // closed from below
+        let mut map = TestMap::new(&["..s..",
+                                     ".#s#.",
+                                     ".#.#.",
+                                     "....."]);
+        map.nodes[node_index(2, 3, 0)].closed_exits = EXIT_NORTH;
+        let TestMap { nodes, connectivity, .. } = &map;
+        let source = node_index(2, 2, 0);
+        let field = distance_field(&[source], nodes, connectivity);
+
+        assert_eq!(field[source], 0);
+        assert_eq!(field[node_index(2, 0, 0)], 4 + 1);
+        assert_eq!(field[node_index(0, 0, 0)], 1 + 4 + 4 + 1);
+        assert_eq!(field[node_index(0, 3, 0)], 4 + 4 + 4 + 1);
+        assert_eq!(field[node_index(1, 1, 0)], i32::MAX);
+
+        for index in 0..(MAP_WIDTH * MAP_HEIGHT) as usize {
+            match map.search(index, source) {
+                Ok(path) => {
+                    assert_eq!(field[index], path.cost);
+                    // and walking downhill gets there for the same cost
+                    let (mut at, mut cost) = (index, 0);
+                    while let Some(next) = downhill(at, &field, nodes, connectivity) {
+                        cost += nodes[next].terrain.cost();
+                        at = next;
+                    }
+                    assert_eq!((at, cost), (source, path.cost));
+                }
+                Err(_) => assert_eq!(field[index], i32::MAX),
+            }
+        }
+    }
+}
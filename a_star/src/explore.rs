@@ -0,0 +1,294 @@
+use std::collections::VecDeque;
+
+use crate::components::Components;
+use crate::constraints::QueryConstraints;
+use crate::limits::SearchLimits;
+use crate::path::PathError;
+use crate::sliced::SlicedSearch;
+use crate::{get_grid_neighbors, neighbor_at, node_index, Connectivity, Node, Topology, MAP_HEIGHT, MAP_WIDTH};
+
+
+// how long the explorer takes over each step, in seconds
+const STEP_TIME: f32 = 0.15;
+
+
+/**
+What the explorer can see from where it stands. It sees every node within `range` of it on its layer, or with
+`line_of_sight` on only the ones no obstacle hides from it. The range never goes below 2, so the explorer sees all the
+nodes around it.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct SensorParams {
+    pub range: i32,
+    pub line_of_sight: bool,
+}
+
+impl Default for SensorParams {
+    fn default() -> SensorParams {
+        SensorParams { range: 4, line_of_sight: true }
+    }
+}
+
+
+/**
+Something finding its way to the goal on a map it doesn't know. It starts out knowing only the walls, portals and
+terrain, and learns which nodes are obstacles by seeing them. It plans as if every node it hasn't seen is free, walks
+that plan a step at a time and plans again whenever it sees an obstacle on the way.
+ */
+pub struct Explorer {
+    pub start: usize,
+    pub goal: usize,
+    pub position: usize,
+    pub sensor: SensorParams,
+    // for every node, None until we have seen it and then whether it was an obstacle when we last saw it
+    pub known: Vec<Option<bool>>,
+    // the nodes we can see from where we are right now
+    pub sensed: Vec<bool>,
+    // the nodes still ahead on the current plan, the next one first
+    route: VecDeque<usize>,
+    // how many times we had to plan again after the first plan, and how far we have walked, in nodes
+    pub replans: usize,
+    pub distance: f32,
+    // None while we are still on our way, then whether we got to the goal or why we gave up
+    pub outcome: Option<Result<(), PathError>>,
+    step_timer: f32,
+}
+
+impl Explorer {
+    pub fn new(start: usize,
+               goal: usize,
+               sensor: SensorParams,
+               nodes: &[Node],
+               connectivity: &Connectivity,
+               limits: &SearchLimits) -> Explorer {
+        let mut explorer = Explorer {
+            start,
+            goal,
+            position: start,
+            sensor,
+            known: vec![None; nodes.len()],
+            sensed: vec![false; nodes.len()],
+            route: VecDeque::new(),
+            replans: 0,
+            distance: 0.0,
+            outcome: None,
+            step_timer: 0.0,
+        };
+        explorer.sense(nodes, connectivity.topology);
+        explorer.plan(nodes, connectivity, limits);
+        explorer
+    }
+
+    pub fn route(&self) -> &VecDeque<usize> {
+        &self.route
+    }
+
+    /**
+    Takes however many steps fit in `elapsed_time`.
+     */
+    pub fn update(&mut self,
+                  elapsed_time: f32,
+                  nodes: &[Node],
+                  connectivity: &Connectivity,
+                  limits: &SearchLimits) {
+        self.step_timer += elapsed_time;
+        while self.step_timer >= STEP_TIME && self.outcome.is_none() {
+            self.step_timer -= STEP_TIME;
+            self.step(nodes, connectivity, limits);
+        }
+    }
+
+    /**
+    Looks around, plans again if an obstacle turned up on the way, and then takes one step along the plan.
+     */
+    fn step(&mut self, nodes: &[Node], connectivity: &Connectivity, limits: &SearchLimits) {
+        self.sense(nodes, connectivity.topology);
+        if self.position == self.goal {
+            self.outcome = Some(Ok(()));
+            return;
+        }
+
+        let blocked = self.route.iter().any(|&index| self.known[index] == Some(true));
+        if blocked || self.route.is_empty() {
+            self.replans += 1;
+            if !self.plan(nodes, connectivity, limits) {
+                return;
+            }
+        }
+
+        let next = match self.route.pop_front() {
+            Some(next) => next,
+            None => return,
+        };
+        // the next node might be one we haven't seen yet, like the far end of a portal or a node across the edge of a
+        // toroidal map, or an obstacle might have been put there since we last looked. Then we bump into it, and the
+        // next step plans around it.
+        if nodes[next].obstacle {
+            self.known[next] = Some(true);
+            self.route.clear();
+            return;
+        }
+
+        let walked = get_grid_neighbors(self.position, nodes, connectivity.topology).contains(&next);
+        if walked {
+            let (from, to) = (&nodes[self.position], &nodes[next]);
+            let dx = connectivity.topology.delta(from.x, to.x, MAP_WIDTH);
+            let dy = connectivity.topology.delta(from.y, to.y, MAP_HEIGHT);
+            self.distance += (dx as f32).hypot(dy as f32);
+        }
+        self.position = next;
+    }
+
+    /**
+    Plans a path from where we are to the goal through the map as we believe it to be, where every node we haven't
+    seen is free. Returns false, and gives up, when even that map has no way to the goal.
+     */
+    fn plan(&mut self, nodes: &[Node], connectivity: &Connectivity, limits: &SearchLimits) -> bool {
+        let believed: Vec<Node> = nodes.iter().zip(&self.known)
+            .map(|(node, known)| Node { obstacle: known.unwrap_or(false), ..node.clone() })
+            .collect();
+        let components = Components::new(&believed, connectivity);
+        // a partial path would walk us up to an obstacle we already know about and leave us there
+        let limits = SearchLimits { allow_partial: false, ..*limits };
+
+        let path = SlicedSearch::new(self.position, self.goal, &believed, connectivity, &components, &limits,
+                                     &QueryConstraints::default())
+            .finish(&believed, connectivity);
+        match path {
+            Ok(path) => {
+                self.route = path.nodes.into_iter().skip(1).collect();
+                true
+            }
+            Err(error) => {
+                self.route.clear();
+                self.outcome = Some(Err(error));
+                false
+            }
+        }
+    }
+
+    /**
+    Works out which nodes we can see from where we are and writes down what they are like now. On a toroidal map we
+    see across the edges, the same as we walk across them.
+     */
+    fn sense(&mut self, nodes: &[Node], topology: Topology) {
+        self.sensed.iter_mut().for_each(|sensed| *sensed = false);
+        let here = &nodes[self.position];
+        let range = self.sensor.range.max(2);
+
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                let (dx, dy) = (topology.delta(here.x, x, MAP_WIDTH), topology.delta(here.y, y, MAP_HEIGHT));
+                if dx * dx + dy * dy > range * range {
+                    continue;
+                }
+                if self.sensor.line_of_sight && !in_sight(here, dx, dy, nodes, topology) {
+                    continue;
+                }
+                let index = node_index(x, y, here.z);
+                self.sensed[index] = true;
+                self.known[index] = Some(nodes[index].obstacle);
+            }
+        }
+    }
+}
+
+
+/**
+Whether the straight line from the middle of `from` to the middle of the node (dx, dy) away from it gets there without
+going through an obstacle on the way, wrapping around the edges of a toroidal map. The nodes at either end don't count,
+so we can see an obstacle itself. We follow the line in small steps rather than exactly, which is plenty for deciding
+what a sensor picks up.
+ */
+fn in_sight(from: &Node, dx: i32, dy: i32, nodes: &[Node], topology: Topology) -> bool {
+    let steps = dx.abs().max(dy.abs()) * 4;
+    (1..steps).all(|step| {
+        let t = step as f32 / steps as f32;
+        let x = (dx as f32 * t + 0.5).floor() as i32;
+        let y = (dy as f32 * t + 0.5).floor() as i32;
+        (x, y) == (0, 0)
+            || (x, y) == (dx, dy)
+            || neighbor_at(from, x, y, topology).is_some_and(|index| !nodes[index].obstacle)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestMap;
+
+    /**
+    Runs `explorer` until it's done, checking it never stands on an obstacle and only knows what it has seen.
+     */
+    fn explore(explorer: &mut Explorer, map: &TestMap) {
+        let limits = SearchLimits::default();
+        for _ in 0..1000 {
+            explorer.update(STEP_TIME, &map.nodes, &map.connectivity, &limits);
+            assert!(!map.nodes[explorer.position].obstacle);
+            assert!(explorer.known.iter()
+                .zip(&map.nodes)
+                .all(|(known, node)| known.is_none_or(|seen| seen == node.obstacle)));
+        }
+    }
+
+    #[test]
+    fn the_explorer_finds_the_gap_in_a_wall_it_didnt_know_about() {
+        // a wall down to the bottom two rows, between the start and the goal
+        let map = TestMap::new(&["        #"; 14]);
+        let (start, goal) = (node_index(2, 2, 0), node_index(13, 2, 0));
+        let limits = SearchLimits::default();
+
+        let mut explorer = Explorer::new(start, goal, SensorParams::default(), &map.nodes, &map.connectivity, &limits);
+        // it heads straight for the goal at first, since it can't see the wall yet
+        assert_eq!(explorer.route().len(), 11);
+        explore(&mut explorer, &map);
+        assert_eq!((explorer.outcome, explorer.position), (Some(Ok(())), goal));
+        assert!(explorer.replans > 0);
+        // it can't do better than the way down through the gap and back up it would take knowing the map
+        assert_eq!(map.search(start, goal).unwrap().cost, 12 + 11 + 12);
+        assert!(explorer.distance >= 35.0);
+    }
+
+    #[test]
+    fn the_explorer_sees_across_the_edges_of_a_toroidal_map() {
+        let mut map = TestMap::new(&["",
+                                     "",
+                                     "",
+                                     "",
+                                     "",
+                                     "               #"]);
+        let (start, goal) = (node_index(0, 5, 0), node_index(8, 5, 0));
+        let (behind, hidden) = (node_index(15, 5, 0), node_index(14, 5, 0));
+        let limits = SearchLimits::default();
+
+        let explorer = Explorer::new(start, goal, SensorParams::default(), &map.nodes, &map.connectivity, &limits);
+        assert_eq!((explorer.known[behind], explorer.known[hidden]), (None, None));
+
+        // on a toroidal map the obstacle is right next to us, and it hides the node behind it
+        map.connectivity.topology = Topology::Toroidal;
+        let explorer = Explorer::new(start, goal, SensorParams::default(), &map.nodes, &map.connectivity, &limits);
+        assert_eq!((explorer.known[behind], explorer.known[hidden]), (Some(true), None));
+        assert!(explorer.sensed[behind] && explorer.sensed[node_index(14, 3, 0)]);
+    }
+
+    #[test]
+    fn the_explorer_gives_up_on_a_walled_in_goal() {
+        let map = TestMap::new(&["",
+                                 "",
+                                 "",
+                                 "           ###",
+                                 "           #.#",
+                                 "           ###"]);
+        let (start, goal) = (node_index(2, 2, 0), node_index(12, 4, 0));
+        let limits = SearchLimits::default();
+
+        let mut explorer = Explorer::new(start, goal, SensorParams::default(), &map.nodes, &map.connectivity, &limits);
+        explore(&mut explorer, &map);
+        assert_eq!(explorer.outcome, Some(Err(PathError::Unreachable)));
+        // it only gives up once it has seen every side of the ring around the goal
+        for (x, y) in [(12, 3), (11, 4), (13, 4), (12, 5)] {
+            assert_eq!(explorer.known[node_index(x, y, 0)], Some(true));
+        }
+    }
+}
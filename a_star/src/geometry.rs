@@ -0,0 +1,49 @@
+// the searches want whole numbers for their costs, so the any-angle planners count distances in thousandths of a node.
+// Rounding every edge up and the heuristic down keeps the heuristic from overestimating.
+pub const COST_SCALE: f32 = 1000.0;
+
+
+pub fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+/**
+What the straight line from `a` to `b` costs as an edge of a search, rounded up.
+ */
+pub fn edge_cost(a: (f32, f32), b: (f32, f32)) -> i32 {
+    (distance(a, b) * COST_SCALE).ceil() as i32
+}
+
+/**
+The straight line from `a` to `b` as a heuristic for a search, rounded down so it never guesses too high.
+ */
+pub fn heuristic_cost(a: (f32, f32), b: (f32, f32)) -> i32 {
+    (distance(a, b) * COST_SCALE).floor() as i32
+}
+
+/**
+How long the path through `points` is, in nodes.
+ */
+pub fn path_length(points: &[(f32, f32)]) -> f32 {
+    points.windows(2).map(|step| distance(step[0], step[1])).sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::SeededRng;
+
+    #[test]
+    fn the_heuristic_never_costs_more_than_the_edges() {
+        let mut rng = SeededRng::new(2);
+        let mut point = || (rng.next_f32() * 16.0 - 0.5, rng.next_f32() * 16.0 - 0.5);
+        for _ in 0..1000 {
+            let (a, b, c) = (point(), point(), point());
+            assert!(heuristic_cost(a, b) <= edge_cost(a, b));
+            // going by way of another point never beats the straight line, even with the rounding
+            assert!(heuristic_cost(a, c) <= edge_cost(a, b) + edge_cost(b, c));
+        }
+        assert_eq!(path_length(&[(0.0, 0.0), (3.0, 4.0), (3.0, 5.0)]), 6.0);
+    }
+}
@@ -0,0 +1,509 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::f32::consts::{PI, SQRT_2};
+
+use crate::path::PathError;
+use crate::reeds_shepp;
+use crate::{exit_bit, node_index, Node, MAP_HEIGHT, MAP_WIDTH};
+
+
+// the longest distance we drive between two collision checks, in nodes
+const SAMPLE_SPACING: f32 = 0.25;
+
+
+/**
+A continuous position and heading on the map. x and y are in nodes, so (3.0, 4.0) is the center of the node at (3, 4)
+and that node covers everything within half a node of it. theta is in radians, 0 faces east and because y grows
+downwards a positive theta turns clockwise on screen.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+}
+
+impl Pose {
+    pub fn new(x: f32, y: f32, theta: f32) -> Pose {
+        Pose { x, y, theta }
+    }
+
+    /**
+    Where we end up after driving `distance` along an arc with the given curvature (1 / radius, 0 for a straight
+    line). A negative distance drives in reverse.
+     */
+    pub fn advanced(&self, curvature: f32, distance: f32) -> Pose {
+        if curvature == 0.0 {
+            return Pose::new(self.x + distance * self.theta.cos(), self.y + distance * self.theta.sin(), self.theta);
+        }
+
+        let theta = self.theta + curvature * distance;
+        Pose::new(self.x + (theta.sin() - self.theta.sin()) / curvature,
+                  self.y - (theta.cos() - self.theta.cos()) / curvature,
+                  reeds_shepp::wrap_angle(theta))
+    }
+
+    /**
+    The (x, y) of the node this pose is in, or None when it's off the map.
+     */
+    pub fn cell(&self) -> Option<(i32, i32)> {
+        let (x, y) = ((self.x + 0.5).floor() as i32, (self.y + 0.5).floor() as i32);
+        if (0..MAP_WIDTH).contains(&x) && (0..MAP_HEIGHT).contains(&y) { Some((x, y)) } else { None }
+    }
+
+    /**
+    The four corners of a `length` x `width` car centered on this pose, front left first, going clockwise.
+     */
+    pub fn footprint(&self, length: f32, width: f32) -> [(f32, f32); 4] {
+        let (sin, cos) = self.theta.sin_cos();
+        let corner = |forward: f32, side: f32| {
+            (self.x + forward * cos - side * sin, self.y + forward * sin + side * cos)
+        };
+        let (half_length, half_width) = (length / 2.0, width / 2.0);
+        [
+            corner(half_length, -half_width),
+            corner(half_length, half_width),
+            corner(-half_length, half_width),
+            corner(-half_length, -half_width),
+        ]
+    }
+}
+
+
+/**
+Everything that shapes a Hybrid A* search. Distances are in nodes and angles in radians.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HybridParams {
+    // the tightest circle the car can drive
+    pub turning_radius: f32,
+    // how far each motion primitive drives. It should be long enough to leave the node we start in.
+    pub step_length: f32,
+    // how many steering angles we try on each side, between straight ahead and full lock
+    pub steering_samples: usize,
+    // how many headings count as different states in the same node
+    pub heading_bins: usize,
+    // how close to the goal heading we need to finish
+    pub heading_tolerance: f32,
+    pub allow_reverse: bool,
+    // multiplies the length of everything driven in reverse
+    pub reverse_penalty: f32,
+    // added per step, scaled by how far the wheel is turned (1.0 at full lock)
+    pub steering_penalty: f32,
+    // added every time we switch between driving forwards and reversing
+    pub direction_switch_penalty: f32,
+    // try to finish with a Reeds-Shepp curve straight to the goal every `analytic_interval` expansions
+    pub analytic_expansion: bool,
+    pub analytic_interval: usize,
+    pub car_length: f32,
+    pub car_width: f32,
+    // give up after this many expansions
+    pub max_expansions: usize,
+}
+
+impl Default for HybridParams {
+    fn default() -> Self {
+        HybridParams {
+            turning_radius: 2.0,
+            step_length: 1.5,
+            steering_samples: 2,
+            heading_bins: 72,
+            heading_tolerance: PI / 12.0,
+            allow_reverse: true,
+            reverse_penalty: 2.0,
+            steering_penalty: 0.1,
+            direction_switch_penalty: 2.0,
+            analytic_expansion: true,
+            analytic_interval: 5,
+            car_length: 1.2,
+            car_width: 0.6,
+            max_expansions: 20000,
+        }
+    }
+}
+
+
+/**
+The trajectory a Hybrid A* search found, sampled every SAMPLE_SPACING nodes or so. `reversing` says for each pose
+whether the car was backing up to get there.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct HybridPath {
+    pub poses: Vec<Pose>,
+    pub reversing: Vec<bool>,
+    pub cost: f32,
+    // whether the last stretch came from a Reeds-Shepp shot at the goal rather than from the search itself
+    pub analytic_expansion_used: bool,
+}
+
+
+// f32 isn't Ord, so we wrap the priorities we push on the open set in a type that orders them with total_cmp
+#[derive(PartialEq)]
+struct Priority(f32);
+
+impl Eq for Priority {}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+
+struct SearchNode {
+    pose: Pose,
+    cost: f32,
+    reversing: bool,
+    parent: Option<usize>,
+    // the poses we drove through from the parent to get here, ending with `pose`
+    trajectory: Vec<Pose>,
+}
+
+
+/**
+Hybrid A* for a car-like vehicle. The search expands continuous (x, y, theta) poses by driving short arcs at a
+handful of steering angles, forwards and (optionally) in reverse. The grid is used twice: every pose along every
+arc must keep the car's footprint clear of obstacle nodes, and two poses that land in the same node with roughly the
+same heading count as the same state, which keeps the search finite.
+
+The search stays on `layer` and treats the edge of the map as a wall, even on a toroidal map. The heuristic is the
+larger of the straight line distance and the obstacle aware distance on the grid, which steers the search around
+walls early.
+ */
+pub fn hybrid_a_star(start: Pose, goal: Pose, layer: i32, nodes: &[Node], params: &HybridParams)
+                     -> Result<HybridPath, PathError> {
+    if start.cell().is_none() {
+        return Err(PathError::StartOutOfBounds);
+    }
+    let (goal_x, goal_y) = goal.cell().ok_or(PathError::GoalOutOfBounds)?;
+
+    let collides = |pose: &Pose| footprint_collides(pose, layer, nodes, params);
+    if collides(&start) {
+        return Err(PathError::StartBlocked);
+    }
+    if collides(&goal) {
+        return Err(PathError::GoalBlocked);
+    }
+
+    let grid_distance = grid_distance_field(goal_x, goal_y, layer, nodes);
+    let heuristic = |pose: &Pose| -> f32 {
+        let straight = ((pose.x - goal.x).powi(2) + (pose.y - goal.y).powi(2)).sqrt();
+        match pose.cell() {
+            Some((x, y)) => straight.max(grid_distance[(y * MAP_WIDTH + x) as usize]),
+            None => f32::INFINITY,
+        }
+    };
+    if heuristic(&start).is_infinite() {
+        // the goal is walled off from the start on the grid, so the car won't get there either
+        return Err(PathError::Unreachable);
+    }
+
+    let state_key = |pose: &Pose| -> Option<(i32, i32, usize)> {
+        let (x, y) = pose.cell()?;
+        let turn = (pose.theta.rem_euclid(2.0 * PI) / (2.0 * PI) * params.heading_bins as f32) as usize;
+        Some((x, y, turn % params.heading_bins))
+    };
+
+    let mut search_nodes = vec![
+        SearchNode { pose: start, cost: 0.0, reversing: false, parent: None, trajectory: vec![] },
+    ];
+    let mut best_cost: HashMap<(i32, i32, usize), f32> = HashMap::new();
+    let mut closed: HashSet<(i32, i32, usize)> = HashSet::new();
+    let mut open_set = BinaryHeap::new();
+    open_set.push(Reverse((Priority(heuristic(&start)), 0)));
+
+    let primitives = motion_primitives(params);
+    let mut expansions = 0;
+
+    while let Some(Reverse((_, current_id))) = open_set.pop() {
+        let current_pose = search_nodes[current_id].pose;
+        match state_key(&current_pose) {
+            Some(key) if closed.insert(key) => {}
+            _ => continue,
+        }
+
+        if current_pose.cell() == goal.cell()
+            && reeds_shepp::wrap_angle(current_pose.theta - goal.theta).abs() <= params.heading_tolerance {
+            return Ok(construct_hybrid_path(&search_nodes, current_id, None));
+        }
+
+        expansions += 1;
+        if expansions > params.max_expansions {
+            return Err(PathError::BudgetExceeded);
+        }
+
+        // every so often try to drive straight to the goal with a Reeds-Shepp curve. When that curve is clear of
+        // obstacles we are done, which saves the search from having to hit the goal heading exactly by itself.
+        if params.analytic_expansion && expansions % params.analytic_interval.max(1) == 0 {
+            if let Some(shot) = analytic_expansion(&current_pose, &goal, layer, nodes, params) {
+                return Ok(construct_hybrid_path(&search_nodes, current_id, Some(shot)));
+            }
+        }
+
+        for &(curvature, direction, steering) in &primitives {
+            let trajectory = sample_arc(&current_pose, curvature, direction * params.step_length);
+            if trajectory.iter().any(&collides) || crosses_wall(&current_pose, &trajectory, layer, nodes) {
+                continue;
+            }
+
+            let next_pose = *trajectory.last().unwrap();
+            let next_key = match state_key(&next_pose) {
+                Some(key) if !closed.contains(&key) => key,
+                _ => continue,
+            };
+
+            let reversing = direction < 0.0;
+            let current = &search_nodes[current_id];
+            let mut step_cost = params.step_length;
+            if reversing {
+                step_cost *= params.reverse_penalty;
+            }
+            step_cost += params.steering_penalty * steering;
+            if current.parent.is_some() && reversing != current.reversing {
+                step_cost += params.direction_switch_penalty;
+            }
+            let next_cost = current.cost + step_cost;
+
+            if next_cost < *best_cost.get(&next_key).unwrap_or(&f32::INFINITY) {
+                best_cost.insert(next_key, next_cost);
+                search_nodes.push(SearchNode {
+                    pose: next_pose,
+                    cost: next_cost,
+                    reversing,
+                    parent: Some(current_id),
+                    trajectory,
+                });
+                open_set.push(Reverse((Priority(next_cost + heuristic(&next_pose)), search_nodes.len() - 1)));
+            }
+        }
+    }
+
+    Err(PathError::Unreachable)
+}
+
+
+/**
+Returns `(curvature, direction, steering)` for every arc we try from each pose. Direction is 1 forwards and -1 in
+reverse, steering is how far the wheel is turned from 0 (straight) to 1 (full lock).
+ */
+fn motion_primitives(params: &HybridParams) -> Vec<(f32, f32, f32)> {
+    let max_curvature = 1.0 / params.turning_radius;
+    let samples = params.steering_samples.max(1) as i32;
+    let directions: &[f32] = if params.allow_reverse { &[1.0, -1.0] } else { &[1.0] };
+
+    let mut primitives = Vec::new();
+    for &direction in directions {
+        for step in -samples..=samples {
+            let steering = step as f32 / samples as f32;
+            primitives.push((max_curvature * steering, direction, steering.abs()));
+        }
+    }
+    primitives
+}
+
+
+/**
+Drives `distance` along an arc from `start` and returns the poses along the way, not including `start` itself.
+ */
+fn sample_arc(start: &Pose, curvature: f32, distance: f32) -> Vec<Pose> {
+    let samples = (distance.abs() / SAMPLE_SPACING).ceil().max(1.0) as usize;
+    (1..=samples)
+        .map(|sample| start.advanced(curvature, distance * sample as f32 / samples as f32))
+        .collect()
+}
+
+
+/**
+Whether a car at `pose` would overlap an obstacle node or hang off the map. We check a grid of points over the car's
+footprint that's fine enough that no node can slip between them.
+ */
+fn footprint_collides(pose: &Pose, layer: i32, nodes: &[Node], params: &HybridParams) -> bool {
+    let along = (params.car_length / 0.4).ceil().max(1.0) as i32;
+    let across = (params.car_width / 0.4).ceil().max(1.0) as i32;
+    let (sin, cos) = pose.theta.sin_cos();
+
+    for i in 0..=along {
+        for j in 0..=across {
+            let forward = params.car_length * (i as f32 / along as f32 - 0.5);
+            let side = params.car_width * (j as f32 / across as f32 - 0.5);
+            let point = Pose::new(pose.x + forward * cos - side * sin, pose.y + forward * sin + side * cos, 0.0);
+            match point.cell() {
+                Some((x, y)) if !nodes[node_index(x, y, layer)].obstacle => {}
+                _ => return true,
+            }
+        }
+    }
+    false
+}
+
+
+/**
+Whether the middle of the car goes through a wall on its way from `from` along `trajectory`. The samples are close
+enough together that the middle never skips a node, but it can cut across a corner, and then a wall on either side of
+the corner stops it.
+ */
+fn crosses_wall(from: &Pose, trajectory: &[Pose], layer: i32, nodes: &[Node]) -> bool {
+    let walls = |x: i32, y: i32| nodes[node_index(x, y, layer)].walls;
+
+    std::iter::once(from).chain(trajectory).zip(trajectory).any(|(previous, next)| {
+        let ((x0, y0), (x1, y1)) = match (previous.cell(), next.cell()) {
+            (Some(previous), Some(next)) => (previous, next),
+            _ => return false,
+        };
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        (dx != 0 && (walls(x0, y0) | walls(x0, y1)) & exit_bit(dx, 0) != 0)
+            || (dy != 0 && (walls(x0, y0) | walls(x1, y0)) & exit_bit(0, dy) != 0)
+    })
+}
+
+
+/**
+The cost of the cheapest 8-connected path from every node on `layer` to the goal node, ignoring how the car turns.
+Nodes that can't reach the goal get infinity. The car drives between nodes rather than along the edges, so one-way
+edges don't hold it up and don't count here either. We leave out walls too, which only makes the cost lower than it
+really is, so it's still fine as a heuristic.
+ */
+fn grid_distance_field(goal_x: i32, goal_y: i32, layer: i32, nodes: &[Node]) -> Vec<f32> {
+    let mut distance = vec![f32::INFINITY; (MAP_WIDTH * MAP_HEIGHT) as usize];
+    let mut open_set = BinaryHeap::new();
+    distance[(goal_y * MAP_WIDTH + goal_x) as usize] = 0.0;
+    open_set.push(Reverse((Priority(0.0), goal_x, goal_y)));
+
+    while let Some(Reverse((Priority(cost), x, y))) = open_set.pop() {
+        if cost > distance[(y * MAP_WIDTH + x) as usize] {
+            continue;
+        }
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (next_x, next_y) = (x + dx, y + dy);
+                if (dx == 0 && dy == 0) || !(0..MAP_WIDTH).contains(&next_x) || !(0..MAP_HEIGHT).contains(&next_y) {
+                    continue;
+                }
+                if nodes[node_index(next_x, next_y, layer)].obstacle {
+                    continue;
+                }
+
+                let next_cost = cost + if dx != 0 && dy != 0 { SQRT_2 } else { 1.0 };
+                let next = (next_y * MAP_WIDTH + next_x) as usize;
+                if next_cost < distance[next] {
+                    distance[next] = next_cost;
+                    open_set.push(Reverse((Priority(next_cost), next_x, next_y)));
+                }
+            }
+        }
+    }
+
+    distance
+}
+
+
+/**
+Samples the shortest Reeds-Shepp curve from `from` to `goal` and returns its poses and whether each one is driven in
+reverse, as long as the whole curve is clear of obstacles.
+ */
+fn analytic_expansion(from: &Pose, goal: &Pose, layer: i32, nodes: &[Node], params: &HybridParams)
+                      -> Option<(Vec<Pose>, Vec<bool>, f32)> {
+    if !params.allow_reverse {
+        // Reeds-Shepp curves back up whenever that's shorter, which this car isn't allowed to do
+        return None;
+    }
+
+    let segments = reeds_shepp::shortest_path(from, goal, params.turning_radius)?;
+    let mut poses = Vec::new();
+    let mut reversing = Vec::new();
+    let mut cost = 0.0;
+    let mut pose = *from;
+
+    for segment in segments {
+        let distance = segment.length() * params.turning_radius;
+        if distance == 0.0 {
+            continue;
+        }
+
+        let arc = sample_arc(&pose, segment.curvature() / params.turning_radius, distance);
+        if arc.iter().any(|pose| footprint_collides(pose, layer, nodes, params))
+            || crosses_wall(&pose, &arc, layer, nodes) {
+            return None;
+        }
+
+        cost += if distance < 0.0 { -distance * params.reverse_penalty } else { distance };
+        pose = *arc.last().unwrap();
+        reversing.extend(std::iter::repeat_n(distance < 0.0, arc.len()));
+        poses.extend(arc);
+    }
+
+    Some((poses, reversing, cost))
+}
+
+
+fn construct_hybrid_path(search_nodes: &[SearchNode],
+                         last_id: usize,
+                         shot: Option<(Vec<Pose>, Vec<bool>, f32)>) -> HybridPath {
+    let mut poses = Vec::new();
+    let mut reversing = Vec::new();
+
+    let mut current_id = Some(last_id);
+    while let Some(id) = current_id {
+        let node = &search_nodes[id];
+        if node.parent.is_none() {
+            // the start has no trajectory leading to it, just its own pose
+            poses.push(node.pose);
+            reversing.push(false);
+        } else {
+            poses.extend(node.trajectory.iter().rev());
+            reversing.extend(std::iter::repeat_n(node.reversing, node.trajectory.len()));
+        }
+        current_id = node.parent;
+    }
+    poses.reverse();
+    reversing.reverse();
+
+    let mut cost = search_nodes[last_id].cost;
+    let analytic_expansion_used = shot.is_some();
+    if let Some((shot_poses, shot_reversing, shot_cost)) = shot {
+        poses.extend(shot_poses);
+        reversing.extend(shot_reversing);
+        cost += shot_cost;
+    }
+
+    HybridPath { poses, reversing, cost, analytic_expansion_used }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node_index, open_map};
+
+    #[test]
+    fn the_car_gets_to_the_goal_pose_without_hitting_anything() {
+        let mut nodes = open_map();
+        for y in 0..10 {
+            nodes[node_index(8, y, 0)].obstacle = true;
+        }
+        let params = HybridParams::default();
+        let start = Pose::new(3.0, 3.0, 0.0);
+
+        // past the obstacle facing back the way we came, parked facing the other way right next to the start, and
+        // round the end of the obstacle
+        for goal in [Pose::new(12.0, 3.0, PI), Pose::new(3.0, 6.0, PI), Pose::new(12.0, 13.0, PI / 2.0)] {
+            let path = hybrid_a_star(start, goal, 0, &nodes, &params).unwrap();
+            assert_eq!(path.poses[0], start);
+            let end = path.poses[path.poses.len() - 1];
+            assert_eq!(end.cell(), goal.cell());
+            assert!(reeds_shepp::wrap_angle(end.theta - goal.theta).abs() <= params.heading_tolerance);
+            assert!(path.poses.iter().all(|pose| !footprint_collides(pose, 0, &nodes, &params)));
+            // the car drives there, it never jumps
+            let step_length = |step: &[Pose]| (step[1].x - step[0].x).hypot(step[1].y - step[0].y);
+            assert!(path.poses.windows(2).all(|step| step_length(step) <= SAMPLE_SPACING + 1e-4));
+            assert_eq!(path.reversing.len(), path.poses.len());
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+
+
+/**
+How much work a single `a_star` query is allowed to do. Every limit is optional and the default has none, which
+searches until it finds the goal or runs out of nodes.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    // stop after taking this many nodes off the open set
+    pub max_expansions: Option<usize>,
+    // stop once the search has been running this long
+    pub max_time: Option<Duration>,
+    // don't look at any path that would cost more than this
+    pub max_cost: Option<i32>,
+    // when the goal can't be reached, or a limit stops the search, return the path to the explored node with the
+    // lowest heuristic instead of an error. Game AI would rather get as close as it can than stand still.
+    pub allow_partial: bool,
+    // which of the states with the same score the search looks at first
+    pub tie_breaking: TieBreaking,
+}
+
+impl SearchLimits {
+    /**
+    Starts the clock on a search with these limits.
+     */
+    pub fn start(&self) -> SearchBudget {
+        SearchBudget { limits: *self, spent: Duration::ZERO, resumed: Instant::now(), expansions: 0 }
+    }
+}
+
+
+/**
+How the search picks between states in the open set with the same score. On an open map lots of states tie, and which
+of them goes first decides how much of the map gets searched before the goal. `Index` takes the state with the lowest
+number, which is how the search has always done it and fans out in odd patterns. `HigherG` takes the one furthest from
+the start, and `LowerH` the one closest to the goal by the heuristic. As the scores tie those two always agree, but
+they're both here since both are common ways of putting it. `CrossProduct` takes the one closest to the straight line
+from the start to the goal, in spaces whose states have a position. `Random` picks at random, the same way every time
+for the same seed.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreaking {
+    #[default]
+    Index,
+    HigherG,
+    LowerH,
+    CrossProduct,
+    Random(u64),
+}
+
+impl TieBreaking {
+    pub const ALL: [TieBreaking; 5] = [TieBreaking::Index,
+                                       TieBreaking::HigherG,
+                                       TieBreaking::LowerH,
+                                       TieBreaking::CrossProduct,
+                                       TieBreaking::Random(1)];
+
+    /**
+    The next policy in `ALL`, back to the first after the last. A `Random` one gets `seed`.
+     */
+    pub fn next(&self, seed: u64) -> TieBreaking {
+        let index = TieBreaking::ALL.iter()
+            .position(|policy| std::mem::discriminant(policy) == std::mem::discriminant(self))
+            .unwrap();
+        TieBreaking::ALL[(index + 1) % TieBreaking::ALL.len()].with_seed(seed)
+    }
+
+    /**
+    The same policy, but with `seed` if it's `Random`.
+     */
+    pub fn with_seed(&self, seed: u64) -> TieBreaking {
+        match self {
+            TieBreaking::Random(_) => TieBreaking::Random(seed),
+            policy => *policy,
+        }
+    }
+
+    /**
+    A short name for the status line.
+     */
+    pub fn label(&self) -> &'static str {
+        match self {
+            TieBreaking::Index => "ix",
+            TieBreaking::HigherG => "hg",
+            TieBreaking::LowerH => "lh",
+            TieBreaking::CrossProduct => "cp",
+            TieBreaking::Random(_) => "rn",
+        }
+    }
+}
+
+
+/**
+Keeps track of how much of its `SearchLimits` a running search has used up. A search that is spread over several
+frames pauses its budget in between, so the time limit only counts the time spent actually searching.
+ */
+pub struct SearchBudget {
+    limits: SearchLimits,
+    // the time spent searching before the last `resume`
+    spent: Duration,
+    resumed: Instant,
+    expansions: usize,
+}
+
+impl SearchBudget {
+    pub fn resume(&mut self) {
+        self.resumed = Instant::now();
+    }
+
+    pub fn pause(&mut self) {
+        self.spent += self.resumed.elapsed();
+    }
+
+    /**
+    Counts one more expansion and returns false if that, or the time spent so far, goes over the limits.
+     */
+    pub fn expand(&mut self) -> bool {
+        self.expansions += 1;
+        if self.limits.max_expansions.is_some_and(|max| self.expansions > max) {
+            return false;
+        }
+        self.limits.max_time.is_none_or(|max| self.spent + self.resumed.elapsed() <= max)
+    }
+
+    /**
+    Whether a path with this (estimated) total cost is still within the cost limit.
+     */
+    pub fn affordable(&self, cost: i32) -> bool {
+        self.limits.max_cost.is_none_or(|max| cost <= max)
+    }
+}
+
+
+/**
+How much work a time sliced search may do in one call, as opposed to `SearchLimits` which covers the whole search.
+The default has no limits and runs the search to the end.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeSlice {
+    pub max_expansions: Option<usize>,
+    pub max_time: Option<Duration>,
+}
+
+impl TimeSlice {
+    /**
+    Whether a slice that started at `started` and has done `expansions` expansions so far has used itself up.
+     */
+    pub fn used_up(&self, started: Instant, expansions: usize) -> bool {
+        self.max_expansions.is_some_and(|max| expansions >= max)
+            || self.max_time.is_some_and(|max| started.elapsed() >= max)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PathError;
+    use crate::{node_index, Node, TestMap};
+
+    #[test]
+    fn the_limits_stop_the_search_and_a_partial_path_gets_as_close_as_it_can() {
+        let map = TestMap::new(&[]);
+        let TestMap { nodes, connectivity, .. } = &map;
+        let (start, goal) = (node_index(0, 0, 0), node_index(15, 15, 0));
+        let search = |limits: SearchLimits| map.sliced(start, goal, &limits).finish(nodes, connectivity);
+
+        let tight = SearchLimits { max_expansions: Some(20), ..SearchLimits::default() };
+        assert_eq!(search(tight), Err(PathError::BudgetExceeded));
+        let partial = search(SearchLimits { allow_partial: true, ..tight }).unwrap();
+        assert_eq!((partial.nodes[0], partial.partial), (start, Some(PathError::BudgetExceeded)));
+        let end = &nodes[partial.nodes[partial.nodes.len() - 1]];
+        let heuristic = |node: &Node| node.heuristic(&nodes[goal], nodes, connectivity);
+        assert!(heuristic(end) < heuristic(&nodes[start]));
+        assert_ne!(partial.nodes[partial.nodes.len() - 1], goal);
+        assert_eq!(partial.cost, partial.nodes.len() as i32 - 1);
+
+        // the cheapest path costs 30, so a cost limit below that leaves no path to find
+        assert_eq!(search(SearchLimits { max_cost: Some(30), ..SearchLimits::default() }).unwrap().cost, 30);
+        assert!(search(SearchLimits { max_cost: Some(29), ..SearchLimits::default() }).is_err());
+    }
+
+    #[test]
+    fn cycling_through_the_policies_keeps_the_seed() {
+        let mut policy = TieBreaking::Random(7);
+        for _ in 0..TieBreaking::ALL.len() {
+            policy = policy.next(7);
+        }
+        assert_eq!(policy, TieBreaking::Random(7));
+        assert_eq!(policy.with_seed(8), TieBreaking::Random(8));
+        assert_eq!(TieBreaking::LowerH.with_seed(8), TieBreaking::LowerH);
+    }
+}
@@ -1,24 +1,83 @@
 extern crate olc_pixel_game_engine;
 
+mod agents;
+mod components;
+mod constraints;
+mod dijkstra;
+mod explore;
+mod geometry;
+mod hybrid;
+mod limits;
+mod multi;
+mod nav_graph;
+mod navmesh;
+mod oriented;
+mod path;
+mod portal;
+mod reeds_shepp;
+mod sampling;
+mod scheduler;
+mod scratch;
+mod service;
+mod sliced;
+mod space;
+mod terrain;
+mod visibility;
+mod waypoints;
+mod yen;
+
 use olc::Application;
 use olc_pixel_game_engine::{get_key, RED};
-use olc_pixel_game_engine::GREY;
+use olc_pixel_game_engine::{GREY, DARK_GREY, VERY_DARK_GREY};
 use olc_pixel_game_engine::GREEN;
 use olc_pixel_game_engine::get_mouse_y;
 use olc_pixel_game_engine::get_mouse_x;
 use olc_pixel_game_engine::get_mouse;
 use olc_pixel_game_engine::fill_rect;
+use olc_pixel_game_engine::fill_circle;
+use olc_pixel_game_engine::fill_triangle;
 use olc_pixel_game_engine::Error;
 use olc_pixel_game_engine::draw_line;
 use olc_pixel_game_engine::DARK_BLUE;
-use olc_pixel_game_engine::BLUE;
 use olc_pixel_game_engine::BLACK;
-use olc_pixel_game_engine::Key::{CTRL, SHIFT};
+use olc_pixel_game_engine::Key::{CTRL, SHIFT, K1, K2, K3, K4, UP, DOWN, LEFT, RIGHT, T, PGUP, PGDN, S, E, P, DEL, ESCAPE, O, R, H, G, K5, B, Q, M, W, V, N, K6, K7, F2, K, Y, U, C, J, F3, I, L, X, Z, A, TAB, D, F, K8, K9};
+use olc_pixel_game_engine::draw_rect;
+use olc_pixel_game_engine::draw_line_with_pattern;
+use olc_pixel_game_engine::{CYAN, MAGENTA, DARK_CYAN, DARK_GREEN, DARK_MAGENTA, DARK_RED, DARK_YELLOW, VERY_DARK_CYAN};
+use olc_pixel_game_engine::get_mouse_wheel;
+use olc_pixel_game_engine::draw_string;
+use olc_pixel_game_engine::Pixel;
+use olc_pixel_game_engine::WHITE;
 use olc_pixel_game_engine::screen_height;
 use olc_pixel_game_engine::screen_width;
 use olc_pixel_game_engine::VERY_DARK_BLUE;
 use olc_pixel_game_engine::YELLOW;
+use olc_pixel_game_engine::VERY_DARK_YELLOW;
 use crate::olc_pixel_game_engine as olc;
+use std::time::Duration;
+use crate::agents::{update_agents, Agent, AGENT_RADIUS};
+use crate::components::Components;
+use crate::constraints::{QueryConstraints, Zone};
+use crate::dijkstra::{combine_fields, distance_field, downhill, flee_field, save_field_csv};
+use crate::explore::{Explorer, SensorParams};
+use crate::hybrid::{hybrid_a_star, HybridParams, HybridPath, Pose};
+use crate::limits::{SearchLimits, TieBreaking, TimeSlice};
+use crate::multi::{multi_a_star, MultiPath};
+use crate::nav_graph::NavGraph;
+use crate::navmesh::{MeshPath, NavMesh};
+use crate::oriented::{oriented_a_star, Heading, OrientedPath, TurnCosts};
+use crate::path::{Path, PathError};
+use crate::portal::{Portal, PortalKind};
+use crate::sampling::{Prm, RrtStar, Sampler, SamplingParams};
+use crate::scheduler::{PathScheduler, RequestId};
+use crate::scratch::SearchScratch;
+use crate::service::{PathHandle, PathPoll, PathService};
+use crate::sliced::SlicedSearch;
+use crate::space::GridSpace;
+use crate::terrain::Terrain;
+use crate::visibility::{ObstacleMap, VisibilityGraph, VisibilityPath};
+use crate::waypoints::{route_through, visiting_order, Route};
+use crate::yen::k_shortest_paths;
 
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -27,44 +86,332 @@ struct Node {
     x: i32,
     // nodes pos in 2D space
     y: i32,
+    // which layer (floor) of the map the node is on
+    z: i32,
 
     obstacle: bool,
     // is the node an obstruction
-    visited: bool,
-    // have we searched this node before?
-    global_goal: i32,
-    // distance to gaol so far
-    local_goal: i32,
-    parent: Option<usize>,
+    terrain: Terrain,
+    // what the ground is like, which sets what stepping onto the node costs
+    closed_exits: u8,
+    // the sides of the node we can't leave it through, made of the EXIT_ bits. A closed exit on only one of the two
+    // nodes of an edge makes the edge one-way.
+    walls: u8,
+    // the sides of the node with a wall between it and its neighbor, made of the EXIT_ bits like the CELL_PATH bits of
+    // the maze generator. A wall blocks the edge both ways and is kept on the nodes on both sides of it.
 }
 
+// bits of `Node::closed_exits` and `Node::walls`, one for each side of a node
+const EXIT_NORTH: u8 = 0x01;
+const EXIT_SOUTH: u8 = 0x02;
+const EXIT_WEST: u8 = 0x04;
+const EXIT_EAST: u8 = 0x08;
+
 const MAP_WIDTH: i32 = 16;
 const MAP_HEIGHT: i32 = 16;
+// how many floors the map has. Each layer is a full MAP_WIDTH x MAP_HEIGHT grid and the layers are only connected
+// through portals.
+const MAP_LAYERS: i32 = 3;
 
 
 const NODE_SIZE: i32 = 9;
 const NODE_BORDER: i32 = 6;
 
+// the biggest zoom factor we allow. At this zoom a node is large enough to fit a three digit label.
+const MAX_ZOOM: i32 = 4;
+
+// width and height in pixels of a single character drawn by `draw_string`
+const CHAR_SIZE: i32 = 8;
+
+// how much searching the time sliced path requests get to do each frame, shared between all of them. Kept small so we
+// can watch a search take a few frames on our little map.
+const FRAME_BUDGET: TimeSlice = TimeSlice { max_expansions: Some(16), max_time: Some(Duration::from_millis(2)) };
+// how many worker threads the path service runs
+const WORKER_THREADS: usize = 2;
+// how many requests the M key queues up at once
+const CROWD_SIZE: usize = 16;
+// how many samples the sampling planners take each frame, slow enough to watch them grow
+const SAMPLES_PER_FRAME: usize = 2;
+// the farthest the explorer's sensor can reach, in nodes
+const MAX_SENSOR_RANGE: i32 = 8;
+
+
+/**
+What we color each node by. `Default` is the plain obstacle/free view, the others turn the grid into a heatmap of
+the scores from the last A* run or of one of the distance fields.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DisplayMode {
+    Default,
+    // cost of the path from the start to this node
+    GCost,
+    // heuristic estimate from this node to the goal
+    HCost,
+    // g + h, the value the open set is ordered by
+    FCost,
+    // every connected region of the map in its own color
+    Components,
+    // what it costs to walk from each node to the goal
+    Distance,
+    // the field for running away from the goal, pulled towards the waypoints when there are any
+    Flee,
+}
+
+// the colors we cycle through when coloring the connected regions of the map
+const COMPONENT_COLORS: [Pixel; 7] =
+    [DARK_BLUE, DARK_GREEN, DARK_RED, DARK_MAGENTA, DARK_CYAN, DARK_YELLOW, VERY_DARK_CYAN];
+// how much stronger than the pull of a distance field the push of a flee field is. See `flee_field`.
+const FLEE_SCALE: f32 = 1.2;
+// where F2 saves the distance field on display
+const FIELD_CSV_FILE: &str = "distance_field.csv";
+// where F3 loads a navigation graph from, see `NavGraph` for what goes in it
+const NAV_GRAPH_FILE: &str = "nav_graph.txt";
+
+// how many alternative paths the K key steps through, and the color each of them is drawn in
+const ALTERNATIVE_COLORS: [Pixel; 5] = [YELLOW, CYAN, MAGENTA, GREEN, WHITE];
+
+// what a step into a penalty zone costs on top of the step itself
+const PENALTY_ZONE_COST: i32 = 6;
+// the terrain multipliers the U key switches to, which make the search keep off rough ground even more
+const AVOID_ROUGH_GROUND: [f32; Terrain::ALL.len()] = [1.0, 2.0, 3.0];
+
+// the colors we cycle through for the legs of a route through waypoints
+const LEG_COLORS: [Pixel; 4] = [YELLOW, CYAN, MAGENTA, WHITE];
+
+/**
+Which planner draws the path. `Grid` is the plain A* over nodes, `Oriented` also tracks the heading of a tracked vehicle
+and `Hybrid` plans continuous poses for a car that can't turn on the spot. `Visibility` treats the obstacles as polygons
+and finds the shortest path at any angle around their corners. `NavMesh` splits the free nodes into rectangles and
+pulls the path through them tight, and draws it over the grid path to compare the two. `Prm` and `RrtStar` grow a
+random roadmap or tree a few samples a frame, over the grid path too.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SearchMode {
+    Grid,
+    Oriented,
+    Hybrid,
+    Visibility,
+    NavMesh,
+    Prm,
+    RrtStar,
+}
+
+
+/**
+How the grid search is run. `Immediate` runs it all in the frame that needs it, `TimeSliced` spreads it over several
+frames with the `PathScheduler` and `Threaded` hands it to the worker threads of the `PathService`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Solver {
+    Immediate,
+    TimeSliced,
+    Threaded,
+}
+
+
+/**
+How the edges of the map connect. On a `Toroidal` map walking off the right edge puts you on the left edge and
+walking off the bottom puts you on the top, like a lot of old games do.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Topology {
+    Bounded,
+    Toroidal,
+}
+
+impl Topology {
+    /**
+    The signed number of steps from `from` to `to` along an axis that is `size` nodes long. On a toroidal map there are
+    two ways around, so we take whichever is shorter.
+     */
+    fn delta(&self, from: i32, to: i32, size: i32) -> i32 {
+        match self {
+            Topology::Bounded => to - from,
+            Topology::Toroidal => {
+                let wrapped = (to - from).rem_euclid(size);
+                if wrapped > size / 2 { wrapped - size } else { wrapped }
+            }
+        }
+    }
+}
+
+/**
+Everything about how the nodes connect to each other, besides the obstacles stored on the nodes themselves.
+ */
+#[derive(Clone)]
+struct Connectivity {
+    topology: Topology,
+    // stairs, elevators and teleporters that link nodes which aren't neighbors on the grid
+    portals: Vec<Portal>,
+}
+
+impl Connectivity {
+    /**
+    All the directed `(from, to, cost)` links added by the portals.
+     */
+    fn portal_links(&self) -> impl Iterator<Item=(usize, usize, i32)> + '_ {
+        self.portals.iter().flat_map(|portal| portal.links())
+    }
+}
+
+/**
+Returns the index of the node at (x, y) on layer z. Nodes are stored layer by layer, row by row.
+ */
+fn node_index(x: i32, y: i32, z: i32) -> usize {
+    ((z * MAP_HEIGHT + y) * MAP_WIDTH + x) as usize
+}
+
+/**
+A map of open ground with nothing in the way, like the one the app starts out with, for the tests to build on.
+ */
+#[cfg(test)]
+fn open_map() -> Vec<Node> {
+    let mut nodes = Vec::new();
+    for z in 0..MAP_LAYERS {
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                nodes.push(Node { obstacle: false, terrain: Terrain::Plain, closed_exits: 0, walls: 0, x, y, z });
+            }
+        }
+    }
+    nodes
+}
+
+/**
+A bounded map for the tests, with its components worked out. `rows` draw the top left corner of the first layer, one
+string per row from y = 0 down: `#` is an obstacle, `f` forest and `s` swamp, and anything else is plain ground. The
+rest of the map is open, like `open_map`.
+ */
+#[cfg(test)]
+struct TestMap {
+    nodes: Vec<Node>,
+    connectivity: Connectivity,
+    components: Components,
+}
+
+#[cfg(test)]
+impl TestMap {
+    fn new(rows: &[&str]) -> TestMap {
+        let mut nodes = open_map();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, tile) in row.chars().enumerate() {
+                let node = &mut nodes[node_index(x as i32, y as i32, 0)];
+                match tile {
+                    '#' => node.obstacle = true,
+                    'f' => node.terrain = Terrain::Forest,
+                    's' => node.terrain = Terrain::Swamp,
+                    _ => {}
+                }
+            }
+        }
+        TestMap::from_nodes(nodes)
+    }
+
+    /**
+    A bounded map of `nodes` the test has set up itself.
+     */
+    fn from_nodes(nodes: Vec<Node>) -> TestMap {
+        let connectivity = Connectivity { topology: Topology::Bounded, portals: Vec::new() };
+        let components = Components::new(&nodes, &connectivity);
+        TestMap { nodes, connectivity, components }
+    }
+
+    /**
+    A grid search from `start` to `goal` within `limits`, with no constraints on it.
+     */
+    fn sliced(&self, start: usize, goal: usize, limits: &SearchLimits) -> SlicedSearch {
+        SlicedSearch::new(start, goal, &self.nodes, &self.connectivity, &self.components, limits,
+                          &QueryConstraints::default())
+    }
+
+    /**
+    The cheapest path from `start` to `goal`, searched the way the app does it.
+     */
+    fn search(&self, start: usize, goal: usize) -> Result<Path, PathError> {
+        self.sliced(start, goal, &SearchLimits::default()).finish(&self.nodes, &self.connectivity)
+    }
+}
+
+/**
+Returns the index of the node (dx, dy) away from `node` on the same layer, wrapping around the edges on a toroidal
+map. Returns None when that would take us off a bounded map.
+ */
+fn neighbor_at(node: &Node, dx: i32, dy: i32, topology: Topology) -> Option<usize> {
+    let (x, y) = (node.x + dx, node.y + dy);
+    match topology {
+        Topology::Toroidal => Some(node_index(x.rem_euclid(MAP_WIDTH), y.rem_euclid(MAP_HEIGHT), node.z)),
+        Topology::Bounded if (0..MAP_WIDTH).contains(&x) && (0..MAP_HEIGHT).contains(&y) => {
+            Some(node_index(x, y, node.z))
+        }
+        Topology::Bounded => None,
+    }
+}
+
 
 impl Node {
     /**
-    The pythagorean theorem to get the distance between two points "as the crow flies" heuristic to aid our A* search
+    The pythagorean theorem to get the distance between two points "as the crow flies" heuristic to aid our A* search.
+    On a toroidal map the crow is allowed to fly across the edges.
      */
-    fn distance(&self, goal: &Node) -> i32 {
-        let square_dif_x: f32 = ((self.x - goal.x) * (self.x - goal.x)) as f32;
-        let square_dif_y: f32 = ((self.y - goal.y) * (self.y - goal.y)) as f32;
-        return (square_dif_x + square_dif_y).sqrt() as i32;
+    fn distance(&self, goal: &Node, topology: Topology) -> i32 {
+        let dif_x = topology.delta(self.x, goal.x, MAP_WIDTH);
+        let dif_y = topology.delta(self.y, goal.y, MAP_HEIGHT);
+        let square_dif_x: f32 = (dif_x * dif_x) as f32;
+        let square_dif_y: f32 = (dif_y * dif_y) as f32;
+        (square_dif_x + square_dif_y).sqrt() as i32
     }
 
     /**
      * This is how the A* algorithm is fast and efficient. Basically a better heuristic means a more efficient search.
+     *
+     * Portals make this a little more involved, since a teleporter can make a far away node cheap to reach and the
+     * only way to another layer is through a portal. Any route that uses portals has to walk to the entrance of the
+     * first one it takes and walk from the exit of the last one it takes, so the cheapest of each of those added
+     * together never overestimates. Returns i32::MAX when the goal can't be reached at all.
      */
-    fn heuristic(&self, goal: &Node) -> i32 {
-        return self.distance(goal);
+    fn heuristic(&self, goal: &Node, nodes: &[Node], connectivity: &Connectivity) -> i32 {
+        let topology = connectivity.topology;
+        let mut best = if self.z == goal.z { self.distance(goal, topology) } else { i32::MAX };
+
+        let to_entrance = connectivity.portal_links()
+            .filter(|&(from, _, _)| nodes[from].z == self.z)
+            .map(|(from, _, cost)| self.distance(&nodes[from], topology) + cost)
+            .min();
+        let from_exit = connectivity.portal_links()
+            .filter(|&(_, to, _)| nodes[to].z == goal.z)
+            .map(|(_, to, _)| nodes[to].distance(goal, topology))
+            .min();
+
+        if let (Some(to_entrance), Some(from_exit)) = (to_entrance, from_exit) {
+            best = best.min(to_entrance + from_exit);
+        }
+        best
     }
 
 }
 
+/**
+Maps `value` in the range `min..=max` onto a blue -> green -> yellow -> red gradient. Used to draw the score heatmaps.
+ */
+fn gradient(value: i32, min: i32, max: i32) -> Pixel {
+    let t = if max > min { (value - min) as f32 / (max - min) as f32 } else { 0.0 };
+    let t = t.clamp(0.0, 1.0);
+
+    // three segments: blue to green, green to yellow, yellow to red.
+    let (r, g, b) = if t < 1.0 / 3.0 {
+        let s = t * 3.0;
+        (0.0, s, 1.0 - s)
+    } else if t < 2.0 / 3.0 {
+        let s = (t - 1.0 / 3.0) * 3.0;
+        (s, 1.0, 0.0)
+    } else {
+        let s = (t - 2.0 / 3.0) * 3.0;
+        (1.0, 1.0 - s, 0.0)
+    };
+
+    Pixel::rgb((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8)
+}
+
 struct AppStruct {
     nodes: Vec<Node>,
     node_start_index: Option<usize>,
@@ -74,6 +421,114 @@ struct AppStruct {
     // this is a simple flag we use to determine when we need re-run our a-star algorithm. we re-run when our obstacles
     // change or our start/end gaol changes. This saves lots of CPU cycles.
     needs_a_star_run: bool,
+
+    // which score, if any, we color the nodes by.
+    display_mode: DisplayMode,
+
+    // how many times bigger than NODE_SIZE we draw each node, and which node sits in the top left corner of the
+    // screen. Together these let us zoom in far enough to read the per node labels.
+    zoom: i32,
+    view_offset_x: i32,
+    view_offset_y: i32,
+
+    // whether the map wraps around at its edges, and the portals between nodes
+    connectivity: Connectivity,
+    // which connected region every node is in. Kept up to date as we edit the map.
+    components: Components,
+
+    // the layer we are currently looking at and editing
+    visible_layer: i32,
+    // the first end of a portal we are in the middle of placing
+    pending_portal: Option<(usize, PortalKind)>,
+
+    // in oriented mode we plan for a tracked vehicle: the search also tracks which way we face and turning costs
+    // extra. In hybrid mode we plan for a car, which also has to finish facing the goal heading.
+    search_mode: SearchMode,
+    start_heading: Heading,
+    goal_heading: Heading,
+    turn_costs: TurnCosts,
+    hybrid_params: HybridParams,
+    // how much work the grid search may do before it settles for the path that gets closest, and how it breaks ties
+    search_limits: SearchLimits,
+    // how many nodes the grid search expands with each way of breaking ties, for the same start and goal. It takes a
+    // search for every policy, so we only work it out once TAB is held, and keep it until the next search.
+    tie_report: Option<Vec<(TieBreaking, usize)>>,
+
+    // the results of the last run of each planner. Planners we aren't using are None.
+    path: Option<Result<Path, PathError>>,
+    oriented_path: Option<Result<OrientedPath, PathError>>,
+    hybrid_path: Option<Result<HybridPath, PathError>>,
+    // in visibility mode, the obstacles of the start layer as polygons with the lines of sight between their corners
+    visibility_graph: Option<VisibilityGraph>,
+    visibility_path: Option<Result<VisibilityPath, PathError>>,
+    // in navmesh mode, the mesh of the start layer and the path through it
+    navmesh: Option<NavMesh>,
+    mesh_path: Option<Result<MeshPath, PathError>>,
+    // in the sampling modes, the roadmap or tree growing on the start layer
+    sampling_params: SamplingParams,
+    sampler: Option<Result<Box<dyn Sampler>, PathError>>,
+
+    // how the grid path is searched. Unless it's searched immediately, `path_request` or `path_handle` is the pending
+    // request for it.
+    solver: Solver,
+    scheduler: PathScheduler,
+    path_request: Option<RequestId>,
+    service: PathService,
+    path_handle: Option<PathHandle>,
+    // set when the obstacles or the connectivity change, so we know to give the service a new snapshot of the map
+    map_edited: bool,
+    // a crowd of extra requests from random nodes to the goal, to see the scheduler and the service share out their
+    // work. The paths that have come back so far are drawn in a dim color. The scheduler forgets its requests when the
+    // map changes, but the service keeps them and works them out again.
+    crowd_requests: Vec<RequestId>,
+    crowd_paths: Vec<Path>,
+    crowd_handles: Vec<PathHandle>,
+
+    // the nodes the grid path has to pass through on its way to the goal, and the route through them. With
+    // `order_waypoints` on we pick the order that makes the route shortest, otherwise we visit them in the order they
+    // were placed.
+    waypoints: Vec<usize>,
+    order_waypoints: bool,
+    route: Option<Result<Route, PathError>>,
+    // with `nearest_goal` on, the waypoints stop being stops along the way and become extra goals instead, and the grid
+    // path goes to whichever of them or the end node is cheapest to reach
+    nearest_goal: bool,
+    nearest_path: Option<Result<MultiPath, PathError>>,
+
+    // with `alternative` set we show one of the cheapest few paths to the goal instead of only the cheapest, counting
+    // from 0 for the cheapest
+    alternative: Option<usize>,
+    alternatives: Option<Result<Vec<Path>, PathError>>,
+
+    // the extra rules the grid path query follows, like the zones we drew, and with it the alternatives, the routes
+    // through the waypoints and the search for the nearest goal. They never change the map itself, and the other
+    // planners don't follow them. `zone_corner` is the first corner of a zone we are in the middle of drawing.
+    constraints: QueryConstraints,
+    zone_corner: Option<usize>,
+
+    // the distance fields shown by the Distance and Flee display modes. Worked out again with every search.
+    distance_field: Vec<i32>,
+    flee_field: Vec<i32>,
+
+    // a navigation graph loaded with F3, and the path through it between the points closest to the start and goal
+    nav_graph: Option<NavGraph>,
+    nav_path: Option<Result<Path, PathError>>,
+
+    // how the last F3 load or F2 save went, shown on the status line until the next one
+    file_report: Option<Result<String, String>>,
+
+    // the agents walking from the start to the goal, spawned with D
+    agents: Vec<Agent>,
+
+    // with `explore` on, an explorer finds its way from the start to the goal seeing only what its sensor picks up.
+    // It starts over whenever the start, goal or sensor change.
+    explore: bool,
+    sensor_params: SensorParams,
+    explorer: Option<Explorer>,
+
+    // the scores of the last search we ran right away, for the heatmaps, labels and hover box. It's handed from one
+    // search to the next, so starting a search doesn't have to clear the scores of every node.
+    scratch: SearchScratch,
 }
 
 
@@ -81,29 +536,33 @@ impl Application for AppStruct {
     fn on_user_create(&mut self) -> Result<(), Error> {
         self.nodes = Vec::new();
 
-        for y in 0..MAP_HEIGHT {
-            for x in 0..MAP_WIDTH {
-                self.nodes.push(Node {
-                    obstacle: false,
-                    visited: false,
-                    global_goal: i32::MAX,
-                    local_goal: i32::MAX,
-                    x,
-                    y,
-                    parent: None,
-                })
+        for z in 0..MAP_LAYERS {
+            for y in 0..MAP_HEIGHT {
+                for x in 0..MAP_WIDTH {
+                    self.nodes.push(Node {
+                        obstacle: false,
+                        terrain: Terrain::Plain,
+                        closed_exits: 0,
+                        walls: 0,
+                        x,
+                        y,
+                        z,
+                    })
+                }
             }
         }
 
         // assign defaults to our start and end locations.
-        self.node_start_index = Some(((MAP_HEIGHT / 2) * MAP_WIDTH + 1) as usize);
-        self.node_end_index = Some(((MAP_HEIGHT / 2) * MAP_WIDTH + MAP_WIDTH - 2) as usize);
+        self.node_start_index = Some(node_index(1, MAP_HEIGHT / 2, 0));
+        self.node_end_index = Some(node_index(MAP_WIDTH - 2, MAP_HEIGHT / 2, 0));
+
+        self.components = Components::new(&self.nodes, &self.connectivity);
 
         Ok(())
     }
 
 
-    fn on_user_update(&mut self, _elapsed_time: f32) -> Result<(), Error> {
+    fn on_user_update(&mut self, elapsed_time: f32) -> Result<(), Error> {
         self.check_mouse_keyboard_events();
 
         // fill our view with black by default. This will set the background color
@@ -111,10 +570,26 @@ impl Application for AppStruct {
         self.render_node_edges();
 
         // we want to render our active path behind the nodes.
+        self.update_path_requests();
+        self.grow_sampler();
+        self.update_agents(elapsed_time);
+        self.update_explorer(elapsed_time);
         self.render_active_path();
+        self.render_downhill_walk();
 
         // render our squares
         self.render_nodes();
+        self.render_portals();
+        self.render_zones();
+        self.render_nav_graph();
+        self.render_agents();
+        self.render_explorer();
+        self.render_headings();
+        self.render_node_labels()?;
+        self.render_hud()?;
+
+        // the hover info sits on top of everything else
+        self.render_hover_info()?;
 
         Ok(())
     }
@@ -124,133 +599,384 @@ impl Application for AppStruct {
     }
 }
 
-fn a_star(start_index: usize, goal_index: usize, nodes: &mut Vec<Node>) -> Option<Vec<usize>> {
-    let mut open_set = std::collections::BinaryHeap::new();
-
-    nodes[start_index].global_goal = 0;
-    nodes[start_index].local_goal = nodes[start_index].heuristic(&nodes[goal_index]);
-    open_set.push(std::cmp::Reverse((nodes[start_index].local_goal, start_index)));
+/**
+Returns every node we can move to from the node at `index` together with what the move costs: the neighbors on the
+grid plus wherever the portals on this node lead.
+ */
+fn get_neighbors(index: usize, nodes: &[Node], connectivity: &Connectivity) -> Vec<(usize, i32)> {
+    // a step to a neighbor on the grid costs what the terrain we step onto costs
+    let mut neighbors: Vec<(usize, i32)> = get_grid_neighbors(index, nodes, connectivity.topology)
+        .into_iter()
+        .filter(|&neighbor_index| can_step(&nodes[index], &nodes[neighbor_index], connectivity.topology))
+        .map(|neighbor_index| (neighbor_index, nodes[neighbor_index].terrain.cost()))
+        .collect();
 
-    // In Rust, the std::collections::BinaryHeap is a max-heap by default, meaning it always pops the largest element
-    // first. However, in many algorithms like A*, you typically need a min-heap, which pops the smallest element first.
-    while let Some(std::cmp::Reverse((_, current_index))) = open_set.pop() {
-        if current_index == goal_index {
-            return Some(construct_path(nodes, goal_index));
+    for (from, to, cost) in connectivity.portal_links() {
+        if from == index && !nodes[to].obstacle {
+            neighbors.push((to, cost));
         }
+    }
 
-        for neighbor_index in get_neighbors(current_index, nodes) {
-            // Check if the neighbor is an obstacle
-            if nodes[neighbor_index].obstacle {
-                continue;  // Skip this neighbor and proceed to the next one
-            }
-
-            let tentative_global_goal = nodes[current_index].global_goal + 1; // Assuming uniform cost for simplicity
+    neighbors
+}
 
-            if tentative_global_goal < nodes[neighbor_index].global_goal {
-                nodes[neighbor_index].parent = Some(current_index);
-                nodes[neighbor_index].global_goal = tentative_global_goal;
-                nodes[neighbor_index].local_goal = tentative_global_goal + nodes[neighbor_index].heuristic(&nodes[goal_index]);
+/**
+The EXIT_ bit for the side of a node a step of (dx, dy) leaves it through, or 0 for a diagonal step or none at all.
+ */
+fn exit_bit(dx: i32, dy: i32) -> u8 {
+    match (dx.signum(), dy.signum()) {
+        (0, -1) => EXIT_NORTH,
+        (0, 1) => EXIT_SOUTH,
+        (-1, 0) => EXIT_WEST,
+        (1, 0) => EXIT_EAST,
+        _ => 0,
+    }
+}
 
-                // A node (neighbor) is added to the open_set if it is not already present in it.
-                // This check is performed by iterating over all nodes currently in the open_set and seeing if any of
-                // them have the same index as the neighbor node (neighbor_index). The check is necessary because
-                // adding the same node multiple times would be inefficient and could lead to incorrect behavior.
-                if !open_set.iter().any(|std::cmp::Reverse((_, idx))| *idx == neighbor_index) {
-                    open_set.push(std::cmp::Reverse((nodes[neighbor_index].local_goal, neighbor_index)));
-                }
-            }
-        }
+/**
+The step (dx, dy) that leaves a node through the side `exit`, the other way around from `exit_bit`.
+ */
+fn exit_offset(exit: u8) -> (i32, i32) {
+    match exit {
+        EXIT_NORTH => (0, -1),
+        EXIT_SOUTH => (0, 1),
+        EXIT_WEST => (-1, 0),
+        EXIT_EAST => (1, 0),
+        _ => (0, 0),
     }
+}
 
-    None
+/**
+Whether the side of `from` that faces its grid neighbor `to` is open and there's no wall between them, so we can step
+from one to the other in that direction. Whether we can step back is up to `to`.
+ */
+fn can_step(from: &Node, to: &Node, topology: Topology) -> bool {
+    let dx = topology.delta(from.x, to.x, MAP_WIDTH);
+    let dy = topology.delta(from.y, to.y, MAP_HEIGHT);
+    (from.closed_exits | from.walls) & exit_bit(dx, dy) == 0 && to.walls & exit_bit(-dx, -dy) == 0
 }
 
+/**
+Puts a wall up on the side `exit` of the node at `index`, or takes it down when `wall` is false. The neighbor on the
+other side of the wall gets it too, if there's one.
+ */
+fn set_wall(index: usize, exit: u8, wall: bool, nodes: &mut [Node], topology: Topology) {
+    let (dx, dy) = exit_offset(exit);
+    let sides = std::iter::once((index, exit))
+        .chain(neighbor_at(&nodes[index], dx, dy, topology).map(|neighbor_index| (neighbor_index, exit_bit(-dx, -dy))));
 
-fn construct_path(nodes: &[Node], mut current_index: usize) -> Vec<usize> {
-    let mut path = vec![current_index];
-    while let Some(parent_index) = nodes[current_index].parent {
-        path.push(parent_index);
-        current_index = parent_index;
+    for (side_index, side) in sides.collect::<Vec<_>>() {
+        if wall {
+            nodes[side_index].walls |= side;
+        } else {
+            nodes[side_index].walls &= !side;
+        }
     }
-    path.reverse();
-    path
 }
 
-fn get_neighbors(index: usize, nodes: &Vec<Node>) -> Vec<usize> {
+/**
+Returns the free nodes directly north, south, west and east of the node at `index` on its own layer that aren't
+behind a wall, whichever way the edges to them go. `get_neighbors` leaves out the ones we can't step to.
+ */
+fn get_grid_neighbors(index: usize, nodes: &[Node], topology: Topology) -> Vec<usize> {
     let mut neighbors = Vec::new();
-    let x = index % (MAP_WIDTH as usize);
-    let y = index / (MAP_WIDTH as usize);
+    let width = MAP_WIDTH as usize;
+    let height = MAP_HEIGHT as usize;
+    let layer_start = index - index % (width * height);
+    let x = index % width;
+    let y = (index % (width * height)) / width;
 
+    // on a toroidal map the nodes along an edge link up with the nodes along the opposite edge
+    let wraps = topology == Topology::Toroidal;
+
+    let mut candidates = Vec::new();
     // north
     if y > 0 {
-        let north_idx = (y - 1) * (MAP_WIDTH as usize) + x;
-        if !nodes[north_idx].obstacle {
-            neighbors.push(north_idx);
-        }
+        candidates.push(((y - 1) * width + x, EXIT_NORTH));
+    } else if wraps {
+        candidates.push(((height - 1) * width + x, EXIT_NORTH));
     }
     // south
-    if y < (MAP_HEIGHT - 1) as usize {
-        let south_idx = (y + 1) * (MAP_WIDTH as usize) + x;
-        if !nodes[south_idx].obstacle {
-            neighbors.push(south_idx);
-        }
+    if y < height - 1 {
+        candidates.push(((y + 1) * width + x, EXIT_SOUTH));
+    } else if wraps {
+        candidates.push((x, EXIT_SOUTH));
     }
     // west
     if x > 0 {
-        let west_idx = y * (MAP_WIDTH as usize) + (x - 1);
-        if !nodes[west_idx].obstacle {
-            neighbors.push(west_idx);
-        }
+        candidates.push((y * width + (x - 1), EXIT_WEST));
+    } else if wraps {
+        candidates.push((y * width + (width - 1), EXIT_WEST));
     }
     // east
-    if x < (MAP_WIDTH - 1) as usize {
-        let east_idx = y * (MAP_WIDTH as usize) + (x + 1);
-        if !nodes[east_idx].obstacle {
-            neighbors.push(east_idx);
+    if x < width - 1 {
+        candidates.push((y * width + (x + 1), EXIT_EAST));
+    } else if wraps {
+        candidates.push((y * width, EXIT_EAST));
+    }
+
+    for (candidate, exit) in candidates {
+        let (dx, dy) = exit_offset(exit);
+        // we look for the wall on both nodes, in case it only went up on one of them while the map didn't wrap
+        let walled = nodes[index].walls & exit != 0 || nodes[layer_start + candidate].walls & exit_bit(-dx, -dy) != 0;
+        if !nodes[layer_start + candidate].obstacle && !walled {
+            neighbors.push(layer_start + candidate);
         }
     }
 
     neighbors
 }
-fn reset_and_clone_nodes(nodes: &[Node]) -> Vec<Node> {
-    nodes.iter().map(|node| Node {
-        x: node.x,
-        y: node.y,
-        obstacle: node.obstacle,
-        local_goal: i32::MAX,
-        global_goal: i32::MAX,
-        parent: None,
-        visited: node.visited,
-    }).collect()
-}
 
 
 impl AppStruct {
 
+    /**
+    The size in pixels of a single node at the current zoom level.
+     */
+    fn node_size(&self) -> i32 {
+        NODE_SIZE * self.zoom
+    }
+
+    /**
+    The top left pixel of the square we fill for the node at (x, y), taking the zoom and view offset into account.
+     */
+    fn node_screen_pos(&self, x: i32, y: i32) -> (i32, i32) {
+        ((x - self.view_offset_x) * self.node_size() + NODE_BORDER,
+         (y - self.view_offset_y) * self.node_size() + NODE_BORDER)
+    }
+
+    /**
+    The center pixel of the node at (x, y). Edges and the path are drawn between node centers.
+     */
+    fn node_center(&self, x: i32, y: i32) -> (i32, i32) {
+        let (screen_x, screen_y) = self.node_screen_pos(x, y);
+        let inner_size = self.node_size() - NODE_BORDER;
+        (screen_x + inner_size / 2, screen_y + inner_size / 2)
+    }
+
+    /**
+    The center pixel of the node one step from (x, y) towards the node at (to_x, to_y). For most edges that's simply
+    the center of the other node, but an edge that wraps around the map ends just past the edge of the grid instead
+    of being drawn all the way across it.
+     */
+    fn edge_end(&self, x: i32, y: i32, to_x: i32, to_y: i32) -> (i32, i32) {
+        let topology = self.connectivity.topology;
+        self.node_center(x + topology.delta(x, to_x, MAP_WIDTH), y + topology.delta(y, to_y, MAP_HEIGHT))
+    }
+
+    /**
+    The EXIT_ bit for the side of the node at `index` that the mouse is closest to.
+     */
+    fn exit_under_mouse(&self, index: usize) -> u8 {
+        let node = &self.nodes[index];
+        let (center_x, center_y) = self.node_center(node.x, node.y);
+        let (dx, dy) = (get_mouse_x() - center_x, get_mouse_y() - center_y);
+        if dx.abs() > dy.abs() {
+            exit_bit(dx, 0)
+        } else {
+            // right on the center we pick north
+            exit_bit(0, if dy > 0 { 1 } else { -1 })
+        }
+    }
+
+    /**
+    The side of the node under the mouse when the mouse is on the gap along its north or west side, rather than on the
+    node itself. The gaps to the south and east belong to the next nodes over. The corners where gaps cross count as
+    the node.
+     */
+    fn border_under_mouse(&self) -> Option<u8> {
+        let x = get_mouse_x().rem_euclid(self.node_size());
+        let y = get_mouse_y().rem_euclid(self.node_size());
+        match (x < NODE_BORDER, y < NODE_BORDER) {
+            (true, false) => Some(EXIT_WEST),
+            (false, true) => Some(EXIT_NORTH),
+            _ => None,
+        }
+    }
+
+    /**
+    Returns the index of the node the mouse is currently over, if any.
+     */
+    fn node_under_mouse(&self) -> Option<usize> {
+        let x = get_mouse_x() / self.node_size() + self.view_offset_x;
+        let y = get_mouse_y() / self.node_size() + self.view_offset_y;
+
+        if (0..MAP_WIDTH).contains(&x) && (0..MAP_HEIGHT).contains(&y) {
+            Some(node_index(x, y, self.visible_layer))
+        } else {
+            None
+        }
+    }
+
+    /**
+    Returns the heuristic from the node at `index` to the end node, or None if there is no end node or the node is an
+    obstacle and so could never be part of a path.
+     */
+    fn node_heuristic(&self, index: usize) -> Option<i32> {
+        let end_index = self.node_end_index?;
+        if self.nodes[index].obstacle {
+            return None;
+        }
+        let heuristic = self.nodes[index].heuristic(&self.nodes[end_index], &self.nodes, &self.connectivity);
+        if heuristic == i32::MAX { None } else { Some(heuristic) }
+    }
+
+    /**
+    Returns the score of the node at `index` for the current display mode. Nodes the last search never reached have
+    no g or f score, so they return None.
+     */
+    fn node_score(&self, index: usize) -> Option<i32> {
+        let scores = self.scratch.get(index);
+        match self.display_mode {
+            DisplayMode::Default | DisplayMode::Components => None,
+            DisplayMode::GCost => if scores.global_goal == i32::MAX { None } else { Some(scores.global_goal) },
+            DisplayMode::HCost => self.node_heuristic(index),
+            DisplayMode::FCost => if scores.local_goal == i32::MAX { None } else { Some(scores.local_goal) },
+            DisplayMode::Distance | DisplayMode::Flee => self.displayed_field()
+                .map(|field| field[index])
+                .filter(|&value| value != i32::MAX),
+        }
+    }
+
+    /**
+    The distance field the current display mode shows, if it shows one.
+     */
+    fn displayed_field(&self) -> Option<&[i32]> {
+        let field = match self.display_mode {
+            DisplayMode::Distance => &self.distance_field,
+            DisplayMode::Flee => &self.flee_field,
+            _ => return None,
+        };
+        // the fields are empty until the first search
+        if field.is_empty() { None } else { Some(field) }
+    }
+
+    /**
+    Works out the distance fields to and away from the goal. The flee field also pulls towards the waypoints, to
+    show how fields combine: whoever follows it wants to get away from the goal and to a waypoint at the same time.
+     */
+    fn update_distance_fields(&mut self, goal_index: usize) {
+        self.distance_field = distance_field(&[goal_index], &self.nodes, &self.connectivity);
+        let flee = flee_field(&self.distance_field, FLEE_SCALE, &self.nodes, &self.connectivity);
+
+        self.flee_field = if self.waypoints.is_empty() {
+            flee
+        } else {
+            let to_waypoints = distance_field(&self.waypoints, &self.nodes, &self.connectivity);
+            combine_fields(&[(&flee, 1.0), (&to_waypoints, 1.0)])
+        };
+    }
+
+    /**
+    When a distance field is on display, draws the walk from the start that always steps to the lowest neighbor.
+     */
+    fn render_downhill_walk(&self) {
+        let (field, start_index) = match (self.displayed_field(), self.node_start_index) {
+            (Some(field), Some(start_index)) => (field, start_index),
+            _ => return,
+        };
+
+        let mut walk = vec![start_index];
+        // every step goes strictly downhill so the walk can't loop, but we cap it anyway
+        while let Some(next_index) = downhill(walk[walk.len() - 1], field, &self.nodes, &self.connectivity) {
+            walk.push(next_index);
+            if walk.len() > self.nodes.len() {
+                break;
+            }
+        }
+
+        self.render_grid_path(&Path { nodes: walk, cost: 0, partial: None }, CYAN);
+    }
+
+    /**
+    Returns the smallest and largest score on the visible layer for the current display mode, so the heatmap gradient
+    always spans the full range of values.
+     */
+    fn score_range(&self) -> Option<(i32, i32)> {
+        let scores = (0..self.nodes.len())
+            .filter(|&index| self.nodes[index].z == self.visible_layer)
+            .filter_map(|index| self.node_score(index));
+        scores.fold(None, |range, score| match range {
+            None => Some((score, score)),
+            Some((min, max)) => Some((min.min(score), max.max(score))),
+        })
+    }
+
     /**
     Renders the node edges that connect the nodes together.
      */
     fn render_node_edges(&mut self) {
         for y in 0..MAP_HEIGHT {
             for x in 0..MAP_WIDTH {
-                let index = (y * MAP_WIDTH + x) as usize;
+                let index = node_index(x, y, self.visible_layer);
 
-                let neighbors = get_neighbors(index, &self.nodes);
+                // portals are drawn separately by render_portals
+                let neighbors = get_grid_neighbors(index, &self.nodes, self.connectivity.topology);
                 for neighbor_index in neighbors {
+                    let node = &self.nodes[index];
                     let neighbor = &self.nodes[neighbor_index];
+                    let forward = can_step(node, neighbor, self.connectivity.topology);
+                    let backward = can_step(neighbor, node, self.connectivity.topology);
+                    // an edge closed both ways isn't an edge at all
+                    if !forward && !backward {
+                        continue;
+                    }
+
+                    let (from_x, from_y) = self.node_center(x, y);
+                    let (to_x, to_y) = self.edge_end(x, y, neighbor.x, neighbor.y);
+                    draw_line(from_x, from_y, to_x, to_y, VERY_DARK_BLUE);
+
+                    // a one-way edge gets an arrowhead halfway along, drawn from the end it leads away from
+                    if forward && !backward {
+                        self.render_arrowhead((from_x + to_x) / 2, (from_y + to_y) / 2,
+                                              (to_x - from_x).signum(), (to_y - from_y).signum());
+                    }
+                }
 
-                    // I'm not sure why we have to offset this by - 4... my original theory was I wasn't taking
-                    // the border into account, but 4 is not a factor of the current border value, so I'm at a loss, but
-                    // this seems to work.
-                    draw_line(x * NODE_SIZE + NODE_SIZE - 4 / 2,
-                              y * NODE_SIZE + NODE_SIZE - 4 / 2,
-                              neighbor.x * NODE_SIZE + NODE_SIZE - 4 / 2,
-                              neighbor.y * NODE_SIZE + NODE_SIZE - 4 / 2, VERY_DARK_BLUE)
+                // walls go on top of the edges, in the gap on their side of the node
+                for exit in [EXIT_NORTH, EXIT_SOUTH, EXIT_WEST, EXIT_EAST] {
+                    if self.nodes[index].walls & exit != 0 {
+                        self.render_wall(x, y, exit);
+                    }
                 }
             }
         }
     }
 
+    /**
+    Draws the wall on side `exit` of the node at (x, y) as a thick line down the middle of the gap on that side. It
+    runs a little into the gaps at both ends so walls meet up at the corners.
+     */
+    fn render_wall(&self, x: i32, y: i32, exit: u8) {
+        let (screen_x, screen_y) = self.node_screen_pos(x, y);
+        let inner_size = self.node_size() - NODE_BORDER;
+        let thickness = NODE_BORDER / 3;
+        let inset = (NODE_BORDER - thickness) / 2;
+        let length = inner_size + NODE_BORDER + thickness;
+        let (left, top) = (screen_x - NODE_BORDER + inset, screen_y - NODE_BORDER + inset);
+
+        match exit {
+            EXIT_NORTH => fill_rect(left, top, length, thickness, GREY),
+            EXIT_SOUTH => fill_rect(left, screen_y + inner_size + inset, length, thickness, GREY),
+            EXIT_WEST => fill_rect(left, top, thickness, length, GREY),
+            _ => fill_rect(screen_x + inner_size + inset, top, thickness, length, GREY),
+        }
+    }
+
+    /**
+    Draws a small arrowhead with its tip just past (x, y), pointing along (dx, dy), which is one of the four
+    straight directions.
+     */
+    fn render_arrowhead(&self, x: i32, y: i32, dx: i32, dy: i32) {
+        let size = self.zoom + 1;
+        let (tip_x, tip_y) = (x + dx * size, y + dy * size);
+        let (back_x, back_y) = (x - dx * size, y - dy * size);
+        // the back corners sit either side of the edge
+        fill_triangle(tip_x, tip_y,
+                      back_x - dy * size, back_y + dx * size,
+                      back_x + dy * size, back_y - dx * size,
+                      DARK_YELLOW);
+    }
 
     /**
     Renders the a* path
@@ -259,88 +985,1065 @@ impl AppStruct {
         if let Some(start_idx, ) = self.node_start_index {
             if let Some(goal_idx) = self.node_end_index {
                 if self.needs_a_star_run {
-                    // reset our node values
-                    let nodes = reset_and_clone_nodes(&self.nodes);
-                    self.nodes = nodes;
-                    a_star(start_idx, goal_idx, &mut self.nodes);
+                    // forget the scores of the last search
+                    self.scratch.begin(0);
+
+                    // whatever changed might have been the map, and a search is only any good on the map it started
+                    // on. The service finds out about a new map from its snapshot instead.
+                    self.scheduler.clear();
+                    self.crowd_requests.clear();
+                    self.crowd_paths.clear();
+                    if self.map_edited {
+                        self.service.publish(&self.nodes, &self.connectivity, &self.components);
+                        self.map_edited = false;
+                    }
+
+                    // unless we search right away the path shows up once the scheduler or the workers are done with
+                    // it. The node scores stay empty then, because those searches keep them to themselves.
+                    self.path_request = None;
+                    self.path_handle = None;
+                    match self.solver {
+                        Solver::Immediate => {
+                            self.path = Some(self.a_star(start_idx, goal_idx));
+                        }
+                        Solver::TimeSliced => {
+                            self.path = None;
+                            let search = SlicedSearch::new(start_idx, goal_idx, &self.nodes, &self.connectivity,
+                                                           &self.components, &self.search_limits, &self.constraints);
+                            self.path_request = Some(self.scheduler.request(search));
+                        }
+                        Solver::Threaded => {
+                            self.path = None;
+                            self.path_handle = Some(self.service.request(start_idx, goal_idx, &self.search_limits,
+                                                                         &self.constraints));
+                        }
+                    }
+
+                    self.update_distance_fields(goal_idx);
+                    self.tie_report = None;
+
+                    self.alternatives = match (self.search_mode, self.alternative) {
+                        (SearchMode::Grid, Some(_)) if self.waypoints.is_empty() => {
+                            Some(k_shortest_paths(start_idx, goal_idx, ALTERNATIVE_COLORS.len(), &self.nodes,
+                                                  &self.connectivity, &self.components, &self.constraints))
+                        }
+                        _ => None,
+                    };
+
+                    let with_waypoints = self.search_mode == SearchMode::Grid && !self.waypoints.is_empty();
+                    self.route = if with_waypoints && !self.nearest_goal {
+                        Some(self.find_route(start_idx, goal_idx))
+                    } else {
+                        None
+                    };
+                    self.nearest_path = if with_waypoints && self.nearest_goal {
+                        let goals: Vec<usize> =
+                            std::iter::once(goal_idx).chain(self.waypoints.iter().copied()).collect();
+                        Some(multi_a_star(&[start_idx], &goals, &self.nodes, &self.connectivity, &self.components,
+                                          &self.search_limits, &self.constraints))
+                    } else {
+                        None
+                    };
+
+                    self.nav_path = self.find_nav_path(start_idx, goal_idx);
+
+                    self.oriented_path = if self.search_mode == SearchMode::Oriented {
+                        Some(oriented_a_star(start_idx, Some(self.start_heading), goal_idx, &self.nodes,
+                                             &self.connectivity, &self.components, &self.turn_costs))
+                    } else {
+                        None
+                    };
+
+                    self.hybrid_path = if self.search_mode == SearchMode::Hybrid {
+                        let (start, goal) = (&self.nodes[start_idx], &self.nodes[goal_idx]);
+                        if start.z == goal.z {
+                            let start_pose = Pose::new(start.x as f32, start.y as f32, self.start_heading.angle());
+                            let goal_pose = Pose::new(goal.x as f32, goal.y as f32, self.goal_heading.angle());
+                            Some(hybrid_a_star(start_pose, goal_pose, start.z, &self.nodes, &self.hybrid_params))
+                        } else {
+                            // the car can't take the stairs
+                            Some(Err(PathError::Unreachable))
+                        }
+                    } else {
+                        None
+                    };
+
+                    self.visibility_graph = None;
+                    self.visibility_path = None;
+                    if self.search_mode == SearchMode::Visibility {
+                        let (start, goal) = (&self.nodes[start_idx], &self.nodes[goal_idx]);
+                        let graph = VisibilityGraph::build(start.z, &self.nodes);
+                        self.visibility_path = Some(if start.z == goal.z {
+                            graph.find_path((start.x as f32, start.y as f32), (goal.x as f32, goal.y as f32),
+                                            &self.search_limits)
+                        } else {
+                            // the polygons only cover one layer, so there's no way up or down
+                            Err(PathError::Unreachable)
+                        });
+                        self.visibility_graph = Some(graph);
+                    }
+
+                    self.navmesh = None;
+                    self.mesh_path = None;
+                    if self.search_mode == SearchMode::NavMesh {
+                        let (start, goal) = (&self.nodes[start_idx], &self.nodes[goal_idx]);
+                        let mesh = NavMesh::build(start.z, &self.nodes);
+                        self.mesh_path = Some(if start.z == goal.z {
+                            mesh.find_path((start.x as f32, start.y as f32), (goal.x as f32, goal.y as f32),
+                                           &self.search_limits)
+                        } else {
+                            Err(PathError::Unreachable)
+                        });
+                        self.navmesh = Some(mesh);
+                    }
+
+                    self.sampler = None;
+                    if matches!(self.search_mode, SearchMode::Prm | SearchMode::RrtStar) {
+                        let (start, goal) = (&self.nodes[start_idx], &self.nodes[goal_idx]);
+                        let (start_pos, goal_pos) = ((start.x as f32, start.y as f32), (goal.x as f32, goal.y as f32));
+                        let obstacles = ObstacleMap::new(start.z, &self.nodes);
+                        self.sampler = Some(if start.z != goal.z {
+                            Err(PathError::Unreachable)
+                        } else if self.search_mode == SearchMode::Prm {
+                            Prm::new(start_pos, goal_pos, obstacles, self.sampling_params)
+                                .map(|prm| Box::new(prm) as Box<dyn Sampler>)
+                        } else {
+                            RrtStar::new(start_pos, goal_pos, obstacles, self.sampling_params)
+                                .map(|rrt| Box::new(rrt) as Box<dyn Sampler>)
+                        });
+                    }
                     self.needs_a_star_run = false
                 }
+
+                if get_key(TAB).held && self.tie_report.is_none() {
+                    self.tie_report = Some(self.tie_breaking_report(start_idx, goal_idx));
+                }
             }
         }
 
-        if let Some(mut node_index) = self.node_end_index {
-            while let Some(parent_index) = self.nodes[node_index].parent {
-                let (node_x, node_y) = (self.nodes[node_index].x, self.nodes[node_index].y);
-                let (parent_x, parent_y) = (self.nodes[parent_index].x, self.nodes[parent_index].y);
-
-                draw_line(node_x * NODE_SIZE + NODE_SIZE - 4 / 2,
-                          node_y * NODE_SIZE + NODE_SIZE - 4  / 2,
-                          parent_x * NODE_SIZE + NODE_SIZE - 4 / 2,
-                          parent_y * NODE_SIZE + NODE_SIZE - 4 / 2,
-                          YELLOW);
+        // the other planners have their own kind of path to draw
+        match self.search_mode {
+            SearchMode::Oriented => {
+                self.render_oriented_path();
+                return;
+            }
+            SearchMode::Hybrid => {
+                self.render_hybrid_path();
+                return;
+            }
+            SearchMode::Visibility => {
+                self.render_visibility();
+                return;
+            }
+            SearchMode::NavMesh => {
+                // the grid path goes underneath, dimmed, so we can see how much shorter the mesh path is
+                if let Some(Ok(path)) = &self.path {
+                    self.render_grid_path(path, DARK_YELLOW);
+                }
+                self.render_navmesh();
+                return;
+            }
+            SearchMode::Prm | SearchMode::RrtStar => {
+                if let Some(Ok(path)) = &self.path {
+                    self.render_grid_path(path, DARK_YELLOW);
+                }
+                self.render_sampler();
+                return;
+            }
+            SearchMode::Grid => {}
+        }
 
-                node_index = parent_index;
+        for path in &self.crowd_paths {
+            self.render_grid_path(path, VERY_DARK_YELLOW);
+        }
+        for handle in &self.crowd_handles {
+            if let Some(Ok(path)) = handle.result() {
+                self.render_grid_path(path, VERY_DARK_YELLOW);
             }
         }
-    }
 
-    fn check_mouse_keyboard_events(&mut self) {
-        let selected_node_x = get_mouse_x() / NODE_SIZE;
-        let selected_node_y = get_mouse_y() / NODE_SIZE;
+        // when we head for the nearest goal we draw the path to it, and outline the start and goal it joins
+        if let Some(nearest) = &self.nearest_path {
+            if let Ok(nearest) = nearest {
+                let color = if nearest.path.partial.is_some() { DARK_YELLOW } else { YELLOW };
+                self.render_grid_path(&nearest.path, color);
 
-        // check what square we are clicking if any and update our node that's being clicked to
-        // toggle the obstacle flag.
-        if get_mouse(0).released {
-            if selected_node_x >= 0 && selected_node_x < MAP_WIDTH {
-                if selected_node_y >= 0 && selected_node_y < MAP_HEIGHT {
-                    let index: usize = (selected_node_y * MAP_WIDTH + selected_node_x) as usize;
-                    if index < self.nodes.len() { // check that our index is not out of bounds.
-                        if get_key(SHIFT).held { // if we hold the shift key while clicking... we should set the end node
-                            self.node_end_index = Some(index)
-                        } else if get_key(CTRL).held { // if we hold the control key while clicking we should set the start node
-                            self.node_start_index = Some(index)
-                        } else { // otherwise just toggle an obstacle node.
-                            self.nodes[index].obstacle = !self.nodes[index].obstacle;
-                        }
-                        self.needs_a_star_run = true
+                let inner_size = self.node_size() - NODE_BORDER;
+                for index in std::iter::once(nearest.start_index).chain(nearest.goal_index) {
+                    let node = &self.nodes[index];
+                    if node.z == self.visible_layer {
+                        let (screen_x, screen_y) = self.node_screen_pos(node.x, node.y);
+                        draw_rect(screen_x - 1, screen_y - 1, inner_size + 1, inner_size + 1, YELLOW);
                     }
                 }
             }
+            return;
+        }
+
+        // with waypoints we draw the route through them instead of the plain path, each leg in its own color
+        if let Some(route) = &self.route {
+            if let Ok(route) = route {
+                for (leg, path) in route.legs.iter().enumerate() {
+                    self.render_grid_path(path, LEG_COLORS[leg % LEG_COLORS.len()]);
+                }
+            }
+            return;
+        }
+
+        if let (Some(alternatives), Some(alternative)) = (&self.alternatives, self.alternative) {
+            if let Some(path) = alternatives.as_ref().ok().and_then(|paths| paths.get(alternative)) {
+                self.render_grid_path(path, ALTERNATIVE_COLORS[alternative]);
+            }
+            return;
+        }
+
+        if let Some(Ok(path)) = &self.path {
+            // a partial path only gets us as close as possible, so we draw it in a darker color
+            let color = if path.partial.is_some() { DARK_YELLOW } else { YELLOW };
+            self.render_grid_path(path, color);
         }
     }
 
     /**
-    Renders the nodes aka the squares.
+    Runs a whole `SlicedSearch` in one go with the constraints of the query, and keeps its scores in `scratch` so the
+    heatmaps and labels can show them.
      */
-    fn render_nodes(&mut self) {
-        for y in 0..MAP_HEIGHT {
-            for x in 0..MAP_WIDTH {
-                let index: usize = (y * MAP_WIDTH + x) as usize; // get the index of the current square being rendered.
-                if index < self.nodes.len() { // check that our index is not out of bounds.
-                    fill_rect(x * NODE_SIZE + NODE_BORDER,
-                              y * NODE_SIZE + NODE_BORDER,
-                              NODE_SIZE - NODE_BORDER,
-                              NODE_SIZE - NODE_BORDER,
-                              // we change the color of our square if it's obstacle value is true
-                              if self.nodes[index].obstacle { GREY } else { DARK_BLUE });
+    fn a_star(&mut self, start_index: usize, goal_index: usize) -> Result<Path, PathError> {
+        let scratch = std::mem::take(&mut self.scratch);
+        let space = GridSpace { nodes: &self.nodes, connectivity: &self.connectivity, constraints: &self.constraints };
+        let mut search = SlicedSearch::reusing(&[start_index], &[goal_index], &space, &self.components,
+                                               &self.search_limits, scratch);
+        let result = search.finish(&self.nodes, &self.connectivity);
+        self.scratch = search.into_scratch();
+        result
+    }
 
+    /**
+    Runs the grid search from `start_index` to `goal_index` once with every way of breaking ties, and counts how many
+    nodes each of them expands.
+     */
+    fn tie_breaking_report(&self, start_index: usize, goal_index: usize) -> Vec<(TieBreaking, usize)> {
+        TieBreaking::ALL.iter()
+            .map(|tie_breaking| {
+                let tie_breaking = tie_breaking.with_seed(self.sampling_params.seed);
+                let limits = SearchLimits { tie_breaking, ..self.search_limits };
+                let mut search = SlicedSearch::new(start_index, goal_index, &self.nodes, &self.connectivity,
+                                                   &self.components, &limits, &self.constraints);
+                // we only want the count, whatever the search finds
+                let _ = search.finish(&self.nodes, &self.connectivity);
+                (tie_breaking, search.expansions())
+            })
+            .collect()
+    }
 
-                    if self.nodes[index].visited {
-                        fill_rect(x * NODE_SIZE + NODE_BORDER,
-                                  y * NODE_SIZE + NODE_BORDER,
-                                  NODE_SIZE - NODE_BORDER,
-                                  NODE_SIZE - NODE_BORDER,
-                                  // we change the color of our square if it's obstacle value is true
-                                  if self.nodes[index].obstacle { GREY } else { BLUE });
-                    }
+    /**
+    Finds the route from the start through the waypoints to the goal.
+     */
+    fn find_route(&self, start_index: usize, goal_index: usize) -> Result<Route, PathError> {
+        let waypoints = if self.order_waypoints {
+            visiting_order(start_index, &self.waypoints, goal_index, &self.nodes, &self.connectivity, &self.components,
+                           &self.constraints)?
+        } else {
+            self.waypoints.clone()
+        };
+
+        let stops: Vec<usize> = std::iter::once(start_index)
+            .chain(waypoints)
+            .chain(std::iter::once(goal_index))
+            .collect();
+        route_through(&stops, &self.nodes, &self.connectivity, &self.components, &self.search_limits,
+                      &self.constraints)
+    }
+
+    /**
+    Draws a path from the grid search in `color`.
+     */
+    fn render_grid_path(&self, path: &Path, color: Pixel) {
+        for step in path.nodes.windows(2) {
+            let (parent_index, node_index) = (step[0], step[1]);
+            let node = &self.nodes[node_index];
+            let parent = &self.nodes[parent_index];
+
+            // parts of the path on other layers, and the jumps through portals between layers, aren't drawn here.
+            // The portal outlines already show where the path changes layer.
+            if node.z == self.visible_layer && parent.z == self.visible_layer {
+                let grid_step = get_grid_neighbors(parent_index, &self.nodes, self.connectivity.topology)
+                    .contains(&node_index);
+
+                if grid_step {
+                    // we draw the step from both ends. Normally the two lines overlap, but when the step wraps
+                    // around the map this gives us a stub leaving one edge of the grid and another one entering on
+                    // the opposite edge.
+                    let (node_x, node_y) = self.node_center(node.x, node.y);
+                    let (parent_x, parent_y) = self.edge_end(node.x, node.y, parent.x, parent.y);
+                    draw_line(node_x, node_y, parent_x, parent_y, color);
+
+                    let (parent_x, parent_y) = self.node_center(parent.x, parent.y);
+                    let (node_x, node_y) = self.edge_end(parent.x, parent.y, node.x, node.y);
+                    draw_line(parent_x, parent_y, node_x, node_y, color);
+                } else {
+                    // a jump through a teleporter on this layer
+                    let (node_x, node_y) = self.node_center(node.x, node.y);
+                    let (parent_x, parent_y) = self.node_center(parent.x, parent.y);
+                    draw_line_with_pattern(node_x, node_y, parent_x, parent_y, color, 0xF0F0F0F0);
+                }
+            }
+        }
+    }
+
+    /**
+    Renders the path from the oriented search. Unlike the regular path this one can move diagonally.
+     */
+    fn render_oriented_path(&self) {
+        let steps = match &self.oriented_path {
+            Some(Ok(path)) => &path.steps,
+            _ => return,
+        };
+
+        for step in steps.windows(2) {
+            let (from, to) = (&self.nodes[step[0].0], &self.nodes[step[1].0]);
+            if from.z != self.visible_layer || to.z != self.visible_layer {
+                continue;
+            }
+
+            let topology = self.connectivity.topology;
+            let grid_step = topology.delta(from.x, to.x, MAP_WIDTH).abs() <= 1
+                && topology.delta(from.y, to.y, MAP_HEIGHT).abs() <= 1;
+
+            if grid_step {
+                // the same two stubs as the regular path so moves that wrap around the map look right
+                let (from_x, from_y) = self.node_center(from.x, from.y);
+                let (to_x, to_y) = self.edge_end(from.x, from.y, to.x, to.y);
+                draw_line(from_x, from_y, to_x, to_y, YELLOW);
+
+                let (to_x, to_y) = self.node_center(to.x, to.y);
+                let (from_x, from_y) = self.edge_end(to.x, to.y, from.x, from.y);
+                draw_line(to_x, to_y, from_x, from_y, YELLOW);
+            } else {
+                let (from_x, from_y) = self.node_center(from.x, from.y);
+                let (to_x, to_y) = self.node_center(to.x, to.y);
+                draw_line_with_pattern(from_x, from_y, to_x, to_y, YELLOW, 0xF0F0F0F0);
+            }
+        }
+    }
+
+    /**
+    Converts a continuous position in nodes, like a `Pose`, to a pixel on screen.
+     */
+    fn world_to_screen(&self, x: f32, y: f32) -> (i32, i32) {
+        let (origin_x, origin_y) = self.node_center(0, 0);
+        (origin_x + (x * self.node_size() as f32).round() as i32,
+         origin_y + (y * self.node_size() as f32).round() as i32)
+    }
+
+    /**
+    Draws a straight line between two continuous positions in nodes.
+     */
+    fn draw_world_line(&self, from: (f32, f32), to: (f32, f32), color: Pixel) {
+        let (from_x, from_y) = self.world_to_screen(from.0, from.1);
+        let (to_x, to_y) = self.world_to_screen(to.0, to.1);
+        draw_line(from_x, from_y, to_x, to_y, color);
+    }
+
+    /**
+    Draws the obstacles of the visibility graph as white outlines, the lines of sight between their corners in dark
+    grey and the shortest path around them in yellow.
+     */
+    fn render_visibility(&self) {
+        let graph = match &self.visibility_graph {
+            Some(graph) if graph.layer == self.visible_layer => graph,
+            _ => return,
+        };
+
+        for (from, edges) in graph.edges.iter().enumerate() {
+            for &(to, _) in edges.iter().filter(|&&(to, _)| to > from) {
+                self.draw_world_line(graph.corners[from], graph.corners[to], DARK_GREY);
+            }
+        }
+        for polygon in &graph.polygons {
+            for corner in 0..polygon.len() {
+                self.draw_world_line(polygon[corner], polygon[(corner + 1) % polygon.len()], WHITE);
+            }
+        }
+        if let Some(Ok(path)) = &self.visibility_path {
+            for step in path.points.windows(2) {
+                self.draw_world_line(step[0], step[1], YELLOW);
+            }
+        }
+    }
+
+    /**
+    Draws the rectangles of the navmesh in dark cyan, the portals between them in cyan and the path through them in
+    yellow.
+     */
+    fn render_navmesh(&self) {
+        let mesh = match &self.navmesh {
+            Some(mesh) if mesh.layer == self.visible_layer => mesh,
+            _ => return,
+        };
+
+        for region in &mesh.regions {
+            let corners = region.outline();
+            for corner in 0..corners.len() {
+                self.draw_world_line(corners[corner], corners[(corner + 1) % corners.len()], DARK_CYAN);
+            }
+        }
+        for portal in &mesh.portals {
+            self.draw_world_line(portal.ends.0, portal.ends.1, CYAN);
+        }
+        if let Some(Ok(path)) = &self.mesh_path {
+            for step in path.points.windows(2) {
+                self.draw_world_line(step[0], step[1], YELLOW);
+            }
+        }
+    }
+
+    /**
+    Draws the links of the roadmap or the branches of the tree in dark green and the path through them in yellow.
+     */
+    fn render_sampler(&self) {
+        let sampler = match &self.sampler {
+            Some(Ok(sampler)) => sampler,
+            _ => return,
+        };
+        if self.node_start_index.map(|index| self.nodes[index].z) != Some(self.visible_layer) {
+            return;
+        }
+        for (from, to) in sampler.edges() {
+            self.draw_world_line(from, to, DARK_GREEN);
+        }
+        if let Some(path) = sampler.path() {
+            for step in path.points.windows(2) {
+                self.draw_world_line(step[0], step[1], YELLOW);
+            }
+        }
+    }
+
+    /**
+    Renders the trajectory from the hybrid search and the outline of the car at regular intervals along it. The
+    stretches driven in reverse are drawn in red.
+     */
+    fn render_hybrid_path(&self) {
+        let path = match &self.hybrid_path {
+            Some(Ok(path)) => path,
+            _ => return,
+        };
+        if self.node_start_index.map(|index| self.nodes[index].z) != Some(self.visible_layer) {
+            return;
+        }
+
+        for (step, poses) in path.poses.windows(2).enumerate() {
+            let color = if path.reversing[step + 1] { RED } else { YELLOW };
+            self.draw_world_line((poses[0].x, poses[0].y), (poses[1].x, poses[1].y), color);
+        }
+
+        // roughly one footprint per node driven, plus one at the very end
+        let footprints = path.poses.iter().step_by(4).chain(path.poses.last());
+        for pose in footprints {
+            let corners = pose.footprint(self.hybrid_params.car_length, self.hybrid_params.car_width)
+                .map(|(x, y)| self.world_to_screen(x, y));
+            for corner in 0..corners.len() {
+                let (from_x, from_y) = corners[corner];
+                let (to_x, to_y) = corners[(corner + 1) % corners.len()];
+                draw_line(from_x, from_y, to_x, to_y, GREEN);
+            }
+        }
+    }
+
+    /**
+    Draws a short tick out of a node's center in the direction the vehicle faces there. In oriented mode we draw one
+    for the start heading and one for every step of the path, in hybrid mode one for the start and goal headings.
+     */
+    fn render_headings(&self) {
+        if !matches!(self.search_mode, SearchMode::Oriented | SearchMode::Hybrid) {
+            return;
+        }
+
+        let tick_length = (self.node_size() - NODE_BORDER) / 2 + 2;
+        let draw_tick = |index: usize, heading: Heading| {
+            let node = &self.nodes[index];
+            if node.z == self.visible_layer {
+                let (center_x, center_y) = self.node_center(node.x, node.y);
+                let (dx, dy) = heading.offset();
+                draw_line(center_x, center_y, center_x + dx * tick_length, center_y + dy * tick_length, WHITE);
+            }
+        };
+
+        if let Some(start_index) = self.node_start_index {
+            draw_tick(start_index, self.start_heading);
+        }
+        if let Some(Ok(path)) = &self.oriented_path {
+            for &(index, heading) in path.steps.iter().skip(1) {
+                draw_tick(index, heading);
+            }
+        }
+        if let (SearchMode::Hybrid, Some(end_index)) = (self.search_mode, self.node_end_index) {
+            draw_tick(end_index, self.goal_heading);
+        }
+    }
+
+    fn check_mouse_keyboard_events(&mut self) {
+        // check what square we are clicking if any and update our node that's being clicked to
+        // toggle the obstacle flag.
+        if get_mouse(0).released {
+            if let Some(index) = self.node_under_mouse() {
+                if get_key(SHIFT).held { // if we hold the shift key while clicking... we should set the end node
+                    self.node_end_index = Some(index)
+                } else if get_key(CTRL).held { // if we hold the control key while clicking we should set the start node
+                    self.node_start_index = Some(index)
+                } else if get_key(W).held { // holding W adds a waypoint, or removes the one that's already there
+                    match self.waypoints.iter().position(|&waypoint| waypoint == index) {
+                        Some(position) => {
+                            self.waypoints.remove(position);
+                        }
+                        None => self.waypoints.push(index),
+                    }
+                } else if let Some(exit) = self.border_under_mouse() { // clicking the gap beside a node toggles a wall
+                    let wall = self.nodes[index].walls & exit == 0;
+                    set_wall(index, exit, wall, &mut self.nodes, self.connectivity.topology);
+                    self.side_changed(index, exit);
+                    self.map_edited = true
+                } else { // otherwise just toggle an obstacle node.
+                    self.nodes[index].obstacle = !self.nodes[index].obstacle;
+                    if self.nodes[index].obstacle {
+                        self.components.obstacle_added(index, &self.nodes, &self.connectivity);
+                    } else {
+                        self.components.obstacle_removed(index, &self.nodes, &self.connectivity);
+                    }
+                    self.map_edited = true
+                }
+                self.needs_a_star_run = true
+            }
+        }
+
+        // the number keys switch what we color the nodes by.
+        if get_key(K1).pressed {
+            self.display_mode = DisplayMode::Default
+        } else if get_key(K2).pressed {
+            self.display_mode = DisplayMode::GCost
+        } else if get_key(K3).pressed {
+            self.display_mode = DisplayMode::HCost
+        } else if get_key(K4).pressed {
+            self.display_mode = DisplayMode::FCost
+        } else if get_key(K5).pressed {
+            self.display_mode = DisplayMode::Components
+        } else if get_key(K6).pressed {
+            self.display_mode = DisplayMode::Distance
+        } else if get_key(K7).pressed {
+            self.display_mode = DisplayMode::Flee
+        }
+
+        // F3 loads the navigation graph, which is searched along with the grid from then on
+        if get_key(F3).pressed {
+            self.file_report = match NavGraph::load(NAV_GRAPH_FILE) {
+                Ok(graph) => {
+                    let report = format!("nav{}", graph.positions.len());
+                    self.nav_graph = Some(graph);
+                    self.needs_a_star_run = true;
+                    Some(Ok(report))
+                }
+                Err(error) => Some(Err(format!("{}: {}", NAV_GRAPH_FILE, error))),
+            }
+        }
+
+        // F2 saves the distance field on display
+        if get_key(F2).pressed {
+            if let Some(field) = self.displayed_field() {
+                self.file_report = match save_field_csv(field, FIELD_CSV_FILE) {
+                    Ok(()) => Some(Ok("csv".to_string())),
+                    Err(error) => Some(Err(format!("{}: {}", FIELD_CSV_FILE, error))),
+                }
+            }
+        }
+
+        // T switches between a bounded map and one that wraps around at the edges
+        if get_key(T).pressed {
+            self.connectivity.topology = match self.connectivity.topology {
+                Topology::Bounded => Topology::Toroidal,
+                Topology::Toroidal => Topology::Bounded,
+            };
+            self.components = Components::new(&self.nodes, &self.connectivity);
+            self.map_edited = true;
+            self.needs_a_star_run = true
+        }
+
+        // O switches the oriented search for tracked vehicles on and off and H does the same for the hybrid search
+        // for cars. R turns the start heading 45° clockwise and G does the same for the goal heading.
+        if get_key(O).pressed {
+            self.search_mode =
+                if self.search_mode == SearchMode::Oriented { SearchMode::Grid } else { SearchMode::Oriented };
+            self.needs_a_star_run = true
+        }
+        if get_key(H).pressed {
+            self.search_mode =
+                if self.search_mode == SearchMode::Hybrid { SearchMode::Grid } else { SearchMode::Hybrid };
+            self.needs_a_star_run = true
+        }
+        // I switches to planning around the obstacles as polygons, and back
+        if get_key(I).pressed {
+            self.search_mode = match self.search_mode {
+                SearchMode::Visibility => SearchMode::Grid,
+                _ => SearchMode::Visibility,
+            };
+            self.needs_a_star_run = true
+        }
+        // L does the same for the navmesh
+        if get_key(L).pressed {
+            self.search_mode = match self.search_mode {
+                SearchMode::NavMesh => SearchMode::Grid,
+                _ => SearchMode::NavMesh,
+            };
+            self.needs_a_star_run = true
+        }
+        // X goes from the grid to the roadmap, then the tree and back to the grid. Z grows them again from a new seed,
+        // which the random tie-breaking takes too.
+        if get_key(X).pressed {
+            self.search_mode = match self.search_mode {
+                SearchMode::Prm => SearchMode::RrtStar,
+                SearchMode::RrtStar => SearchMode::Grid,
+                _ => SearchMode::Prm,
+            };
+            self.needs_a_star_run = true
+        }
+        if get_key(Z).pressed {
+            self.sampling_params.seed += 1;
+            self.search_limits.tie_breaking = self.search_limits.tie_breaking.with_seed(self.sampling_params.seed);
+            self.needs_a_star_run = true
+        }
+        if get_key(R).pressed {
+            self.start_heading = self.start_heading.rotated(1);
+            self.needs_a_star_run = true
+        }
+        if get_key(G).pressed {
+            self.goal_heading = self.goal_heading.rotated(1);
+            self.needs_a_star_run = true
+        }
+
+        // page up and page down move between the layers of the map
+        if get_key(PGUP).pressed {
+            self.visible_layer = (self.visible_layer + 1).min(MAP_LAYERS - 1)
+        }
+        if get_key(PGDN).pressed {
+            self.visible_layer = (self.visible_layer - 1).max(0)
+        }
+
+        // S, E and P place stairs, elevators and teleporters. The first press marks the node under the mouse as the
+        // entrance, the second press (on any layer) marks the exit.
+        for (key, kind) in [(S, PortalKind::Stairs), (E, PortalKind::Elevator), (P, PortalKind::Teleporter)] {
+            if get_key(key).pressed {
+                if let Some(index) = self.node_under_mouse() {
+                    match self.pending_portal {
+                        Some((from, pending_kind)) if pending_kind == kind && from != index => {
+                            self.connectivity.portals.push(Portal::new(from, index, kind));
+                            self.components = Components::new(&self.nodes, &self.connectivity);
+                            self.pending_portal = None;
+                            self.map_edited = true;
+                            self.needs_a_star_run = true
+                        }
+                        _ => self.pending_portal = Some((index, kind)),
+                    }
+                }
+            }
+        }
+
+        // delete removes every portal touching the node under the mouse and escape cancels a half placed portal
+        if get_key(DEL).pressed {
+            if let Some(index) = self.node_under_mouse() {
+                self.connectivity.portals.retain(|portal| !portal.touches(index));
+                self.components = Components::new(&self.nodes, &self.connectivity);
+                self.map_edited = true;
+                self.needs_a_star_run = true
+            }
+        }
+        if get_key(ESCAPE).pressed {
+            self.pending_portal = None
+        }
+
+        // B cycles through how many nodes the grid search may expand before it settles for a partial path
+        if get_key(B).pressed {
+            self.search_limits.max_expansions = match self.search_limits.max_expansions {
+                None => Some(100),
+                Some(100) => Some(25),
+                Some(_) => None,
+            };
+            self.needs_a_star_run = true
+        }
+
+        // A moves on to the next way of breaking ties between nodes with the same score. Hold TAB to compare them.
+        if get_key(A).pressed {
+            self.search_limits.tie_breaking = self.search_limits.tie_breaking.next(self.sampling_params.seed);
+            self.needs_a_star_run = true
+        }
+
+        // Q cycles between running the grid search all at once, a bit each frame and on the worker threads
+        if get_key(Q).pressed {
+            self.solver = match self.solver {
+                Solver::Immediate => Solver::TimeSliced,
+                Solver::TimeSliced => Solver::Threaded,
+                Solver::Threaded => Solver::Immediate,
+            };
+            self.needs_a_star_run = true
+        }
+
+        // V switches between visiting the waypoints in the order they were placed and in the shortest order we can find
+        if get_key(V).pressed {
+            self.order_waypoints = !self.order_waypoints;
+            self.needs_a_star_run = true
+        }
+
+        // N switches between routing through the waypoints and heading for the nearest of them and the end node
+        if get_key(N).pressed {
+            self.nearest_goal = !self.nearest_goal;
+            self.needs_a_star_run = true
+        }
+
+        // K steps through the cheapest few paths to the goal, and back to the plain path after the last one
+        if get_key(K).pressed {
+            self.alternative = match self.alternative {
+                None => Some(0),
+                Some(alternative) if alternative + 1 < ALTERNATIVE_COLORS.len() => Some(alternative + 1),
+                Some(_) => None,
+            };
+            self.needs_a_star_run = true
+        }
+
+        // dragging with the right mouse button draws a zone the grid path query has to pay extra to go through, or
+        // with control held one it can't go through at all. C clears all the zones.
+        if get_mouse(1).pressed {
+            self.zone_corner = self.node_under_mouse();
+        }
+        if get_mouse(1).released {
+            if let (Some(corner), Some(index)) = (self.zone_corner.take(), self.node_under_mouse()) {
+                let zone = self.zone_between(corner, index);
+                if get_key(CTRL).held {
+                    self.constraints.no_go.push(zone);
+                } else {
+                    self.constraints.penalties.push((zone, PENALTY_ZONE_COST));
+                }
+                self.needs_a_star_run = true
+            }
+        }
+        if get_key(C).pressed {
+            self.constraints.no_go.clear();
+            self.constraints.penalties.clear();
+            self.needs_a_star_run = true
+        }
+
+        // Y changes the terrain of the node under the mouse, and U switches the grid path query between the normal
+        // terrain costs and keeping off rough ground
+        if get_key(Y).pressed {
+            if let Some(index) = self.node_under_mouse() {
+                self.nodes[index].terrain = self.nodes[index].terrain.next();
+                self.map_edited = true;
+                self.needs_a_star_run = true
+            }
+        }
+        // J opens or closes the side of the node under the mouse that's closest to the mouse. With the side closed we
+        // can still come in that way but not leave, which makes the edge one-way, or closes it for good if the
+        // neighbor's side is closed too.
+        if get_key(J).pressed {
+            if let Some(index) = self.node_under_mouse() {
+                let exit = self.exit_under_mouse(index);
+                self.nodes[index].closed_exits ^= exit;
+                self.side_changed(index, exit);
+                self.map_edited = true;
+                self.needs_a_star_run = true
+            }
+        }
+        if get_key(U).pressed {
+            self.constraints.terrain_multipliers = if self.constraints.terrain_multipliers == AVOID_ROUGH_GROUND {
+                QueryConstraints::default().terrain_multipliers
+            } else {
+                AVOID_ROUGH_GROUND
+            };
+            self.needs_a_star_run = true
+        }
+
+        // M sends a crowd of path requests from random free nodes on this layer to the goal, to the worker threads when
+        // we are using them and to the scheduler otherwise
+        if get_key(M).pressed {
+            self.request_crowd_paths();
+        }
+        // D sends an agent walking from the start to the goal
+        if get_key(D).pressed {
+            self.spawn_agent();
+        }
+        // F lets an explorer loose on the map with only its sensor to go by. 8 switches its line of sight on and off
+        // and 9 goes through the sensor ranges.
+        if get_key(F).pressed {
+            self.explore = !self.explore;
+            self.explorer = None;
+        }
+        if get_key(K8).pressed {
+            self.sensor_params.line_of_sight = !self.sensor_params.line_of_sight;
+            self.explorer = None;
+        }
+        if get_key(K9).pressed {
+            let range = self.sensor_params.range;
+            self.sensor_params.range = if range >= MAX_SENSOR_RANGE { 2 } else { range + 1 };
+            self.explorer = None;
+        }
+
+        // the mouse wheel zooms in and out and the arrow keys move the view around the map.
+        let wheel = get_mouse_wheel();
+        if wheel > 0 {
+            self.zoom = (self.zoom + 1).min(MAX_ZOOM)
+        } else if wheel < 0 {
+            self.zoom = (self.zoom - 1).max(1)
+        }
+
+        if get_key(LEFT).pressed {
+            self.view_offset_x -= 1
+        }
+        if get_key(RIGHT).pressed {
+            self.view_offset_x += 1
+        }
+        if get_key(UP).pressed {
+            self.view_offset_y -= 1
+        }
+        if get_key(DOWN).pressed {
+            self.view_offset_y += 1
+        }
+        self.view_offset_x = self.view_offset_x.clamp(0, MAP_WIDTH - 1);
+        self.view_offset_y = self.view_offset_y.clamp(0, MAP_HEIGHT - 1);
+    }
+
+    /**
+    Brings the components up to date after a wall went up or came down on the side `exit` of the node at `index`, or
+    that side was closed or opened. Either can cut an area in two or join two areas together.
+     */
+    fn side_changed(&mut self, index: usize, exit: u8) {
+        let (dx, dy) = exit_offset(exit);
+        if let Some(neighbor_index) = neighbor_at(&self.nodes[index], dx, dy, self.connectivity.topology) {
+            self.components.edge_changed(index, neighbor_index, &self.nodes, &self.connectivity);
+        }
+    }
+
+    /**
+    Queues CROWD_SIZE time sliced requests to the goal, each from a random free node on the visible layer.
+     */
+    fn request_crowd_paths(&mut self) {
+        let goal_index = match self.node_end_index {
+            Some(index) => index,
+            None => return,
+        };
+        let free_nodes: Vec<usize> = (0..self.nodes.len())
+            .filter(|&index| self.nodes[index].z == self.visible_layer && !self.nodes[index].obstacle)
+            .collect();
+        if free_nodes.is_empty() {
+            return;
+        }
+
+        for _ in 0..CROWD_SIZE {
+            let start_index = free_nodes[olc::c_rand() as usize % free_nodes.len()];
+            if self.solver == Solver::Threaded {
+                self.crowd_handles.push(self.service.request(start_index, goal_index, &self.search_limits,
+                                                             &self.constraints));
+            } else {
+                let search = SlicedSearch::new(start_index, goal_index, &self.nodes, &self.connectivity,
+                                               &self.components, &self.search_limits, &self.constraints);
+                let id = self.scheduler.request(search);
+                self.crowd_requests.push(id);
+            }
+        }
+    }
+
+    /**
+    Adds an agent on the start node and gives it the grid path to the goal.
+     */
+    fn spawn_agent(&mut self) {
+        if let (Some(start_index), Some(goal_index)) = (self.node_start_index, self.node_end_index) {
+            let mut agent = Agent::new(start_index, goal_index, &self.nodes);
+            agent.set_path(self.agent_path(start_index, goal_index, &[]), &self.nodes, self.connectivity.topology);
+            self.agents.push(agent);
+        }
+    }
+
+    /**
+    The path an agent takes, found the same way as the grid path but without touching the scores on show, and keeping
+    out of the `occupied` nodes.
+     */
+    fn agent_path(&self, start_index: usize, goal_index: usize, occupied: &[usize]) -> Result<Path, PathError> {
+        SlicedSearch::new(start_index, goal_index, &self.nodes, &self.connectivity, &self.components,
+                          &self.search_limits, &self.constraints)
+            .excluding(occupied, &[])
+            .finish(&self.nodes, &self.connectivity)
+    }
+
+    /**
+    Walks the agents along for the time this frame took. Anything that makes us run the search again might have opened
+    a way for the stuck ones.
+     */
+    fn update_agents(&mut self, elapsed_time: f32) {
+        let mut agents = std::mem::take(&mut self.agents);
+        update_agents(&mut agents, elapsed_time, &self.nodes, &self.connectivity, self.needs_a_star_run,
+                      |start_index, goal_index, occupied| self.agent_path(start_index, goal_index, occupied));
+        self.agents = agents;
+    }
+
+    /**
+    Walks the explorer along for the time this frame took, starting a new one when there isn't one yet or the start or
+    goal moved.
+     */
+    fn update_explorer(&mut self, elapsed_time: f32) {
+        if !self.explore {
+            return;
+        }
+        let (start_index, goal_index) = match (self.node_start_index, self.node_end_index) {
+            (Some(start_index), Some(goal_index)) => (start_index, goal_index),
+            _ => {
+                self.explorer = None;
+                return;
+            }
+        };
+        match &mut self.explorer {
+            Some(explorer) if explorer.start == start_index && explorer.goal == goal_index => {
+                explorer.update(elapsed_time, &self.nodes, &self.connectivity, &self.search_limits);
+            }
+            _ => {
+                self.explorer = Some(Explorer::new(start_index, goal_index, self.sensor_params, &self.nodes,
+                                                   &self.connectivity, &self.search_limits));
+            }
+        }
+    }
+
+    /**
+    Draws the explorer on its layer as a cyan circle, with the rest of its plan in dark cyan.
+     */
+    fn render_explorer(&self) {
+        let explorer = match &self.explorer {
+            Some(explorer) => explorer,
+            None => return,
+        };
+        let topology = self.connectivity.topology;
+        let mut previous = &self.nodes[explorer.position];
+        for &index in explorer.route() {
+            let node = &self.nodes[index];
+            // we leave out the jumps through portals. Like on the grid path, a step across the edge of a toroidal map
+            // is drawn from both ends, as a stub leaving one edge of the grid and another entering on the opposite one.
+            let dx = topology.delta(previous.x, node.x, MAP_WIDTH);
+            let dy = topology.delta(previous.y, node.y, MAP_HEIGHT);
+            let adjacent = dx.abs() <= 1 && dy.abs() <= 1;
+            if adjacent && node.z == previous.z && node.z == self.visible_layer {
+                let (from_x, from_y) = self.node_center(previous.x, previous.y);
+                let (to_x, to_y) = self.edge_end(previous.x, previous.y, node.x, node.y);
+                draw_line(from_x, from_y, to_x, to_y, DARK_CYAN);
+                let (from_x, from_y) = self.node_center(node.x, node.y);
+                let (to_x, to_y) = self.edge_end(node.x, node.y, previous.x, previous.y);
+                draw_line(from_x, from_y, to_x, to_y, DARK_CYAN);
+            }
+            previous = node;
+        }
+
+        let here = &self.nodes[explorer.position];
+        if here.z == self.visible_layer {
+            let (x, y) = self.world_to_screen(here.x as f32, here.y as f32);
+            fill_circle(x, y, ((AGENT_RADIUS * self.node_size() as f32) as i32).max(1), CYAN);
+        }
+    }
+
+    /**
+    Draws the agents on this layer as white circles, or red ones when they can't get to their goal.
+     */
+    fn render_agents(&self) {
+        let radius = ((AGENT_RADIUS * self.node_size() as f32) as i32).max(1);
+        for agent in self.agents.iter().filter(|agent| agent.z == self.visible_layer) {
+            let (x, y) = self.world_to_screen(agent.x, agent.y);
+            fill_circle(x, y, radius, if agent.stuck { RED } else { WHITE });
+        }
+    }
+
+    /**
+    Lets the roadmap or tree take this frame's samples.
+     */
+    fn grow_sampler(&mut self) {
+        if let Some(Ok(sampler)) = &mut self.sampler {
+            for _ in 0..SAMPLES_PER_FRAME {
+                sampler.step();
+            }
+        }
+    }
+
+    /**
+    Gives the pending path requests their share of this frame and picks up the ones that finished, from the scheduler
+    and from the worker threads. Results the workers worked out on an old map are sent off again.
+     */
+    fn update_path_requests(&mut self) {
+        self.scheduler.run(&self.nodes, &self.connectivity, &FRAME_BUDGET);
+
+        if let Some(id) = self.path_request {
+            if let Some(result) = self.scheduler.take_result(id) {
+                self.path = Some(result);
+                self.path_request = None;
+            }
+        }
+
+        let scheduler = &mut self.scheduler;
+        let crowd_paths = &mut self.crowd_paths;
+        self.crowd_requests.retain(|&id| match scheduler.take_result(id) {
+            Some(result) => {
+                // a crowd member that can't get anywhere simply has nothing to draw
+                crowd_paths.extend(result.ok());
+                false
+            }
+            None => true,
+        });
+
+        if let Some(handle) = &mut self.path_handle {
+            match handle.poll() {
+                PathPoll::Pending => {}
+                PathPoll::Ready(result) => {
+                    self.path = Some(result.clone());
+                    self.path_handle = None;
+                }
+                PathPoll::Stale(_) => self.service.recompute(handle),
+            }
+        }
+
+        for handle in &mut self.crowd_handles {
+            if let PathPoll::Stale(_) = handle.poll() {
+                self.service.recompute(handle);
+            }
+        }
+    }
+
+    /**
+    Renders the nodes aka the squares.
+     */
+    fn render_nodes(&mut self) {
+        let inner_size = self.node_size() - NODE_BORDER;
+        let score_range = self.score_range();
+
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                // get the index of the current square being rendered.
+                let index: usize = node_index(x, y, self.visible_layer);
+                if index < self.nodes.len() { // check that our index is not out of bounds.
+                    let (screen_x, screen_y) = self.node_screen_pos(x, y);
+
+                    // in one of the heatmap modes we color free nodes by their score instead of plain blue
+                    let free_color = match (self.node_score(index), score_range) {
+                        (Some(score), Some((min, max))) => gradient(score, min, max),
+                        _ => match (self.display_mode, self.components.label(index)) {
+                            (DisplayMode::Components, Some(label)) => COMPONENT_COLORS[label % COMPONENT_COLORS.len()],
+                            _ => self.nodes[index].terrain.color(),
+                        },
+                    };
+
+                    fill_rect(screen_x,
+                              screen_y,
+                              inner_size,
+                              inner_size,
+                              // we change the color of our square if it's obstacle value is true
+                              if self.nodes[index].obstacle { GREY } else { free_color });
 
 
                     if let Some(start_index) = self.node_start_index {
                         if index == start_index {
-                            fill_rect(x * NODE_SIZE + NODE_BORDER,
-                                      y * NODE_SIZE + NODE_BORDER,
-                                      NODE_SIZE - NODE_BORDER,
-                                      NODE_SIZE - NODE_BORDER,
+                            fill_rect(screen_x,
+                                      screen_y,
+                                      inner_size,
+                                      inner_size,
                                       // we change the color of our square if it's obstacle value is true
                                       GREEN);
                         }
@@ -349,17 +2052,371 @@ impl AppStruct {
                     if let Some(end_index) = self.node_end_index {
                         if index == end_index {
                             fill_rect(
-                                x * NODE_SIZE + NODE_BORDER,
-                                y * NODE_SIZE + NODE_BORDER,
-                                NODE_SIZE - NODE_BORDER,
-                                NODE_SIZE - NODE_BORDER,
+                                screen_x,
+                                screen_y,
+                                inner_size,
+                                inner_size,
                                 RED, // assuming RED is previously defined
                             );
                         }
                     }
+
+                    // while exploring, the nodes the explorer hasn't seen are dark grey and the ones it remembers but
+                    // can't see right now are drawn the way it remembers them, in dim colors. What it can see shows as
+                    // it is.
+                    if let Some(explorer) = &self.explorer {
+                        let remembered = match explorer.known[index] {
+                            None => Some(VERY_DARK_GREY),
+                            Some(_) if explorer.sensed[index] => None,
+                            Some(true) => Some(DARK_GREY),
+                            Some(false) => Some(VERY_DARK_BLUE),
+                        };
+                        if let Some(color) = remembered {
+                            fill_rect(screen_x, screen_y, inner_size, inner_size, color);
+                        }
+                    }
+
+                    // waypoints get a white square in the middle, so the node still shows its own color around it
+                    if self.waypoints.contains(&index) {
+                        let (offset, size) = (inner_size / 4, inner_size / 2);
+                        fill_rect(screen_x + offset, screen_y + offset, size, size, WHITE);
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+    Draws an outline around every portal end on the visible layer in the portal's color. When both ends are on this
+    layer we also draw a dashed line between them.
+     */
+    fn render_portals(&self) {
+        let inner_size = self.node_size() - NODE_BORDER;
+        let outline = |index: usize, color: Pixel| {
+            let node = &self.nodes[index];
+            if node.z == self.visible_layer {
+                let (screen_x, screen_y) = self.node_screen_pos(node.x, node.y);
+                draw_rect(screen_x - 1, screen_y - 1, inner_size + 1, inner_size + 1, color);
+            }
+        };
+
+        for portal in &self.connectivity.portals {
+            let color = portal.kind.color();
+            let (from, to) = (&self.nodes[portal.from], &self.nodes[portal.to]);
+            if from.z == self.visible_layer && to.z == self.visible_layer {
+                let (from_x, from_y) = self.node_center(from.x, from.y);
+                let (to_x, to_y) = self.node_center(to.x, to.y);
+                draw_line_with_pattern(from_x, from_y, to_x, to_y, color, 0xF0F0F0F0);
+            }
+
+            outline(portal.from, color);
+            outline(portal.to, color);
+        }
+
+        if let Some((index, _)) = self.pending_portal {
+            outline(index, WHITE);
+        }
+    }
+
+    /**
+    Why the grid path on screen failed or stops short, if it does. Which path is on screen depends on what we are
+    doing with the waypoints and the alternatives, in the same order `render_active_path` checks them.
+     */
+    fn grid_error(&self) -> Option<&PathError> {
+        if let Some(nearest) = &self.nearest_path {
+            return nearest.as_ref().map_or_else(Some, |nearest| nearest.path.partial.as_ref());
+        }
+        if let Some(route) = &self.route {
+            return route.as_ref().map_or_else(Some, |route| route.partial.as_ref());
+        }
+        if let Some(alternatives) = &self.alternatives {
+            // the alternatives are never partial
+            return alternatives.as_ref().err();
+        }
+        self.path.as_ref().and_then(|path| path.as_ref().map_or_else(Some, |path| path.partial.as_ref()))
+    }
+
+    /**
+    Outlines the zones of the query on the visible layer, no-go zones in red and penalty zones in magenta, and the zone
+    we are in the middle of drawing in white.
+     */
+    fn render_zones(&self) {
+        let outline = |zone: &Zone, color: Pixel| {
+            if zone.layer == self.visible_layer {
+                let (min_x, min_y) = self.node_screen_pos(zone.min_x, zone.min_y);
+                let (max_x, max_y) = self.node_screen_pos(zone.max_x + 1, zone.max_y + 1);
+                draw_rect(min_x - 2, min_y - 2, max_x - min_x + 1, max_y - min_y + 1, color);
+            }
+        };
+
+        for zone in &self.constraints.no_go {
+            outline(zone, RED);
+        }
+        for (zone, _) in &self.constraints.penalties {
+            outline(zone, MAGENTA);
+        }
+        if let (Some(corner), Some(index)) = (self.zone_corner, self.node_under_mouse()) {
+            outline(&self.zone_between(corner, index), WHITE);
+        }
+    }
+
+    /**
+    The path through the navigation graph from the point closest to the start node to the point closest to the goal
+    node, if a graph is loaded.
+     */
+    fn find_nav_path(&self, start_index: usize, goal_index: usize) -> Option<Result<Path, PathError>> {
+        let graph = self.nav_graph.as_ref()?;
+        let (start, goal) = (&self.nodes[start_index], &self.nodes[goal_index]);
+        let from = graph.nearest(start.x as f32, start.y as f32)?;
+        let to = graph.nearest(goal.x as f32, goal.y as f32)?;
+        Some(graph.find_path(from, to, &self.search_limits))
+    }
+
+    /**
+    Draws the navigation graph over the map: its links in dark magenta, its points in magenta and the path through it
+    in white.
+     */
+    fn render_nav_graph(&self) {
+        let graph = match &self.nav_graph {
+            Some(graph) => graph,
+            None => return,
+        };
+        let screen_pos = |index: usize| self.world_to_screen(graph.positions[index].0, graph.positions[index].1);
+
+        for (from, links) in graph.links.iter().enumerate() {
+            for &(to, _) in links {
+                let ((from_x, from_y), (to_x, to_y)) = (screen_pos(from), screen_pos(to));
+                draw_line(from_x, from_y, to_x, to_y, DARK_MAGENTA);
+            }
+        }
+        if let Some(Ok(path)) = &self.nav_path {
+            for step in path.nodes.windows(2) {
+                let ((from_x, from_y), (to_x, to_y)) = (screen_pos(step[0]), screen_pos(step[1]));
+                draw_line(from_x, from_y, to_x, to_y, WHITE);
+            }
+        }
+        for index in 0..graph.positions.len() {
+            let (x, y) = screen_pos(index);
+            fill_rect(x - 1, y - 1, 3, 3, MAGENTA);
+        }
+    }
+
+    /**
+    The zone with the nodes at `a` and `b` in opposite corners, on the layer of `a`.
+     */
+    fn zone_between(&self, a: usize, b: usize) -> Zone {
+        let (a, b) = (&self.nodes[a], &self.nodes[b]);
+        Zone::new(a.z, (a.x, a.y), (b.x, b.y))
+    }
+
+    /**
+    Draws the status line along the bottom of the screen.
+     */
+    fn render_hud(&self) -> Result<(), Error> {
+        let mut status = format!("L{}/{}", self.visible_layer + 1, MAP_LAYERS);
+        if self.search_limits.tie_breaking != TieBreaking::Index {
+            // the tie-breaking we use, when it isn't the plain one
+            status.push_str(&format!(" {}", self.search_limits.tie_breaking.label()));
+        }
+        if let Some(Ok(path)) = &self.oriented_path {
+            // how much of the cost went on moving and how much on turning
+            status.push_str(&format!(" mv{} tn{}", path.move_cost, path.turn_cost));
+        }
+        if let Some(Ok(path)) = &self.hybrid_path {
+            status.push_str(&format!(" car {:.1}", path.cost));
+        }
+        if let Some(Ok(path)) = &self.visibility_path {
+            status.push_str(&format!(" vis {:.1}", path.length));
+        }
+        if let Some(Ok(sampler)) = &self.sampler {
+            // how far the roadmap or tree has got, and how long its path is so far
+            let name = if self.search_mode == SearchMode::Prm { "prm" } else { "rrt" };
+            status.push_str(&format!(" {} {}/{}", name, sampler.samples_taken(), self.sampling_params.samples));
+            if let Some(path) = sampler.path() {
+                status.push_str(&format!(" {:.1}", path.length));
+            }
+        }
+        if let Some(Ok(path)) = &self.mesh_path {
+            // the length of the mesh path next to the number of steps the grid path takes
+            let grid_steps = self.path.as_ref().and_then(|path| path.as_ref().ok()).map(|path| path.nodes.len() - 1);
+            match grid_steps {
+                Some(steps) => status.push_str(&format!(" mesh {:.1}/{}", path.length, steps)),
+                None => status.push_str(&format!(" mesh {:.1}", path.length)),
+            }
+        }
+        let pending = self.scheduler.pending_count() + self.service.pending_count();
+        if pending > 0 {
+            // how many path requests the scheduler and the workers are still working through
+            status.push_str(&format!(" q{}", pending));
+        }
+        if let (Some(Ok(paths)), Some(alternative)) = (&self.alternatives, self.alternative) {
+            // which alternative we are looking at out of how many there are, and what it costs
+            match paths.get(alternative) {
+                Some(path) => status.push_str(&format!(" k{}/{} c{}", alternative + 1, paths.len(), path.cost)),
+                None => status.push_str(&format!(" k{}/{} -", alternative + 1, paths.len())),
+            }
+        }
+        let zone_count = self.constraints.no_go.len() + self.constraints.penalties.len();
+        if zone_count > 0 {
+            status.push_str(&format!(" z{}", zone_count));
+        }
+        if self.constraints.terrain_multipliers == AVOID_ROUGH_GROUND {
+            // we are keeping off rough ground
+            status.push_str(" r");
+        }
+        if !self.waypoints.is_empty() {
+            // the number of waypoints, with a + when we pick the order or a ? when we head for the nearest goal
+            let marker = if self.nearest_goal { "?" } else if self.order_waypoints { "+" } else { "" };
+            status.push_str(&format!(" w{}{}", self.waypoints.len(), marker));
+        }
+        if !self.agents.is_empty() {
+            status.push_str(&format!(" a{}", self.agents.len()));
+        }
+        if let Some(explorer) = &self.explorer {
+            // the sensor range, with an l when it needs line of sight, how often the explorer had to plan again and how
+            // far it has walked, and whether it got there
+            let sight = if explorer.sensor.line_of_sight { "l" } else { "" };
+            status.push_str(&format!(" s{}{} rp{} d{:.1}", explorer.sensor.range, sight, explorer.replans,
+                                     explorer.distance));
+            match explorer.outcome {
+                Some(Ok(())) => status.push_str(" ok"),
+                Some(Err(error)) => status.push_str(&format!(" {}", error)),
+                None => {}
+            }
+        }
+        if let Some((_, kind)) = self.pending_portal {
+            status.push_str(&format!(" {:?}?", kind));
+        }
+        // how the last load or save went, nav and the number of points it loaded, or csv
+        if let Some(Ok(report)) = &self.file_report {
+            status.push_str(&format!(" {}", report));
+        }
+        draw_string(2, screen_height() - CHAR_SIZE - 1, &status, WHITE)?;
+
+        // holding TAB lists how many nodes every way of breaking ties expands, the one we use in yellow
+        if get_key(TAB).held {
+            let mut x = 2;
+            for &(tie_breaking, expansions) in self.tie_report.iter().flatten() {
+                let entry = format!("{}{}", tie_breaking.label(), expansions);
+                let color = if tie_breaking == self.search_limits.tie_breaking { YELLOW } else { WHITE };
+                draw_string(x, screen_height() - 2 * (CHAR_SIZE + 1), &entry, color)?;
+                x += (entry.len() as i32 + 1) * CHAR_SIZE;
+            }
+        }
+
+        // when the planner we are using couldn't find a path we say why, rather than just not drawing one. The same
+        // goes for a partial path that stops short of the goal.
+        let error = match self.search_mode {
+            SearchMode::Grid => self.grid_error(),
+            SearchMode::Oriented => self.oriented_path.as_ref().and_then(|result| result.as_ref().err()),
+            SearchMode::Hybrid => self.hybrid_path.as_ref().and_then(|result| result.as_ref().err()),
+            SearchMode::Visibility => self.visibility_path.as_ref().and_then(|result| result.as_ref().err()),
+            SearchMode::NavMesh => self.mesh_path.as_ref().and_then(|result| result.as_ref().err()),
+            SearchMode::Prm | SearchMode::RrtStar => match &self.sampler {
+                Some(Err(error)) => Some(error),
+                // it's only unreachable once the samples have run out without a path
+                Some(Ok(sampler)) if sampler.finished() && sampler.path().is_none() => Some(&PathError::Unreachable),
+                _ => None,
+            },
+        };
+        // a load or save that failed says why first
+        let error = match &self.file_report {
+            Some(Err(report)) => Some(report.clone()),
+            _ => error.map(PathError::to_string),
+        };
+        match error {
+            Some(error) => draw_string(2 + (status.len() as i32 + 1) * CHAR_SIZE,
+                                       screen_height() - CHAR_SIZE - 1,
+                                       &error,
+                                       RED),
+            None => Ok(()),
+        }
+    }
+
+    /**
+    Draws the score of the current display mode inside each node. We only draw a label when the node is big enough
+    at the current zoom level to fit all of its digits.
+     */
+    fn render_node_labels(&self) -> Result<(), Error> {
+        let inner_size = self.node_size() - NODE_BORDER;
+        if inner_size < CHAR_SIZE {
+            return Ok(());
+        }
+
+        for index in 0..self.nodes.len() {
+            if self.nodes[index].z != self.visible_layer {
+                continue;
+            }
+
+            if let Some(score) = self.node_score(index) {
+                let label = score.to_string();
+                let label_width = label.len() as i32 * CHAR_SIZE;
+                if label_width > inner_size {
+                    continue;
                 }
+
+                let (screen_x, screen_y) = self.node_screen_pos(self.nodes[index].x, self.nodes[index].y);
+                draw_string(screen_x + (inner_size - label_width) / 2,
+                            screen_y + (inner_size - CHAR_SIZE) / 2,
+                            &label,
+                            BLACK)?;
             }
         }
+
+        Ok(())
+    }
+
+    /**
+    Shows the position, parent and scores of the node under the mouse in a small box next to the cursor.
+     */
+    fn render_hover_info(&self) -> Result<(), Error> {
+        let index = match self.node_under_mouse() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let node = &self.nodes[index];
+
+        let format_score = |score: Option<i32>| match score {
+            Some(score) if score != i32::MAX => score.to_string(),
+            _ => String::from("-"),
+        };
+        let scores = self.scratch.get(index);
+        let parent = match scores.parent {
+            Some(parent_index) => format!("{},{}", self.nodes[parent_index].x, self.nodes[parent_index].y),
+            None => String::from("-"),
+        };
+
+        let lines = [
+            format!("@ {},{} L{}", node.x, node.y, node.z + 1),
+            format!("p {}", parent),
+            format!("g {}", format_score(Some(scores.global_goal))),
+            format!("h {}", format_score(self.node_heuristic(index))),
+            format!("f {}", format_score(Some(scores.local_goal))),
+            format!("c {}", format_score(self.components.label(index).map(|label| label as i32))),
+            format!("d {}", format_score(self.distance_field.get(index).copied())),
+            format!("t {:?}", node.terrain),
+        ];
+
+        let line_height = CHAR_SIZE + 1;
+        let box_width = lines.iter().map(|line| line.len() as i32).max().unwrap_or(0) * CHAR_SIZE + 4;
+        let box_height = lines.len() as i32 * line_height + 3;
+
+        // keep the box next to the cursor, but flip it to the other side when it would run off the screen
+        let mut box_x = get_mouse_x() + 4;
+        let mut box_y = get_mouse_y() + 4;
+        if box_x + box_width > screen_width() {
+            box_x = (get_mouse_x() - box_width - 1).max(0);
+        }
+        if box_y + box_height > screen_height() {
+            box_y = (get_mouse_y() - box_height - 1).max(0);
+        }
+
+        fill_rect(box_x, box_y, box_width, box_height, BLACK);
+        for (line_index, line) in lines.iter().enumerate() {
+            draw_string(box_x + 2, box_y + 2 + line_index as i32 * line_height, line, WHITE)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -370,7 +2427,201 @@ fn main() {
         node_start_index: None,
         node_end_index: None,
         needs_a_star_run: true,
+        display_mode: DisplayMode::Default,
+        zoom: 1,
+        view_offset_x: 0,
+        view_offset_y: 0,
+        connectivity: Connectivity {
+            topology: Topology::Bounded,
+            portals: vec![],
+        },
+        components: Components::default(),
+        visible_layer: 0,
+        pending_portal: None,
+        search_mode: SearchMode::Grid,
+        start_heading: Heading::East,
+        goal_heading: Heading::East,
+        turn_costs: TurnCosts::default(),
+        hybrid_params: HybridParams::default(),
+        search_limits: SearchLimits { allow_partial: true, ..SearchLimits::default() },
+        tie_report: None,
+        path: None,
+        oriented_path: None,
+        hybrid_path: None,
+        visibility_graph: None,
+        visibility_path: None,
+        navmesh: None,
+        mesh_path: None,
+        sampling_params: SamplingParams::default(),
+        sampler: None,
+        solver: Solver::Immediate,
+        scheduler: PathScheduler::default(),
+        path_request: None,
+        service: PathService::new(WORKER_THREADS),
+        path_handle: None,
+        map_edited: true,
+        crowd_requests: vec![],
+        crowd_paths: vec![],
+        crowd_handles: vec![],
+        waypoints: vec![],
+        order_waypoints: false,
+        route: None,
+        nearest_goal: false,
+        nearest_path: None,
+        alternative: None,
+        alternatives: None,
+        constraints: QueryConstraints::default(),
+        zone_corner: None,
+        distance_field: vec![],
+        flee_field: vec![],
+        nav_graph: None,
+        nav_path: None,
+        file_report: None,
+        agents: vec![],
+        explore: false,
+        sensor_params: SensorParams::default(),
+        explorer: None,
+        scratch: SearchScratch::default(),
     };
 
     olc::start("A*", &mut a_star, 160, 160, 6, 6).unwrap();
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+    The cheapest path from `start` to `goal`, searched the way the app does it.
+     */
+    fn search(start: usize, goal: usize, nodes: &[Node], connectivity: &Connectivity) -> Result<Path, PathError> {
+        let components = Components::new(nodes, connectivity);
+        SlicedSearch::new(start, goal, nodes, connectivity, &components, &SearchLimits::default(),
+                          &QueryConstraints::default())
+            .finish(nodes, connectivity)
+    }
+
+    #[test]
+    fn heatmap_scores_add_up_and_the_gradient_runs_from_blue_to_red() {
+        let map = TestMap::new(&["",
+                                 "",
+                                 "",
+                                 "      #",
+                                 "      #",
+                                 "      #",
+                                 "      #",
+                                 "      #",
+                                 "      #",
+                                 "      #",
+                                 "      #",
+                                 "      #"]);
+        let TestMap { nodes, connectivity, .. } = &map;
+        let (start, goal) = (node_index(2, 7, 0), node_index(11, 7, 0));
+        let mut search = map.sliced(start, goal, &SearchLimits::default());
+        let path = search.finish(nodes, connectivity).unwrap();
+        // around one end of the wall, 5 rows up or down and back again
+        assert_eq!(path.cost, 9 + 2 * 5);
+        let scratch = search.into_scratch();
+
+        // every node the search scored has f = g + h, and along the path g is what it cost to get there
+        for index in (0..nodes.len()).filter(|&index| scratch.get(index).global_goal != i32::MAX) {
+            let scores = scratch.get(index);
+            let heuristic = nodes[index].heuristic(&nodes[goal], nodes, connectivity);
+            assert_eq!(scores.local_goal, scores.global_goal + heuristic);
+        }
+        for (steps, &index) in path.nodes.iter().enumerate() {
+            assert_eq!(scratch.get(index).global_goal, steps as i32);
+        }
+
+        let rgb = |pixel: Pixel| (pixel.r, pixel.g, pixel.b);
+        assert_eq!(rgb(gradient(3, 3, 9)), (0, 0, 255));
+        assert_eq!(rgb(gradient(9, 3, 9)), (255, 0, 0));
+        // values outside the range get the color of the end they are past
+        assert_eq!(rgb(gradient(20, 3, 9)), (255, 0, 0));
+        assert_eq!(rgb(gradient(5, 5, 5)), (0, 0, 255));
+    }
+
+    #[test]
+    fn paths_wrap_around_the_edges_of_a_toroidal_map() {
+        let nodes = open_map();
+        let mut connectivity = Connectivity { topology: Topology::Bounded, portals: Vec::new() };
+        let (start, goal) = (node_index(0, 0, 0), node_index(MAP_WIDTH - 1, MAP_HEIGHT - 1, 0));
+        assert_eq!(search(start, goal, &nodes, &connectivity).unwrap().cost, MAP_WIDTH + MAP_HEIGHT - 2);
+
+        // the nodes along the far edges are one step left and one step up from here
+        connectivity.topology = Topology::Toroidal;
+        let neighbors = get_neighbors(start, &nodes, &connectivity);
+        assert!(neighbors.contains(&(node_index(MAP_WIDTH - 1, 0, 0), 1)));
+        assert!(neighbors.contains(&(node_index(0, MAP_HEIGHT - 1, 0), 1)));
+        // and the crow flies across the corner too, a distance of √2 rounded down
+        assert_eq!(nodes[start].heuristic(&nodes[goal], &nodes, &connectivity), 1);
+        assert_eq!(search(start, goal, &nodes, &connectivity).unwrap().cost, 2);
+    }
+
+    #[test]
+    fn portals_lead_between_layers_and_teleporters_only_one_way() {
+        let nodes = open_map();
+        let stairs = Portal::new(node_index(2, 2, 0), node_index(2, 2, 1), PortalKind::Stairs);
+        let teleporter = Portal::new(node_index(10, 10, 0), node_index(5, 6, 1), PortalKind::Teleporter);
+        let connectivity = Connectivity { topology: Topology::Bounded, portals: vec![stairs, teleporter] };
+        let (start, goal) = (node_index(9, 9, 0), node_index(5, 5, 1));
+
+        // two steps to the teleporter, through it and one more step
+        let there = search(start, goal, &nodes, &connectivity).unwrap();
+        assert_eq!(there.cost, 2 + 1 + 1);
+        // the way back has to take the stairs
+        let back = search(goal, start, &nodes, &connectivity).unwrap();
+        assert_eq!(back.cost, 6 + 4 + 14);
+        assert!(back.nodes.contains(&node_index(2, 2, 1)) && back.nodes.contains(&node_index(2, 2, 0)));
+
+        // the heuristic never promises less than what's left of the way
+        for path in [&there, &back] {
+            let end = &nodes[path.nodes[path.nodes.len() - 1]];
+            for (i, &index) in path.nodes.iter().enumerate() {
+                let left = search(index, path.nodes[path.nodes.len() - 1], &nodes, &connectivity).unwrap().cost;
+                assert!(nodes[index].heuristic(end, &nodes, &connectivity) <= left, "step {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn one_way_edges_can_only_be_crossed_the_way_they_go() {
+        let mut nodes = open_map();
+        // a line of edges down the middle of the first layer that only let us go east
+        for y in 0..MAP_HEIGHT {
+            nodes[node_index(8, y, 0)].closed_exits = EXIT_WEST;
+        }
+        let map = TestMap::from_nodes(nodes);
+        let (west, east) = (node_index(3, 4, 0), node_index(12, 4, 0));
+
+        assert!(can_step(&map.nodes[node_index(7, 4, 0)], &map.nodes[node_index(8, 4, 0)], Topology::Bounded));
+        assert!(!can_step(&map.nodes[node_index(8, 4, 0)], &map.nodes[node_index(7, 4, 0)], Topology::Bounded));
+        assert_eq!(map.search(west, east).unwrap().cost, 9);
+        assert_eq!(map.search(east, west), Err(PathError::Unreachable));
+    }
+
+    #[test]
+    fn walls_block_both_ways_and_come_down_again() {
+        let mut nodes = open_map();
+        let topology = Topology::Bounded;
+        // a wall along the east side of column 7 with a single gap in it
+        for y in (0..MAP_HEIGHT).filter(|&y| y != 12) {
+            set_wall(node_index(7, y, 0), EXIT_EAST, true, &mut nodes, topology);
+        }
+        let (west, east) = (node_index(7, 2, 0), node_index(8, 2, 0));
+
+        // the neighbor gets the wall on its own side, so neither of them can step across
+        assert_eq!(nodes[east].walls, EXIT_WEST);
+        assert!(!can_step(&nodes[west], &nodes[east], topology) && !can_step(&nodes[east], &nodes[west], topology));
+        assert!(!get_grid_neighbors(west, &nodes, topology).contains(&east));
+        // so the path goes down to the gap and back up
+        let mut map = TestMap::from_nodes(nodes);
+        assert_eq!(map.search(west, east).unwrap().cost, 1 + 2 * 10);
+        assert_eq!(map.search(east, west).unwrap().cost, 1 + 2 * 10);
+
+        set_wall(east, EXIT_WEST, false, &mut map.nodes, topology);
+        assert_eq!((map.nodes[west].walls, map.nodes[east].walls), (0, 0));
+        assert_eq!(map.search(west, east).unwrap().cost, 1);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::components::Components;
+use crate::constraints::QueryConstraints;
+use crate::limits::SearchLimits;
+use crate::path::{Path, PathError};
+use crate::sliced::SlicedSearch;
+use crate::{Connectivity, Node};
+
+
+/**
+The cheapest path from any of several starts to any of several goals, and which start and goal it joins. A partial
+path didn't make it to a goal, so it has none.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiPath {
+    pub path: Path,
+    pub start_index: usize,
+    pub goal_index: Option<usize>,
+}
+
+
+/**
+Finds the cheapest path from any of `start_indices` to any of `goal_indices`, like the nearest exit from where we
+stand or the closest resource to any of our units. All the starts go into the open set at once, and the search
+stops at the first goal it takes off it. The search follows `constraints` the same way a single query does.
+ */
+pub fn multi_a_star(start_indices: &[usize],
+                    goal_indices: &[usize],
+                    nodes: &[Node],
+                    connectivity: &Connectivity,
+                    components: &Components,
+                    limits: &SearchLimits,
+                    constraints: &QueryConstraints) -> Result<MultiPath, PathError> {
+    let path = SlicedSearch::new_multi(start_indices, goal_indices, nodes, connectivity, components, limits,
+                                       constraints)
+        .finish(nodes, connectivity)?;
+
+    // the path runs from the start it was found from, and unless it's partial it ends on the goal it found
+    let start_index = path.nodes[0];
+    let goal_index = match path.partial {
+        Some(_) => None,
+        None => path.nodes.last().copied(),
+    };
+    Ok(MultiPath { path, start_index, goal_index })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node_index, TestMap};
+
+    #[test]
+    fn the_path_is_the_cheapest_between_any_start_and_goal() {
+        // the closest goal to any start is walled in, so the cheapest path is the next closest pair
+        let map = TestMap::new(&["",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "           ###",
+                                 "           #.#",
+                                 "           ###"]);
+        let TestMap { nodes, connectivity, components } = &map;
+        let (limits, constraints) = (SearchLimits::default(), QueryConstraints::default());
+        let starts = [node_index(0, 0, 0), node_index(10, 10, 0), node_index(3, 8, 0)];
+        let goals = [node_index(15, 0, 0), node_index(5, 3, 0), node_index(12, 12, 0)];
+
+        let found = multi_a_star(&starts, &goals, nodes, connectivity, components, &limits, &constraints).unwrap();
+        assert_eq!((found.start_index, found.goal_index), (starts[2], Some(goals[1])));
+        assert_eq!(found.path.cost, 2 + 5);
+        let cheapest = starts.iter()
+            .flat_map(|&start| goals.iter().map(move |&goal| (start, goal)))
+            .filter_map(|(start, goal)| map.search(start, goal).ok())
+            .map(|path| path.cost)
+            .min();
+        assert_eq!(Some(found.path.cost), cheapest);
+
+        // with only the walled in goal there's nothing to find
+        let walled_in = multi_a_star(&starts, &goals[2..], nodes, connectivity, components, &limits, &constraints);
+        assert_eq!(walled_in.map(|found| found.path.cost), Err(PathError::Unreachable));
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs;
+use std::io;
+
+use crate::limits::SearchLimits;
+use crate::path::{Path, PathError};
+use crate::scratch::SearchScratch;
+use crate::sliced::GraphSearch;
+use crate::space::SearchSpace;
+
+
+/**
+A navigation graph that isn't a grid: named points anywhere on the map and the links between them. It's read from a
+text file with one entry per line, and lines that are empty or start with `#` are skipped:
+
+    point <name> <x> <y>
+    link <from> <to> [cost]
+    arc <from> <to> [cost]
+
+A `link` goes both ways and an `arc` only from the first point to the second. Positions are in nodes, so a graph can
+be drawn over the grid. A link without a cost costs the distance between its points, rounded up.
+ */
+pub struct NavGraph {
+    pub names: Vec<String>,
+    pub positions: Vec<(f32, f32)>,
+    // the points we can get to from every point, and what getting there costs
+    pub links: Vec<Vec<(usize, i32)>>,
+    // the lowest cost of any link for every node of distance it covers. The heuristic scales the distance by it, so it
+    // still never overestimates when the file gives links costs below their length.
+    cost_per_distance: f32,
+}
+
+impl NavGraph {
+    pub fn load(file_name: impl AsRef<std::path::Path>) -> io::Result<NavGraph> {
+        NavGraph::parse(&fs::read_to_string(file_name)?)
+    }
+
+    /**
+    Reads a graph from `text` in the format `load` reads from a file. Anything we can't make sense of is an
+    `InvalidData` error that says which line it's on.
+     */
+    pub fn parse(text: &str) -> io::Result<NavGraph> {
+        let mut graph = NavGraph {
+            names: Vec::new(),
+            positions: Vec::new(),
+            links: Vec::new(),
+            cost_per_distance: 1.0,
+        };
+
+        for (line_number, line) in text.lines().enumerate() {
+            let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData,
+                                                           format!("line {}: {}", line_number + 1, message));
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            match words.as_slice() {
+                [] => {}
+                [first, ..] if first.starts_with('#') => {}
+                ["point", name, x, y] => {
+                    if graph.index_of(name).is_some() {
+                        return Err(invalid(format!("there's already a point called {}", name)));
+                    }
+                    let number = |word: &str| word.parse().ok()
+                        .filter(|value: &f32| value.is_finite())
+                        .ok_or_else(|| invalid(format!("{} isn't a number", word)));
+                    let (x, y) = (number(x)?, number(y)?);
+                    graph.names.push(name.to_string());
+                    graph.positions.push((x, y));
+                    graph.links.push(Vec::new());
+                }
+                [kind @ ("link" | "arc"), from, to, rest @ ..] if rest.len() <= 1 => {
+                    let from = graph.index_of(from).ok_or_else(|| invalid(format!("no point called {}", from)))?;
+                    let to = graph.index_of(to).ok_or_else(|| invalid(format!("no point called {}", to)))?;
+                    let cost = match rest.first() {
+                        Some(cost) => cost.parse().ok()
+                            .filter(|&cost: &i32| cost >= 0)
+                            .ok_or_else(|| invalid(format!("{} isn't a cost", cost)))?,
+                        None => graph.distance(from, to).ceil() as i32,
+                    };
+                    graph.links[from].push((to, cost));
+                    if *kind == "link" {
+                        graph.links[to].push((from, cost));
+                    }
+                }
+                _ => return Err(invalid(format!("can't read \"{}\"", line.trim()))),
+            }
+        }
+
+        graph.cost_per_distance = graph.links.iter()
+            .enumerate()
+            .flat_map(|(from, links)| links.iter().map(move |&(to, cost)| (from, to, cost)))
+            .filter(|&(from, to, _)| graph.distance(from, to) > 0.0)
+            .map(|(from, to, cost)| cost as f32 / graph.distance(from, to))
+            .fold(1.0, f32::min);
+        Ok(graph)
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|point_name| point_name == name)
+    }
+
+    /**
+    The point closest to (x, y), if the graph has any points at all.
+     */
+    pub fn nearest(&self, x: f32, y: f32) -> Option<usize> {
+        (0..self.positions.len()).min_by(|&a, &b| {
+            let distance = |index: usize| (self.positions[index].0 - x).hypot(self.positions[index].1 - y);
+            distance(a).total_cmp(&distance(b))
+        })
+    }
+
+    /**
+    The cheapest way from the point `from` to the point `to`, found by the same search the grid uses.
+     */
+    pub fn find_path(&self, from: usize, to: usize, limits: &SearchLimits) -> Result<Path, PathError> {
+        if from >= self.positions.len() {
+            return Err(PathError::StartOutOfBounds);
+        }
+        if to >= self.positions.len() {
+            return Err(PathError::GoalOutOfBounds);
+        }
+        GraphSearch::new(&[from], &[to], self, limits, SearchScratch::default()).finish(self)
+    }
+
+    fn distance(&self, from: usize, to: usize) -> f32 {
+        let ((from_x, from_y), (to_x, to_y)) = (self.positions[from], self.positions[to]);
+        (to_x - from_x).hypot(to_y - from_y)
+    }
+}
+
+impl SearchSpace for NavGraph {
+    type State = usize;
+
+    fn state_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn index(&self, state: usize) -> usize {
+        state
+    }
+
+    fn state(&self, index: usize) -> usize {
+        index
+    }
+
+    fn successors(&self, state: usize) -> Vec<(usize, i32)> {
+        self.links[state].clone()
+    }
+
+    fn heuristic(&self, state: usize, goal: usize) -> i32 {
+        (self.distance(state, goal) * self.cost_per_distance).floor() as i32
+    }
+
+    fn position(&self, state: usize) -> Option<(f32, f32)> {
+        Some(self.positions[state])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAPH: &str = "\
+# a square with a shortcut that only goes one way
+point a 0 0
+point b 4 0
+point c 4 3
+point d 0 3
+
+link a b
+link b c
+link c d 2
+arc d a 1
+arc a c 1
+";
+
+    #[test]
+    fn the_graph_reads_and_searches_like_the_file_says() {
+        let graph = NavGraph::parse(GRAPH).unwrap();
+        let limits = SearchLimits::default();
+        let (a, c, d) = (graph.index_of("a").unwrap(), graph.index_of("c").unwrap(), graph.index_of("d").unwrap());
+
+        // a link without a cost costs its length, and the arcs only go one way
+        assert_eq!(graph.links[a], vec![(graph.index_of("b").unwrap(), 4), (c, 1)]);
+        assert_eq!(graph.find_path(a, c, &limits).unwrap().nodes, vec![a, c]);
+        assert_eq!(graph.find_path(c, a, &limits).unwrap().cost, 2 + 1);
+        assert_eq!(graph.find_path(a, d, &limits).unwrap().cost, 1 + 2);
+
+        // and the heuristic never promises less than the search finds, even with links cheaper than their length
+        for from in 0..graph.state_count() {
+            for to in 0..graph.state_count() {
+                let cost = graph.find_path(from, to, &limits).unwrap().cost;
+                assert!(graph.heuristic(from, to) <= cost, "{} to {}", graph.names[from], graph.names[to]);
+            }
+        }
+    }
+
+    #[test]
+    fn mistakes_say_which_line_they_are_on() {
+        let error = |text: &str| NavGraph::parse(text).err().unwrap().to_string();
+        assert_eq!(error("point a 0 0\npoint a 1 1"), "line 2: there's already a point called a");
+        assert_eq!(error("point a 0 zero"), "line 1: zero isn't a number");
+        assert_eq!(error("point a 0 0\npoint b inf 1"), "line 2: inf isn't a number");
+        assert_eq!(error("point a NaN 0"), "line 1: NaN isn't a number");
+        assert_eq!(error("point a 0 0\n\nlink a b"), "line 3: no point called b");
+        assert_eq!(error("point a 0 0\npoint b 1 1\nlink a b -2"), "line 3: -2 isn't a cost");
+        assert_eq!(error("teleport a b"), "line 1: can't read \"teleport a b\"");
+    }
+
+    #[test]
+    fn the_shipped_graph_reads_and_every_point_reaches_the_others() {
+        let text = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/nav_graph.txt")).unwrap();
+        let graph = NavGraph::parse(&text).unwrap();
+        let limits = SearchLimits::default();
+
+        assert_eq!(graph.state_count(), 8);
+        for from in 0..graph.state_count() {
+            for to in 0..graph.state_count() {
+                assert!(graph.find_path(from, to, &limits).is_ok(), "{} to {}", graph.names[from], graph.names[to]);
+            }
+        }
+    }
+}
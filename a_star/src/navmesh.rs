@@ -0,0 +1,415 @@
+use crate::geometry::{distance, edge_cost, heuristic_cost, path_length};
+use crate::limits::SearchLimits;
+use crate::path::PathError;
+use crate::scratch::SearchScratch;
+use crate::sliced::GraphSearch;
+use crate::space::SearchSpace;
+use crate::visibility::ObstacleMap;
+use crate::{node_index, Node, MAP_HEIGHT, MAP_WIDTH};
+
+
+// the two ends of a straight line
+type Segment = ((f32, f32), (f32, f32));
+
+
+/**
+A rectangle of free nodes, from `min` to `max` with both corners included.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+}
+
+impl Region {
+    /**
+    The middle of the region, in the same positions as node centers.
+     */
+    pub fn center(&self) -> (f32, f32) {
+        ((self.min.0 + self.max.0) as f32 / 2.0, (self.min.1 + self.max.1) as f32 / 2.0)
+    }
+
+    /**
+    The corners of the outline of the region, which runs along the outer sides of its nodes.
+     */
+    pub fn outline(&self) -> [(f32, f32); 4] {
+        let (left, top) = (self.min.0 as f32 - 0.5, self.min.1 as f32 - 0.5);
+        let (right, bottom) = (self.max.0 as f32 + 0.5, self.max.1 as f32 + 0.5);
+        [(left, top), (right, top), (right, bottom), (left, bottom)]
+    }
+}
+
+
+/**
+Where two regions touch: the stretch of their shared side that we can walk through from one into the other.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshPortal {
+    pub regions: (usize, usize),
+    pub ends: Segment,
+}
+
+impl MeshPortal {
+    fn middle(&self) -> (f32, f32) {
+        let ((x1, y1), (x2, y2)) = self.ends;
+        ((x1 + x2) / 2.0, (y1 + y2) / 2.0)
+    }
+}
+
+
+/**
+A path through a `NavMesh`: the portals it goes through, the points left after pulling it tight and how long it is in
+nodes.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshPath {
+    pub portals: Vec<usize>,
+    pub points: Vec<(f32, f32)>,
+    pub length: f32,
+}
+
+
+/**
+A navigation mesh for one layer of the map. The free nodes are split into as few rectangles as a greedy sweep finds,
+and the rectangles that touch are joined by portals. A search over the portals finds which rectangles a path goes
+through, and the funnel algorithm then pulls the path tight through them, so it cuts straight across open ground
+instead of following the grid.
+
+Like the visibility graph the mesh only knows about obstacles, not walls, one-way edges, terrain or portals between
+nodes.
+ */
+pub struct NavMesh {
+    pub layer: i32,
+    pub regions: Vec<Region>,
+    pub portals: Vec<MeshPortal>,
+    // the portals around every region
+    region_portals: Vec<Vec<usize>>,
+    // the region every free node on the layer is in
+    region_of: Vec<Option<usize>>,
+    obstacles: ObstacleMap,
+}
+
+impl NavMesh {
+    pub fn build(layer: i32, nodes: &[Node]) -> NavMesh {
+        let free = |x: i32, y: i32| !nodes[node_index(x, y, layer)].obstacle;
+        let cell = |x: i32, y: i32| (y * MAP_WIDTH + x) as usize;
+        let mut region_of = vec![None; (MAP_WIDTH * MAP_HEIGHT) as usize];
+        let mut regions = Vec::new();
+
+        // sweep the layer row by row. The first free node that isn't in a region yet starts a new one, which grows
+        // right as far as it can and then down for as long as the whole row below is free too.
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                if !free(x, y) || region_of[cell(x, y)].is_some() {
+                    continue;
+                }
+                let available = |x: i32, y: i32| free(x, y) && region_of[cell(x, y)].is_none();
+
+                let mut right = x;
+                while right + 1 < MAP_WIDTH && available(right + 1, y) {
+                    right += 1;
+                }
+                let mut bottom = y;
+                while bottom + 1 < MAP_HEIGHT && (x..=right).all(|column| available(column, bottom + 1)) {
+                    bottom += 1;
+                }
+
+                let region = Region { min: (x, y), max: (right, bottom) };
+                for region_y in y..=bottom {
+                    for region_x in x..=right {
+                        region_of[cell(region_x, region_y)] = Some(regions.len());
+                    }
+                }
+                regions.push(region);
+            }
+        }
+
+        let mut portals = Vec::new();
+        for (a, first) in regions.iter().enumerate() {
+            for (b, second) in regions.iter().enumerate().skip(a + 1) {
+                if let Some(ends) = shared_side(first, second) {
+                    portals.push(MeshPortal { regions: (a, b), ends });
+                }
+            }
+        }
+
+        let mut region_portals = vec![Vec::new(); regions.len()];
+        for (index, portal) in portals.iter().enumerate() {
+            region_portals[portal.regions.0].push(index);
+            region_portals[portal.regions.1].push(index);
+        }
+
+        NavMesh { layer, regions, portals, region_portals, region_of, obstacles: ObstacleMap::new(layer, nodes) }
+    }
+
+    /**
+    The region the point is in, or None when it's off the map or in an obstacle.
+     */
+    pub fn region_at(&self, point: (f32, f32)) -> Option<usize> {
+        let (x, y) = self.obstacles.node_at(point)?;
+        self.region_of[(y * MAP_WIDTH + x) as usize]
+    }
+
+    /**
+    The path from `start` to `goal` through the mesh, pulled tight.
+     */
+    pub fn find_path(&self, start: (f32, f32), goal: (f32, f32), limits: &SearchLimits) -> Result<MeshPath, PathError> {
+        // every free node is in a region, so once both ends are on free nodes they are in regions too
+        self.obstacles.check_endpoints(start, goal)?;
+        let (start_region, goal_region) = match (self.region_at(start), self.region_at(goal)) {
+            (Some(start_region), Some(goal_region)) => (start_region, goal_region),
+            _ => unreachable!("every free node is in a region"),
+        };
+
+        let query = Query { mesh: self, start, goal, start_region, goal_region };
+        // pulling a corridor tight only makes sense when it reaches the goal, so we never settle for a partial one
+        let limits = SearchLimits { allow_partial: false, ..*limits };
+        let corridor = GraphSearch::new(&[Crossing::Start], &[Crossing::Goal], &query, &limits,
+                                        SearchScratch::default())
+            .finish(&query)?;
+
+        // the portals in the order we go through them, each with the side we come from on the right and the side we
+        // head for on the left
+        let mut region = start_region;
+        let mut portals = Vec::new();
+        let mut gates = vec![(start, start)];
+        for crossing in &corridor.nodes {
+            if let Crossing::Portal(index, into) = *crossing {
+                gates.push(self.oriented_ends(index, region, into));
+                portals.push(index);
+                region = into;
+            }
+        }
+        gates.push((goal, goal));
+
+        let points = pull_string(&gates);
+        let length = path_length(&points);
+        Ok(MeshPath { portals, points, length })
+    }
+
+    /**
+    The ends of a portal as (left, right) for someone walking through it from the region `from` into `into`.
+     */
+    fn oriented_ends(&self, portal: usize, from: usize, into: usize) -> Segment {
+        let (from_x, from_y) = self.regions[from].center();
+        let (into_x, into_y) = self.regions[into].center();
+        let (middle_x, middle_y) = self.portals[portal].middle();
+        let (first, second) = self.portals[portal].ends;
+        let side = (into_x - from_x) * (first.1 - middle_y) - (into_y - from_y) * (first.0 - middle_x);
+        if side > 0.0 { (first, second) } else { (second, first) }
+    }
+}
+
+
+/**
+The ends of the side two regions share, if they share one that's longer than a point.
+ */
+fn shared_side(a: &Region, b: &Region) -> Option<Segment> {
+    let overlap = |a_min: i32, a_max: i32, b_min: i32, b_max: i32| {
+        let (low, high) = (a_min.max(b_min), a_max.min(b_max));
+        if low <= high { Some((low as f32 - 0.5, high as f32 + 0.5)) } else { None }
+    };
+
+    if a.max.0 + 1 == b.min.0 || b.max.0 + 1 == a.min.0 {
+        let x = if a.max.0 + 1 == b.min.0 { b.min.0 } else { a.min.0 } as f32 - 0.5;
+        let (top, bottom) = overlap(a.min.1, a.max.1, b.min.1, b.max.1)?;
+        return Some(((x, top), (x, bottom)));
+    }
+    if a.max.1 + 1 == b.min.1 || b.max.1 + 1 == a.min.1 {
+        let y = if a.max.1 + 1 == b.min.1 { b.min.1 } else { a.min.1 } as f32 - 0.5;
+        let (left, right) = overlap(a.min.0, a.max.0, b.min.0, b.max.0)?;
+        return Some(((left, y), (right, y)));
+    }
+    None
+}
+
+
+/**
+The funnel algorithm. `gates` are the (left, right) ends of every portal along the corridor, with the start as the
+first gate and the goal as the last. We keep a funnel from the last corner of the path through the gates, narrowing
+it at every gate, and when one side of the funnel crosses over the other, the corner it crossed becomes the next point
+of the path.
+ */
+fn pull_string(gates: &[Segment]) -> Vec<(f32, f32)> {
+    // twice the signed area of the triangle a, b, c. Positive when c is to the right of the line from a to b.
+    let area = |a: (f32, f32), b: (f32, f32), c: (f32, f32)| (c.0 - a.0) * (b.1 - a.1) - (b.0 - a.0) * (c.1 - a.1);
+    let same = |a: (f32, f32), b: (f32, f32)| distance(a, b) < 1e-4;
+
+    let mut points = vec![gates[0].0];
+    let (mut apex, mut left, mut right) = (gates[0].0, gates[0].0, gates[0].1);
+    let (mut left_gate, mut right_gate) = (0, 0);
+
+    let mut gate = 1;
+    while gate < gates.len() {
+        let (next_left, next_right) = gates[gate];
+
+        // try to narrow the right side of the funnel
+        if area(apex, right, next_right) <= 0.0 {
+            if same(apex, right) || area(apex, left, next_right) > 0.0 {
+                right = next_right;
+                right_gate = gate;
+            } else {
+                // the right side crossed the left one, so the left one is a corner of the path
+                points.push(left);
+                apex = left;
+                (right, right_gate) = (left, left_gate);
+                gate = left_gate + 1;
+                continue;
+            }
+        }
+
+        // and the left side
+        if area(apex, left, next_left) >= 0.0 {
+            if same(apex, left) || area(apex, right, next_left) < 0.0 {
+                left = next_left;
+                left_gate = gate;
+            } else {
+                points.push(right);
+                apex = right;
+                (left, left_gate) = (right, right_gate);
+                gate = right_gate + 1;
+                continue;
+            }
+        }
+        gate += 1;
+    }
+
+    let goal = gates[gates.len() - 1].0;
+    if !same(points[points.len() - 1], goal) {
+        points.push(goal);
+    }
+    points
+}
+
+
+/**
+The states of the search through the mesh: the start, going through a portal into one of its two regions, and the
+goal.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Crossing {
+    Start,
+    Portal(usize, usize),
+    Goal,
+}
+
+struct Query<'a> {
+    mesh: &'a NavMesh,
+    start: (f32, f32),
+    goal: (f32, f32),
+    start_region: usize,
+    goal_region: usize,
+}
+
+impl Query<'_> {
+    fn position(&self, crossing: Crossing) -> (f32, f32) {
+        match crossing {
+            Crossing::Start => self.start,
+            Crossing::Portal(index, _) => self.mesh.portals[index].middle(),
+            Crossing::Goal => self.goal,
+        }
+    }
+}
+
+impl SearchSpace for Query<'_> {
+    type State = Crossing;
+
+    fn state_count(&self) -> usize {
+        self.mesh.portals.len() * 2 + 2
+    }
+
+    // every portal has two states, one for each way through it, and the start and goal come after them
+    fn index(&self, crossing: Crossing) -> usize {
+        match crossing {
+            Crossing::Portal(index, into) => index * 2 + usize::from(into == self.mesh.portals[index].regions.1),
+            Crossing::Start => self.mesh.portals.len() * 2,
+            Crossing::Goal => self.mesh.portals.len() * 2 + 1,
+        }
+    }
+
+    fn state(&self, index: usize) -> Crossing {
+        match index.checked_sub(self.mesh.portals.len() * 2) {
+            None => {
+                let (first, second) = self.mesh.portals[index / 2].regions;
+                Crossing::Portal(index / 2, if index.is_multiple_of(2) { first } else { second })
+            }
+            Some(0) => Crossing::Start,
+            Some(_) => Crossing::Goal,
+        }
+    }
+
+    fn successors(&self, crossing: Crossing) -> Vec<(Crossing, i32)> {
+        let (region, came_through) = match crossing {
+            Crossing::Start => (self.start_region, None),
+            Crossing::Portal(index, into) => (into, Some(index)),
+            Crossing::Goal => return Vec::new(),
+        };
+        let from = self.position(crossing);
+
+        let mut successors: Vec<(Crossing, i32)> = self.mesh.region_portals[region].iter()
+            .filter(|&&portal| Some(portal) != came_through)
+            .map(|&portal| {
+                let (first, second) = self.mesh.portals[portal].regions;
+                let next = Crossing::Portal(portal, if first == region { second } else { first });
+                (next, edge_cost(from, self.position(next)))
+            })
+            .collect();
+        if region == self.goal_region {
+            successors.push((Crossing::Goal, edge_cost(from, self.goal)));
+        }
+        successors
+    }
+
+    fn heuristic(&self, crossing: Crossing, goal: Crossing) -> i32 {
+        heuristic_cost(self.position(crossing), self.position(goal))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visibility::VisibilityGraph;
+    use crate::TestMap;
+
+    #[test]
+    fn regions_cover_the_free_nodes_and_paths_stay_clear_of_obstacles() {
+        // a wall from the top down to row 11 and a block off to the side of it
+        let map = TestMap::new(&["       #",
+                                 "       #",
+                                 "       #",
+                                 "       #",
+                                 "       #",
+                                 "       #     ##",
+                                 "       #     ##",
+                                 "       #",
+                                 "       #",
+                                 "       #",
+                                 "       #",
+                                 "       #"]);
+        let mesh = NavMesh::build(0, &map.nodes);
+        let limits = SearchLimits::default();
+
+        // every free node is in exactly one region, and no obstacle is in any
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                let containing = mesh.regions.iter()
+                    .filter(|region| (region.min.0..=region.max.0).contains(&x)
+                        && (region.min.1..=region.max.1).contains(&y))
+                    .count();
+                let free = !map.nodes[node_index(x, y, 0)].obstacle;
+                assert_eq!(containing, free as usize, "node ({}, {})", x, y);
+            }
+        }
+
+        // the funnel pulls the path tight around the bottom of the wall, the same as the truly shortest path
+        let (start, goal) = ((2.0, 3.0), (13.0, 3.0));
+        let path = mesh.find_path(start, goal, &limits).unwrap();
+        assert_eq!(path.points, [start, (6.5, 11.5), (7.5, 11.5), goal]);
+        assert!(path.points.windows(2).all(|step| mesh.obstacles.can_see(step[0], step[1])));
+        let shortest = VisibilityGraph::build(0, &map.nodes).find_path(start, goal, &limits).unwrap();
+        assert!((path.length - shortest.length).abs() < 1e-3);
+
+        assert_eq!(mesh.find_path(start, (13.0, 6.0), &limits).map(|path| path.length), Err(PathError::GoalBlocked));
+    }
+}
@@ -0,0 +1,400 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::components::Components;
+use crate::path::{check_endpoints, PathError};
+use crate::{can_step, neighbor_at, Connectivity, Node, MAP_HEIGHT, MAP_WIDTH};
+
+
+// costs in the oriented search are in tenths of a grid step on plain ground. That way a diagonal step (roughly 1.4) and
+// the turn costs can stay whole numbers like everywhere else. Like on the grid, a move costs more the more the terrain
+// we move onto costs.
+pub const STRAIGHT_COST: i32 = 10;
+pub const DIAGONAL_COST: i32 = 14;
+
+
+/**
+The eight directions a vehicle can face, clockwise from north. Diagonal headings sit 45° between their neighbors.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Heading {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Heading {
+    pub const ALL: [Heading; 8] = [
+        Heading::North,
+        Heading::NorthEast,
+        Heading::East,
+        Heading::SouthEast,
+        Heading::South,
+        Heading::SouthWest,
+        Heading::West,
+        Heading::NorthWest,
+    ];
+
+    pub fn from_index(index: usize) -> Heading {
+        Heading::ALL[index % Heading::ALL.len()]
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /**
+    The (x, y) step a move in this heading takes. y grows downwards, the same as on screen.
+     */
+    pub fn offset(&self) -> (i32, i32) {
+        match self {
+            Heading::North => (0, -1),
+            Heading::NorthEast => (1, -1),
+            Heading::East => (1, 0),
+            Heading::SouthEast => (1, 1),
+            Heading::South => (0, 1),
+            Heading::SouthWest => (-1, 1),
+            Heading::West => (-1, 0),
+            Heading::NorthWest => (-1, -1),
+        }
+    }
+
+    /**
+    The heading as an angle in radians, with 0 facing east and angles growing clockwise on screen like a `Pose`.
+     */
+    pub fn angle(&self) -> f32 {
+        (self.index() as f32 - 2.0) * std::f32::consts::FRAC_PI_4
+    }
+
+    pub fn is_diagonal(&self) -> bool {
+        self.index() % 2 == 1
+    }
+
+    /**
+    Returns the heading `steps` lots of 45° clockwise from this one.
+     */
+    pub fn rotated(&self, steps: usize) -> Heading {
+        Heading::from_index(self.index() + steps)
+    }
+
+    /**
+    How many 45° steps we have to turn through to face `other`, whichever way round is shorter. Between 0 and 4.
+     */
+    pub fn turn_steps(&self, other: Heading) -> usize {
+        let clockwise = (other.index() + 8 - self.index()) % 8;
+        clockwise.min(8 - clockwise)
+    }
+}
+
+
+/**
+What it costs to turn on the spot, in the same tenths of a step as the moves. A 135° turn costs a 90° turn plus a 45°
+turn.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TurnCosts {
+    pub deg_45: i32,
+    pub deg_90: i32,
+    pub deg_180: i32,
+}
+
+impl Default for TurnCosts {
+    fn default() -> Self {
+        TurnCosts { deg_45: 5, deg_90: 15, deg_180: 40 }
+    }
+}
+
+impl TurnCosts {
+    pub fn cost(&self, from: Heading, to: Heading) -> i32 {
+        match from.turn_steps(to) {
+            0 => 0,
+            1 => self.deg_45,
+            2 => self.deg_90,
+            3 => self.deg_90 + self.deg_45,
+            _ => self.deg_180,
+        }
+    }
+}
+
+
+/**
+The result of an oriented search. Every step is the node we are on and the heading we face there, from the start to
+the goal. The total cost is split into the part spent moving and the part spent turning.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrientedPath {
+    pub steps: Vec<(usize, Heading)>,
+    pub move_cost: i32,
+    pub turn_cost: i32,
+}
+
+
+/**
+A* over (node, heading) states instead of plain nodes. Each move first turns to face the node it moves to, paying the
+turn cost, and then moves there. Going through a portal keeps the heading we had. If `start_heading` is None the
+vehicle can start out facing whichever way suits it best.
+ */
+pub fn oriented_a_star(start_index: usize,
+                       start_heading: Option<Heading>,
+                       goal_index: usize,
+                       nodes: &[Node],
+                       connectivity: &Connectivity,
+                       components: &Components,
+                       turn_costs: &TurnCosts) -> Result<OrientedPath, PathError> {
+    check_endpoints(start_index, goal_index, nodes)?;
+    if !components.same_component(start_index, goal_index) {
+        return Err(PathError::Unreachable);
+    }
+
+    // every node has one state per heading, stored at node_index * 8 + heading_index
+    let state_count = nodes.len() * Heading::ALL.len();
+    let mut cost_so_far = vec![i32::MAX; state_count];
+    let mut turn_cost_so_far = vec![0; state_count];
+    let mut parent: Vec<Option<usize>> = vec![None; state_count];
+    let mut open_set = BinaryHeap::new();
+
+    let start_headings = match start_heading {
+        Some(heading) => vec![heading],
+        None => Heading::ALL.to_vec(),
+    };
+    let start_heuristic = heuristic(start_index, goal_index, nodes, connectivity);
+    if start_heuristic == i32::MAX {
+        return Err(PathError::Unreachable);
+    }
+    for heading in start_headings {
+        let state = start_index * 8 + heading.index();
+        cost_so_far[state] = 0;
+        open_set.push(Reverse((start_heuristic, 0, state)));
+    }
+
+    // we don't update entries already in the open set. Instead we push the state again with its new cost and skip the
+    // out of date entry when it comes off the heap.
+    while let Some(Reverse((_, cost, state))) = open_set.pop() {
+        if cost > cost_so_far[state] {
+            continue;
+        }
+
+        let (index, heading) = (state / 8, Heading::from_index(state % 8));
+        if index == goal_index {
+            return Ok(construct_oriented_path(state, &parent, &cost_so_far, &turn_cost_so_far));
+        }
+
+        let moves = successors(index, heading, nodes, connectivity, turn_costs);
+        for (next_index, next_heading, move_cost, turn_cost) in moves {
+            let next_state = next_index * 8 + next_heading.index();
+            let tentative_cost = cost + move_cost + turn_cost;
+
+            if tentative_cost < cost_so_far[next_state] {
+                let next_heuristic = heuristic(next_index, goal_index, nodes, connectivity);
+                if next_heuristic == i32::MAX {
+                    continue;
+                }
+
+                cost_so_far[next_state] = tentative_cost;
+                turn_cost_so_far[next_state] = turn_cost_so_far[state] + turn_cost;
+                parent[next_state] = Some(state);
+                open_set.push(Reverse((tentative_cost + next_heuristic, tentative_cost, next_state)));
+            }
+        }
+    }
+
+    Err(PathError::Unreachable)
+}
+
+
+/**
+Returns `(node, heading, move cost, turn cost)` for every state we can reach in one move from `index` while facing
+`heading`. The move cost is the length of the step times the cost of the terrain we step onto. We can move to any of
+the eight surrounding nodes, but we don't squeeze diagonally between two obstacles or around the corner of one.
+A diagonal move also needs both ways around the corner to be open in the direction we are going, so it never sneaks
+past a one-way edge.
+ */
+fn successors(index: usize,
+              heading: Heading,
+              nodes: &[Node],
+              connectivity: &Connectivity,
+              turn_costs: &TurnCosts) -> Vec<(usize, Heading, i32, i32)> {
+    let node = &nodes[index];
+    let topology = connectivity.topology;
+    let is_free = |dx: i32, dy: i32| {
+        neighbor_at(node, dx, dy, topology).filter(|&neighbor_index| !nodes[neighbor_index].obstacle)
+    };
+
+    let mut successors = Vec::new();
+    for next_heading in Heading::ALL {
+        let (dx, dy) = next_heading.offset();
+        let next_index = match is_free(dx, dy) {
+            Some(next_index) => next_index,
+            None => continue,
+        };
+
+        let move_cost = if next_heading.is_diagonal() {
+            let around_corner = |corner: Option<usize>| corner.is_some_and(|corner| {
+                can_step(node, &nodes[corner], topology) && can_step(&nodes[corner], &nodes[next_index], topology)
+            });
+            if !around_corner(is_free(dx, 0)) || !around_corner(is_free(0, dy)) {
+                continue;
+            }
+            DIAGONAL_COST
+        } else {
+            if !can_step(node, &nodes[next_index], topology) {
+                continue;
+            }
+            STRAIGHT_COST
+        };
+
+        let move_cost = move_cost * nodes[next_index].terrain.cost();
+        successors.push((next_index, next_heading, move_cost, turn_costs.cost(heading, next_heading)));
+    }
+
+    for (from, to, cost) in connectivity.portal_links() {
+        if from == index && !nodes[to].obstacle {
+            successors.push((to, heading, cost * STRAIGHT_COST, 0));
+        }
+    }
+
+    successors
+}
+
+
+/**
+The octile distance, which is the exact cost of the cheapest path between two nodes on an empty layer when we can
+move diagonally. Ignores the layer the nodes are on.
+ */
+fn octile_distance(from: &Node, to: &Node, connectivity: &Connectivity) -> i32 {
+    let dif_x = connectivity.topology.delta(from.x, to.x, MAP_WIDTH).abs();
+    let dif_y = connectivity.topology.delta(from.y, to.y, MAP_HEIGHT).abs();
+    STRAIGHT_COST * dif_x.max(dif_y) + (DIAGONAL_COST - STRAIGHT_COST) * dif_x.min(dif_y)
+}
+
+
+/**
+The same lower bound as `Node::heuristic`, but measured with the octile distance and in tenths of a step. Turning is
+left out and every node is taken to be plain ground, which keeps it from ever overestimating.
+ */
+fn heuristic(index: usize, goal_index: usize, nodes: &[Node], connectivity: &Connectivity) -> i32 {
+    let (node, goal) = (&nodes[index], &nodes[goal_index]);
+    let mut best = if node.z == goal.z { octile_distance(node, goal, connectivity) } else { i32::MAX };
+
+    let to_entrance = connectivity.portal_links()
+        .filter(|&(from, _, _)| nodes[from].z == node.z)
+        .map(|(from, _, cost)| octile_distance(node, &nodes[from], connectivity) + cost * STRAIGHT_COST)
+        .min();
+    let from_exit = connectivity.portal_links()
+        .filter(|&(_, to, _)| nodes[to].z == goal.z)
+        .map(|(_, to, _)| octile_distance(&nodes[to], goal, connectivity))
+        .min();
+
+    if let (Some(to_entrance), Some(from_exit)) = (to_entrance, from_exit) {
+        best = best.min(to_entrance + from_exit);
+    }
+    best
+}
+
+
+fn construct_oriented_path(goal_state: usize,
+                           parent: &[Option<usize>],
+                           cost_so_far: &[i32],
+                           turn_cost_so_far: &[i32]) -> OrientedPath {
+    let mut steps = Vec::new();
+    let mut current_state = Some(goal_state);
+    while let Some(state) = current_state {
+        steps.push((state / 8, Heading::from_index(state % 8)));
+        current_state = parent[state];
+    }
+    steps.reverse();
+
+    OrientedPath {
+        steps,
+        move_cost: cost_so_far[goal_state] - turn_cost_so_far[goal_state],
+        turn_cost: turn_cost_so_far[goal_state],
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::Terrain;
+    use crate::{node_index, open_map, TestMap};
+
+    fn search(map: &TestMap, start: (i32, i32), heading: Heading, goal: (i32, i32), turn_costs: &TurnCosts)
+              -> OrientedPath {
+        let (start, goal) = (node_index(start.0, start.1, 0), node_index(goal.0, goal.1, 0));
+        oriented_a_star(start, Some(heading), goal, &map.nodes, &map.connectivity, &map.components, turn_costs)
+            .unwrap()
+    }
+
+    /**
+    Checks that every step of `path` faces the way it moved, and that the moves and turns add up to what the path says
+    they cost.
+     */
+    fn check_steps(path: &OrientedPath, map: &TestMap, turn_costs: &TurnCosts) {
+        let (mut move_cost, mut turn_cost) = (0, 0);
+        for step in path.steps.windows(2) {
+            let ((from, from_heading), (to, heading)) = (step[0], step[1]);
+            let (from, to) = (&map.nodes[from], &map.nodes[to]);
+            assert_eq!((to.x - from.x, to.y - from.y), heading.offset());
+            let length = if heading.is_diagonal() { DIAGONAL_COST } else { STRAIGHT_COST };
+            move_cost += length * to.terrain.cost();
+            turn_cost += turn_costs.cost(from_heading, heading);
+        }
+        assert_eq!((path.move_cost, path.turn_cost), (move_cost, turn_cost));
+    }
+
+    #[test]
+    fn forced_turns_cost_what_the_turn_costs_say() {
+        let turn_costs = TurnCosts { deg_45: 3, deg_90: 7, deg_180: 20 };
+        // a corridor that runs east and then turns south, too narrow to cut the corner
+        let corner = TestMap::new(&["#####",
+                                    "#...#",
+                                    "###.#",
+                                    "###.#",
+                                    "#####"]);
+        let path = search(&corner, (1, 1), Heading::East, (3, 3), &turn_costs);
+        assert_eq!((path.move_cost, path.turn_cost), (4 * STRAIGHT_COST, turn_costs.deg_90));
+        assert_eq!(path.steps.last(), Some(&(node_index(3, 3, 0), Heading::South)));
+        check_steps(&path, &corner, &turn_costs);
+
+        // a dead end, where the only way to the goal behind us is to turn right around
+        let dead_end = TestMap::new(&["####",
+                                      "#..#",
+                                      "####"]);
+        let path = search(&dead_end, (2, 1), Heading::East, (1, 1), &turn_costs);
+        assert_eq!((path.move_cost, path.turn_cost), (STRAIGHT_COST, turn_costs.deg_180));
+        assert_eq!(path.steps, [(node_index(2, 1, 0), Heading::East), (node_index(1, 1, 0), Heading::West)]);
+    }
+
+    #[test]
+    fn moves_cost_what_the_terrain_costs() {
+        let turn_costs = TurnCosts::default();
+
+        // on all forest the same straight run costs twice what it does on plain ground
+        let plain = search(&TestMap::new(&[]), (2, 5), Heading::East, (8, 5), &turn_costs);
+        let mut forest = open_map();
+        forest.iter_mut().for_each(|node| node.terrain = Terrain::Forest);
+        let forest = TestMap::from_nodes(forest);
+        let forested = search(&forest, (2, 5), Heading::East, (8, 5), &turn_costs);
+        assert_eq!((plain.move_cost, plain.turn_cost), (6 * STRAIGHT_COST, 0));
+        assert_eq!((forested.move_cost, forested.turn_cost), (2 * plain.move_cost, 0));
+        check_steps(&forested, &forest, &turn_costs);
+
+        // and a patch of swamp on the straight line is cheaper to go around than through
+        let swamp = TestMap::new(&["",
+                                   "",
+                                   "",
+                                   "",
+                                   "    sss",
+                                   "    sss",
+                                   "    sss"]);
+        let detour = search(&swamp, (2, 5), Heading::East, (8, 5), &turn_costs);
+        assert!(detour.steps.iter().all(|&(index, _)| swamp.nodes[index].terrain != Terrain::Swamp));
+        assert!(detour.turn_cost > 0);
+        check_steps(&detour, &swamp, &turn_costs);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+
+use crate::Node;
+
+
+/**
+A path found by `a_star`: every node from the start to the goal, both included, and what it costs to walk it.
+A partial path stops at the node that got closest to the goal instead, and `partial` says why it stopped there.
+Searches through other kinds of `SearchSpace` give paths of their own states instead of node indices.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Path<T = usize> {
+    pub nodes: Vec<T>,
+    pub cost: i32,
+    pub partial: Option<PathError>,
+}
+
+
+/**
+Why a path query didn't return a path.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathError {
+    StartOutOfBounds,
+    GoalOutOfBounds,
+    // the start or goal is an obstacle, so nothing can stand there
+    StartBlocked,
+    GoalBlocked,
+    // the start and goal are both fine, but nothing connects them
+    Unreachable,
+    // the search gave up before it could find the goal or prove there is no path
+    BudgetExceeded,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // these end up on screen, so they are kept short
+        let message = match self {
+            PathError::StartOutOfBounds => "start off map",
+            PathError::GoalOutOfBounds => "goal off map",
+            PathError::StartBlocked => "start blocked",
+            PathError::GoalBlocked => "goal blocked",
+            PathError::Unreachable => "unreachable",
+            PathError::BudgetExceeded => "over budget",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for PathError {}
+
+
+/**
+Checks the things every search needs from its start and goal before it does any work: they have to be on the map and
+they can't be obstacles.
+ */
+pub fn check_endpoints(start_index: usize, goal_index: usize, nodes: &[Node]) -> Result<(), PathError> {
+    if start_index >= nodes.len() {
+        return Err(PathError::StartOutOfBounds);
+    }
+    if goal_index >= nodes.len() {
+        return Err(PathError::GoalOutOfBounds);
+    }
+    if nodes[start_index].obstacle {
+        return Err(PathError::StartBlocked);
+    }
+    if nodes[goal_index].obstacle {
+        return Err(PathError::GoalBlocked);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node_index, TestMap};
+
+    #[test]
+    fn queries_say_why_they_have_no_path() {
+        let map = TestMap::new(&["",
+                                 "",
+                                 "",
+                                 "",
+                                 "    #",
+                                 "",
+                                 "",
+                                 "",
+                                 "         #",
+                                 "        #.#",
+                                 "         #"]);
+        let (free, blocked, walled_in) = (node_index(1, 1, 0), node_index(4, 4, 0), node_index(9, 9, 0));
+        let off_map = map.nodes.len();
+
+        assert_eq!(map.search(off_map, free), Err(PathError::StartOutOfBounds));
+        assert_eq!(map.search(free, off_map), Err(PathError::GoalOutOfBounds));
+        assert_eq!(map.search(blocked, free), Err(PathError::StartBlocked));
+        assert_eq!(map.search(free, blocked), Err(PathError::GoalBlocked));
+        assert_eq!(map.search(free, walled_in), Err(PathError::Unreachable));
+        assert_eq!(map.search(free, free).map(|path| (path.nodes, path.cost)), Ok((vec![free], 0)));
+    }
+}
@@ -0,0 +1,73 @@
+use olc_pixel_game_engine::Pixel;
+use olc_pixel_game_engine::CYAN;
+use olc_pixel_game_engine::MAGENTA;
+use olc_pixel_game_engine::DARK_YELLOW;
+
+
+/**
+The different kinds of connector we can place between two nodes. They differ in how much they cost to use by default,
+whether they work in both directions and the color we draw them in.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortalKind {
+    // slow, but you can walk them both ways
+    Stairs,
+    // quicker than the stairs and also goes both ways
+    Elevator,
+    // instant, but only takes you from its entrance to its exit
+    Teleporter,
+}
+
+impl PortalKind {
+    pub fn default_cost(&self) -> i32 {
+        match self {
+            PortalKind::Stairs => 4,
+            PortalKind::Elevator => 2,
+            PortalKind::Teleporter => 1,
+        }
+    }
+
+    pub fn two_way(&self) -> bool {
+        !matches!(self, PortalKind::Teleporter)
+    }
+
+    pub fn color(&self) -> Pixel {
+        match self {
+            PortalKind::Stairs => DARK_YELLOW,
+            PortalKind::Elevator => CYAN,
+            PortalKind::Teleporter => MAGENTA,
+        }
+    }
+}
+
+
+/**
+A connector that links two nodes that aren't next to each other on the grid. The nodes can be on different layers
+(stairs and elevators between floors) or far apart on the same layer (teleporters).
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Portal {
+    pub from: usize,
+    pub to: usize,
+    pub kind: PortalKind,
+    // what it costs to go through the portal, in the same units as a single step on the grid
+    pub cost: i32,
+}
+
+impl Portal {
+    pub fn new(from: usize, to: usize, kind: PortalKind) -> Portal {
+        Portal { from, to, kind, cost: kind.default_cost() }
+    }
+
+    /**
+    The directed `(from, to, cost)` links this portal adds to the graph. Two way portals add a link in each direction.
+     */
+    pub fn links(&self) -> impl Iterator<Item=(usize, usize, i32)> {
+        let back = if self.kind.two_way() { Some((self.to, self.from, self.cost)) } else { None };
+        std::iter::once((self.from, self.to, self.cost)).chain(back)
+    }
+
+    pub fn touches(&self, index: usize) -> bool {
+        self.from == index || self.to == index
+    }
+}
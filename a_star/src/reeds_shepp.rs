@@ -0,0 +1,336 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use crate::hybrid::Pose;
+
+
+/**
+One piece of a Reeds-Shepp path: turn left, turn right or drive straight for `length`, measured in turning radii.
+A negative length means driving it in reverse.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    Left(f32),
+    Straight(f32),
+    Right(f32),
+}
+
+impl Segment {
+    pub fn length(&self) -> f32 {
+        match self {
+            Segment::Left(length) | Segment::Straight(length) | Segment::Right(length) => *length,
+        }
+    }
+
+    /**
+    The curvature of the segment for a unit turning radius. Left turns increase theta.
+     */
+    pub fn curvature(&self) -> f32 {
+        match self {
+            Segment::Left(_) => 1.0,
+            Segment::Straight(_) => 0.0,
+            Segment::Right(_) => -1.0,
+        }
+    }
+
+    // swaps left and right, which mirrors the segment in the x axis
+    fn reflected(&self) -> Segment {
+        match *self {
+            Segment::Left(length) => Segment::Right(length),
+            Segment::Straight(length) => Segment::Straight(length),
+            Segment::Right(length) => Segment::Left(length),
+        }
+    }
+
+    // drives the segment the other way
+    fn time_flipped(&self) -> Segment {
+        match *self {
+            Segment::Left(length) => Segment::Left(-length),
+            Segment::Straight(length) => Segment::Straight(-length),
+            Segment::Right(length) => Segment::Right(-length),
+        }
+    }
+}
+
+
+// how far below zero a length can come out through rounding and still count as zero
+const ZERO: f32 = 1e-5;
+
+
+/**
+Finds the shortest Reeds-Shepp path from `start` to `goal` for a car that can't turn tighter than `turning_radius`,
+returned as its segments. We try every family, from curve-straight-curve up to the five piece curve-curve-straight-
+curve-curve, driven forwards or backwards, which between them reach any pose from any other. Only returns None when
+rounding throws every one of them out.
+ */
+pub fn shortest_path(start: &Pose, goal: &Pose, turning_radius: f32) -> Option<Vec<Segment>> {
+    // express the goal in the start's frame, scaled so the turning radius is 1
+    let (dx, dy) = (goal.x - start.x, goal.y - start.y);
+    let (sin, cos) = start.theta.sin_cos();
+    let x = (cos * dx + sin * dy) / turning_radius;
+    let y = (-sin * dx + cos * dy) / turning_radius;
+    let phi = wrap_angle(goal.theta - start.theta);
+
+    let mut candidates = Vec::new();
+    curve_straight_curve(x, y, phi, &mut candidates);
+    curve_curve_curve(x, y, phi, &mut candidates);
+    curve_curve_curve_curve(x, y, phi, &mut candidates);
+    curve_curve_straight_curve(x, y, phi, &mut candidates);
+    curve_curve_straight_curve_curve(x, y, phi, &mut candidates);
+
+    candidates.into_iter()
+        .min_by(|a, b| path_length(a).total_cmp(&path_length(b)))
+}
+
+/**
+The total length of a path in turning radii, counting reversing the same as driving forwards.
+ */
+pub fn path_length(segments: &[Segment]) -> f32 {
+    segments.iter().map(|segment| segment.length().abs()).sum()
+}
+
+/**
+Wraps an angle into the range (-PI, PI].
+ */
+pub fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped <= -PI { wrapped + 2.0 * PI } else { wrapped }
+}
+
+fn polar(x: f32, y: f32) -> (f32, f32) {
+    ((x * x + y * y).sqrt(), y.atan2(x))
+}
+
+
+// left, straight, left
+fn lsl(x: f32, y: f32, phi: f32) -> Option<[f32; 3]> {
+    let (u, t) = polar(x - phi.sin(), y - 1.0 + phi.cos());
+    if t >= -ZERO {
+        let v = wrap_angle(phi - t);
+        if v >= -ZERO {
+            return Some([t, u, v]);
+        }
+    }
+    None
+}
+
+// left, straight, right
+fn lsr(x: f32, y: f32, phi: f32) -> Option<[f32; 3]> {
+    let (u1, t1) = polar(x + phi.sin(), y - 1.0 - phi.cos());
+    let u1 = u1 * u1;
+    if u1 >= 4.0 {
+        let u = (u1 - 4.0).sqrt();
+        let t = wrap_angle(t1 + 2.0f32.atan2(u));
+        let v = wrap_angle(t - phi);
+        if t >= -ZERO && v >= -ZERO {
+            return Some([t, u, v]);
+        }
+    }
+    None
+}
+
+// left, right, left, with a change of direction between each
+fn lrl(x: f32, y: f32, phi: f32) -> Option<[f32; 3]> {
+    let (u1, t1) = polar(x - phi.sin(), y - 1.0 + phi.cos());
+    if u1 <= 4.0 {
+        let u = -2.0 * (0.25 * u1).asin();
+        let t = wrap_angle(t1 + 0.5 * u + PI);
+        let v = wrap_angle(phi - t + u);
+        if t >= -ZERO && u <= ZERO {
+            return Some([t, u, v]);
+        }
+    }
+    None
+}
+
+// the turns tau and omega at either end of the four piece paths, given the turns u and v in the middle
+fn tau_omega(u: f32, v: f32, xi: f32, eta: f32, phi: f32) -> (f32, f32) {
+    let delta = wrap_angle(u - v);
+    let a = u.sin() - delta.sin();
+    let b = u.cos() - delta.cos() - 1.0;
+    let t1 = (eta * a - xi * b).atan2(xi * a + eta * b);
+    let t2 = 2.0 * (delta.cos() - v.cos() - u.cos()) + 3.0;
+    let tau = if t2 < 0.0 { wrap_angle(t1 + PI) } else { wrap_angle(t1) };
+    (tau, wrap_angle(tau - u + v - phi))
+}
+
+// left, right, left, right, with the direction changing between the two middle turns
+fn lrlr_forward(x: f32, y: f32, phi: f32) -> Option<[f32; 4]> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let rho = 0.25 * (2.0 + (xi * xi + eta * eta).sqrt());
+    if rho <= 1.0 {
+        let u = rho.acos();
+        let (t, v) = tau_omega(u, -u, xi, eta, phi);
+        if t >= -ZERO && v <= ZERO {
+            return Some([t, u, -u, v]);
+        }
+    }
+    None
+}
+
+// left, right, left, right, with the direction changing after the first turn and before the last
+fn lrlr_backward(x: f32, y: f32, phi: f32) -> Option<[f32; 4]> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let rho = (20.0 - xi * xi - eta * eta) / 16.0;
+    if (0.0..=1.0).contains(&rho) {
+        let u = -rho.acos();
+        if u >= -0.5 * PI {
+            let (t, v) = tau_omega(u, u, xi, eta, phi);
+            if t >= -ZERO && v >= -ZERO {
+                return Some([t, u, u, v]);
+            }
+        }
+    }
+    None
+}
+
+// left, a quarter turn right, straight, left, all but the first driven the other way
+fn lrsl(x: f32, y: f32, phi: f32) -> Option<[f32; 4]> {
+    let (rho, theta) = polar(x - phi.sin(), y - 1.0 + phi.cos());
+    if rho >= 2.0 {
+        let r = (rho * rho - 4.0).sqrt();
+        let u = 2.0 - r;
+        let t = wrap_angle(theta + r.atan2(-2.0));
+        let v = wrap_angle(phi - FRAC_PI_2 - t);
+        if t >= -ZERO && u <= ZERO && v <= ZERO {
+            return Some([t, -FRAC_PI_2, u, v]);
+        }
+    }
+    None
+}
+
+// left, a quarter turn right, straight, right, all but the first driven the other way
+fn lrsr(x: f32, y: f32, phi: f32) -> Option<[f32; 4]> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let (rho, theta) = polar(-eta, xi);
+    if rho >= 2.0 {
+        let t = theta;
+        let u = 2.0 - rho;
+        let v = wrap_angle(t + FRAC_PI_2 - phi);
+        if t >= -ZERO && u <= ZERO && v <= ZERO {
+            return Some([t, -FRAC_PI_2, u, v]);
+        }
+    }
+    None
+}
+
+// left, a quarter turn right, straight, a quarter turn left and right, with the middle three driven the other way
+fn lrslr(x: f32, y: f32, phi: f32) -> Option<[f32; 5]> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let (rho, _) = polar(xi, eta);
+    if rho >= 2.0 {
+        let u = 4.0 - (rho * rho - 4.0).sqrt();
+        if u <= ZERO {
+            let t = wrap_angle(((4.0 - u) * xi - 2.0 * eta).atan2(-2.0 * xi + (u - 4.0) * eta));
+            let v = wrap_angle(t - phi);
+            if t >= -ZERO && v >= -ZERO {
+                return Some([t, -FRAC_PI_2, u, -FRAC_PI_2, v]);
+            }
+        }
+    }
+    None
+}
+
+
+/**
+Every path family can be mirrored (swap left and right) and time flipped (drive it backwards), which turns one base
+formula into four. `reversed` is for the "backwards" variants, worked out from the goal to the start, where the
+segments come out in the opposite order.
+ */
+fn push_variants<const N: usize>(formula: fn(f32, f32, f32) -> Option<[f32; N]>,
+                                 base: [fn(f32) -> Segment; N],
+                                 x: f32,
+                                 y: f32,
+                                 phi: f32,
+                                 reversed: bool,
+                                 candidates: &mut Vec<Vec<Segment>>) {
+    let variants = [
+        (x, y, phi, false, false),
+        (-x, y, -phi, true, false),
+        (x, -y, -phi, false, true),
+        (-x, -y, phi, true, true),
+    ];
+
+    for (vx, vy, vphi, time_flip, reflect) in variants {
+        if let Some(lengths) = formula(vx, vy, vphi) {
+            let mut segments: Vec<Segment> = base.iter().zip(lengths)
+                .map(|(segment, length)| segment(length))
+                .collect();
+            if reversed {
+                segments.reverse();
+            }
+            for segment in segments.iter_mut() {
+                if time_flip {
+                    *segment = segment.time_flipped();
+                }
+                if reflect {
+                    *segment = segment.reflected();
+                }
+            }
+            candidates.push(segments);
+        }
+    }
+}
+
+fn curve_straight_curve(x: f32, y: f32, phi: f32, candidates: &mut Vec<Vec<Segment>>) {
+    push_variants(lsl, [Segment::Left, Segment::Straight, Segment::Left], x, y, phi, false, candidates);
+    push_variants(lsr, [Segment::Left, Segment::Straight, Segment::Right], x, y, phi, false, candidates);
+}
+
+fn curve_curve_curve(x: f32, y: f32, phi: f32, candidates: &mut Vec<Vec<Segment>>) {
+    push_variants(lrl, [Segment::Left, Segment::Right, Segment::Left], x, y, phi, false, candidates);
+
+    // the same family driven from the goal back to the start
+    let x_back = x * phi.cos() + y * phi.sin();
+    let y_back = x * phi.sin() - y * phi.cos();
+    push_variants(lrl, [Segment::Left, Segment::Right, Segment::Left], x_back, y_back, phi, true, candidates);
+}
+
+fn curve_curve_curve_curve(x: f32, y: f32, phi: f32, candidates: &mut Vec<Vec<Segment>>) {
+    let base = [Segment::Left, Segment::Right, Segment::Left, Segment::Right];
+    push_variants(lrlr_forward, base, x, y, phi, false, candidates);
+    push_variants(lrlr_backward, base, x, y, phi, false, candidates);
+}
+
+fn curve_curve_straight_curve(x: f32, y: f32, phi: f32, candidates: &mut Vec<Vec<Segment>>) {
+    let lrsl_base = [Segment::Left, Segment::Right, Segment::Straight, Segment::Left];
+    let lrsr_base = [Segment::Left, Segment::Right, Segment::Straight, Segment::Right];
+    push_variants(lrsl, lrsl_base, x, y, phi, false, candidates);
+    push_variants(lrsr, lrsr_base, x, y, phi, false, candidates);
+
+    // the same families driven from the goal back to the start, which puts the straight second
+    let x_back = x * phi.cos() + y * phi.sin();
+    let y_back = x * phi.sin() - y * phi.cos();
+    push_variants(lrsl, lrsl_base, x_back, y_back, phi, true, candidates);
+    push_variants(lrsr, lrsr_base, x_back, y_back, phi, true, candidates);
+}
+
+fn curve_curve_straight_curve_curve(x: f32, y: f32, phi: f32, candidates: &mut Vec<Vec<Segment>>) {
+    let base = [Segment::Left, Segment::Right, Segment::Straight, Segment::Left, Segment::Right];
+    push_variants(lrslr, base, x, y, phi, false, candidates);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_path_ends_on_the_goal_pose() {
+        // paths don't change when both poses are moved or turned together, so starting at the origin covers them all
+        let start = Pose::new(0.0, 0.0, 0.0);
+        let turning_radius = 1.5;
+        let coordinates = (0..15).map(|i| i as f32 * 1.37 - 9.6);
+        let goals = coordinates.clone().flat_map(|x| coordinates.clone().map(move |y| (x, y)))
+            .flat_map(|(x, y)| (0..12).map(move |i| Pose::new(x, y, i as f32 * PI / 6.0 - PI + 0.05)));
+        for goal in goals {
+            let segments = shortest_path(&start, &goal, turning_radius)
+                .unwrap_or_else(|| panic!("no path from {:?} to {:?}", start, goal));
+
+            let end = segments.iter().fold(start, |pose, segment| {
+                pose.advanced(segment.curvature() / turning_radius, segment.length() * turning_radius)
+            });
+            assert!((end.x - goal.x).abs() < 1e-2 && (end.y - goal.y).abs() < 1e-2, "{:?} isn't {:?}", end, goal);
+            assert!(wrap_angle(end.theta - goal.theta).abs() < 1e-2, "{:?} isn't {:?}", end, goal);
+        }
+    }
+}
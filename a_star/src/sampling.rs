@@ -0,0 +1,460 @@
+use crate::geometry::{distance, edge_cost, heuristic_cost, path_length};
+use crate::limits::SearchLimits;
+use crate::path::PathError;
+use crate::scratch::SearchScratch;
+use crate::sliced::GraphSearch;
+use crate::space::SearchSpace;
+use crate::visibility::ObstacleMap;
+use crate::{MAP_HEIGHT, MAP_WIDTH};
+
+
+// one sample in this many the tree grows straight at the goal instead of at a random point
+const GOAL_BIAS: u64 = 20;
+
+
+/**
+A small random number generator (splitmix64) that starts from a seed, so the same seed grows the same roadmap or tree
+every time.
+ */
+#[derive(Clone, Debug)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /**
+    A number from 0 up to but not including 1.
+     */
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /**
+    A random position anywhere on the map.
+     */
+    fn next_position(&mut self) -> (f32, f32) {
+        (self.next_f32() * MAP_WIDTH as f32 - 0.5, self.next_f32() * MAP_HEIGHT as f32 - 0.5)
+    }
+}
+
+
+/**
+The knobs of the sampling planners. `samples` is how many random points they try, `connection_radius` how far apart two
+points can be and still get linked (or rewired, for RRT*), and `step_size` how far RRT* grows the tree towards a sample
+at most. The roadmap links its samples wherever they are, so it has no use for `step_size`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplingParams {
+    pub samples: usize,
+    pub connection_radius: f32,
+    pub step_size: f32,
+    pub seed: u64,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        SamplingParams {
+            samples: 300,
+            connection_radius: 3.0,
+            step_size: 1.5,
+            seed: 1,
+        }
+    }
+}
+
+
+/**
+A path found by one of the sampling planners, and how long it is in nodes.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampledPath {
+    pub points: Vec<(f32, f32)>,
+    pub length: f32,
+}
+
+
+/**
+A planner that grows one sample at a time, so we can watch it work. Positions are in nodes like the hybrid poses, and
+only obstacles count, not walls, one-way edges or terrain.
+ */
+pub trait Sampler {
+    /**
+    Tries one more sample. Does nothing once all of them are used up.
+     */
+    fn step(&mut self);
+
+    fn samples_taken(&self) -> usize;
+
+    fn finished(&self) -> bool;
+
+    /**
+    The lines the planner has drawn between its points so far, as their two ends.
+     */
+    fn edges(&self) -> Vec<((f32, f32), (f32, f32))>;
+
+    /**
+    The best path from the start to the goal found so far.
+     */
+    fn path(&self) -> Option<&SampledPath>;
+}
+
+
+/**
+A probabilistic roadmap. Every free sample is linked to all the points within the connection radius it can see, and
+after every sample we look for the shortest way from the start to the goal through the links.
+ */
+pub struct Prm {
+    obstacles: ObstacleMap,
+    params: SamplingParams,
+    rng: SeededRng,
+    samples_taken: usize,
+    // the start is point 0 and the goal point 1, the samples that landed on free ground come after them
+    points: Vec<(f32, f32)>,
+    links: Vec<Vec<(usize, i32)>>,
+    path: Option<SampledPath>,
+}
+
+impl Prm {
+    pub fn new(start: (f32, f32), goal: (f32, f32), obstacles: ObstacleMap, params: SamplingParams)
+               -> Result<Prm, PathError> {
+        obstacles.check_endpoints(start, goal)?;
+
+        let mut prm = Prm {
+            obstacles,
+            params,
+            rng: SeededRng::new(params.seed),
+            samples_taken: 0,
+            points: Vec::new(),
+            links: Vec::new(),
+            path: None,
+        };
+        prm.add_point(start);
+        prm.add_point(goal);
+        prm.find_path();
+        Ok(prm)
+    }
+
+    /**
+    Adds a point to the roadmap and links it to the points around it. Returns whether it got any links.
+     */
+    fn add_point(&mut self, point: (f32, f32)) -> bool {
+        let index = self.points.len();
+        self.points.push(point);
+        self.links.push(Vec::new());
+
+        for other in 0..index {
+            let other_point = self.points[other];
+            let in_reach = distance(point, other_point) <= self.params.connection_radius;
+            if in_reach && self.obstacles.can_see(point, other_point) {
+                let cost = edge_cost(point, other_point);
+                self.links[index].push((other, cost));
+                self.links[other].push((index, cost));
+            }
+        }
+        !self.links[index].is_empty()
+    }
+
+    fn find_path(&mut self) {
+        let limits = SearchLimits::default();
+        self.path = GraphSearch::new(&[0], &[1], &*self, &limits, SearchScratch::default())
+            .finish(&*self)
+            .ok()
+            .map(|path| {
+                let points: Vec<(f32, f32)> = path.nodes.iter().map(|&index| self.points[index]).collect();
+                let length = path_length(&points);
+                SampledPath { points, length }
+            });
+    }
+}
+
+impl Sampler for Prm {
+    fn step(&mut self) {
+        if self.finished() {
+            return;
+        }
+        self.samples_taken += 1;
+
+        let sample = self.rng.next_position();
+        // only a new link can make the path shorter
+        if self.obstacles.is_free(sample) && self.add_point(sample) {
+            self.find_path();
+        }
+    }
+
+    fn samples_taken(&self) -> usize {
+        self.samples_taken
+    }
+
+    fn finished(&self) -> bool {
+        self.samples_taken >= self.params.samples
+    }
+
+    fn edges(&self) -> Vec<((f32, f32), (f32, f32))> {
+        self.links.iter()
+            .enumerate()
+            .flat_map(|(from, links)| links.iter().map(move |&(to, _)| (from, to)))
+            .filter(|&(from, to)| to > from)
+            .map(|(from, to)| (self.points[from], self.points[to]))
+            .collect()
+    }
+
+    fn path(&self) -> Option<&SampledPath> {
+        self.path.as_ref()
+    }
+}
+
+impl SearchSpace for Prm {
+    type State = usize;
+
+    fn state_count(&self) -> usize {
+        self.points.len()
+    }
+
+    fn index(&self, state: usize) -> usize {
+        state
+    }
+
+    fn state(&self, index: usize) -> usize {
+        index
+    }
+
+    fn successors(&self, state: usize) -> Vec<(usize, i32)> {
+        self.links[state].clone()
+    }
+
+    fn heuristic(&self, state: usize, goal: usize) -> i32 {
+        heuristic_cost(self.points[state], self.points[goal])
+    }
+}
+
+
+/**
+RRT*, a tree grown out from the start. Every sample pulls the nearest point of the tree up to a step towards it. The new
+point hangs off whichever point close by gives it the shortest way back to the start, and then becomes the parent of
+the points close by it gives a shorter way. The more samples, the closer the path gets to the shortest one.
+ */
+pub struct RrtStar {
+    obstacles: ObstacleMap,
+    params: SamplingParams,
+    rng: SeededRng,
+    samples_taken: usize,
+    goal: (f32, f32),
+    // the points of the tree, starting with the start, and for each the point it hangs off, the points hanging off it
+    // and how far it is from the start along the tree
+    points: Vec<(f32, f32)>,
+    parents: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    costs: Vec<f32>,
+    // the points that can see the goal from within the connection radius
+    goal_links: Vec<usize>,
+    path: Option<SampledPath>,
+}
+
+impl RrtStar {
+    pub fn new(start: (f32, f32), goal: (f32, f32), obstacles: ObstacleMap, params: SamplingParams)
+               -> Result<RrtStar, PathError> {
+        obstacles.check_endpoints(start, goal)?;
+
+        let mut rrt = RrtStar {
+            obstacles,
+            params,
+            rng: SeededRng::new(params.seed),
+            samples_taken: 0,
+            goal,
+            points: vec![start],
+            parents: vec![None],
+            children: vec![Vec::new()],
+            costs: vec![0.0],
+            goal_links: Vec::new(),
+            path: None,
+        };
+        rrt.link_goal(0);
+        rrt.update_path();
+        Ok(rrt)
+    }
+
+    fn link_goal(&mut self, index: usize) {
+        let point = self.points[index];
+        if distance(point, self.goal) <= self.params.connection_radius && self.obstacles.can_see(point, self.goal) {
+            self.goal_links.push(index);
+        }
+    }
+
+    /**
+    Hangs `index` off `parent` instead of the point it hung off before, and moves the costs of everything hanging off
+    it along with it.
+     */
+    fn set_parent(&mut self, index: usize, parent: usize) {
+        if let Some(old_parent) = self.parents[index] {
+            self.children[old_parent].retain(|&child| child != index);
+        }
+        self.parents[index] = Some(parent);
+        self.children[parent].push(index);
+
+        let change = self.costs[parent] + distance(self.points[parent], self.points[index]) - self.costs[index];
+        let mut stack = vec![index];
+        while let Some(below) = stack.pop() {
+            self.costs[below] += change;
+            stack.extend(self.children[below].iter().copied());
+        }
+    }
+
+    fn update_path(&mut self) {
+        let best = self.goal_links.iter()
+            .copied()
+            .min_by(|&a, &b| {
+                let through = |index: usize| self.costs[index] + distance(self.points[index], self.goal);
+                through(a).total_cmp(&through(b))
+            });
+
+        self.path = best.map(|mut index| {
+            // the tree can grow right onto the goal, and then we don't need to go to it a second time
+            let mut points = vec![self.goal];
+            if distance(self.points[index], self.goal) > 1e-4 {
+                points.push(self.points[index]);
+            }
+            while let Some(parent) = self.parents[index] {
+                points.push(self.points[parent]);
+                index = parent;
+            }
+            points.reverse();
+            let length = path_length(&points);
+            SampledPath { points, length }
+        });
+    }
+}
+
+impl Sampler for RrtStar {
+    fn step(&mut self) {
+        if self.finished() {
+            return;
+        }
+        self.samples_taken += 1;
+
+        let sample = if self.rng.next_u64().is_multiple_of(GOAL_BIAS) { self.goal } else { self.rng.next_position() };
+        let nearest = (0..self.points.len())
+            .min_by(|&a, &b| distance(self.points[a], sample).total_cmp(&distance(self.points[b], sample)))
+            .unwrap();
+
+        // take at most a step from the nearest point towards the sample
+        let from = self.points[nearest];
+        let reach = distance(from, sample);
+        let point = if reach <= self.params.step_size {
+            sample
+        } else {
+            let scale = self.params.step_size / reach;
+            (from.0 + (sample.0 - from.0) * scale, from.1 + (sample.1 - from.1) * scale)
+        };
+        if reach < 1e-4 || !self.obstacles.is_free(point) || !self.obstacles.can_see(from, point) {
+            return;
+        }
+
+        let near: Vec<usize> = (0..self.points.len())
+            .filter(|&other| distance(self.points[other], point) <= self.params.connection_radius)
+            .filter(|&other| other == nearest || self.obstacles.can_see(self.points[other], point))
+            .collect();
+        let parent = near.iter()
+            .copied()
+            .chain(std::iter::once(nearest))
+            .min_by(|&a, &b| {
+                let through = |index: usize| self.costs[index] + distance(self.points[index], point);
+                through(a).total_cmp(&through(b))
+            })
+            .unwrap();
+
+        let index = self.points.len();
+        self.points.push(point);
+        self.parents.push(None);
+        self.children.push(Vec::new());
+        self.costs.push(self.costs[parent] + distance(self.points[parent], point));
+        self.set_parent(index, parent);
+
+        // rewire the points close by that get a shorter way back to the start through the new one
+        for other in near {
+            if self.costs[index] + distance(point, self.points[other]) < self.costs[other] {
+                self.set_parent(other, index);
+            }
+        }
+
+        self.link_goal(index);
+        self.update_path();
+    }
+
+    fn samples_taken(&self) -> usize {
+        self.samples_taken
+    }
+
+    fn finished(&self) -> bool {
+        self.samples_taken >= self.params.samples
+    }
+
+    fn edges(&self) -> Vec<((f32, f32), (f32, f32))> {
+        self.parents.iter()
+            .enumerate()
+            .filter_map(|(index, parent)| parent.map(|parent| (self.points[parent], self.points[index])))
+            .collect()
+    }
+
+    fn path(&self) -> Option<&SampledPath> {
+        self.path.as_ref()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visibility::VisibilityGraph;
+    use crate::TestMap;
+
+    /**
+    Runs a planner through all its samples and returns every path it had along the way, checking each one on the way.
+     */
+    fn run(mut sampler: impl Sampler, obstacles: &ObstacleMap, start: (f32, f32), goal: (f32, f32))
+           -> Vec<SampledPath> {
+        let mut paths: Vec<SampledPath> = Vec::new();
+        while !sampler.finished() {
+            sampler.step();
+            if let Some(path) = sampler.path() {
+                assert_eq!((path.points[0], path.points[path.points.len() - 1]), (start, goal));
+                assert!(path.points.windows(2).all(|step| obstacles.can_see(step[0], step[1])));
+                // more samples only ever make the path shorter
+                assert!(paths.last().is_none_or(|last| path.length <= last.length + 1e-4));
+                paths.push(path.clone());
+            }
+        }
+        paths
+    }
+
+    #[test]
+    fn the_same_seed_grows_the_same_clear_paths() {
+        // a wall from the top down to row 11, between the start and the goal
+        let map = TestMap::new(&["       #"; 12]);
+        let obstacles = ObstacleMap::new(0, &map.nodes);
+        let (start, goal) = ((2.0, 3.0), (13.0, 3.0));
+        let params = SamplingParams::default();
+
+        let prm = |params| run(Prm::new(start, goal, obstacles.clone(), params).unwrap(), &obstacles, start, goal);
+        let rrt = |params| run(RrtStar::new(start, goal, obstacles.clone(), params).unwrap(), &obstacles, start, goal);
+        // neither of them can beat the truly shortest path around the bottom of the wall
+        let shortest = VisibilityGraph::build(0, &map.nodes).find_path(start, goal, &SearchLimits::default()).unwrap();
+        for paths in [prm(params), rrt(params)] {
+            assert!(paths.last().is_some_and(|path| path.length >= shortest.length - 1e-3));
+        }
+        assert_eq!(prm(params), prm(params));
+        assert_eq!(rrt(params), rrt(params));
+        assert_ne!(rrt(params), rrt(SamplingParams { seed: 2, ..params }));
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use crate::limits::TimeSlice;
+use crate::path::{Path, PathError};
+use crate::sliced::{SearchStatus, SlicedSearch};
+use crate::{Connectivity, Node};
+
+
+/**
+Identifies a path request handed to a `PathScheduler`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(usize);
+
+
+/**
+Runs many path requests side by side, a little of each per frame. Every frame the scheduler gets one `TimeSlice` and
+shares it out between the requests that are still pending, so the frame takes about as long however many there are.
+Requests are served in turn, and one that doesn't get a go this frame is first in line the next.
+ */
+#[derive(Default)]
+pub struct PathScheduler {
+    next_id: usize,
+    pending: VecDeque<(RequestId, SlicedSearch)>,
+    finished: HashMap<RequestId, Result<Path, PathError>>,
+}
+
+impl PathScheduler {
+    /**
+    Queues a search that has been set up but not stepped yet. Nothing is searched until the next `run`.
+     */
+    pub fn request(&mut self, search: SlicedSearch) -> RequestId {
+        let id = RequestId(self.next_id);
+        self.next_id += 1;
+        self.pending.push_back((id, search));
+        id
+    }
+
+    /**
+    Forgets about every request. The searches only make sense on the map they were started on, so this is what we do
+    when the map changes.
+     */
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.finished.clear();
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /**
+    Steps the pending requests, sharing `frame` out evenly between them. Whatever a request leaves unused, because it
+    finished early, goes to the ones after it.
+     */
+    pub fn run(&mut self, nodes: &[Node], connectivity: &Connectivity, frame: &TimeSlice) {
+        let started = Instant::now();
+        let mut expansions_left = frame.max_expansions;
+
+        // finished requests leave the queue as we go, so count them up front
+        let request_count = self.pending.len();
+        for served in 0..request_count {
+            let requests_left = request_count - served;
+            let share = TimeSlice {
+                max_expansions: expansions_left.map(|left| left.div_ceil(requests_left)),
+                max_time: frame.max_time.map(|max| max.saturating_sub(started.elapsed()) / requests_left as u32),
+            };
+            if share.max_expansions == Some(0) || share.max_time.is_some_and(|time| time.is_zero()) {
+                break;
+            }
+
+            let (id, mut search) = match self.pending.pop_front() {
+                Some(request) => request,
+                None => break,
+            };
+            let expansions_before = search.expansions();
+            let status = search.step(nodes, connectivity, &share);
+            expansions_left = expansions_left.map(|left| left.saturating_sub(search.expansions() - expansions_before));
+
+            match status {
+                SearchStatus::Running => self.pending.push_back((id, search)),
+                SearchStatus::Finished(result) => {
+                    self.finished.insert(id, result);
+                }
+            }
+        }
+    }
+
+    /**
+    Hands back the result of a request once it has finished. The scheduler forgets the request after that.
+     */
+    pub fn take_result(&mut self, id: RequestId) -> Option<Result<Path, PathError>> {
+        self.finished.remove(&id)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::SearchLimits;
+    use crate::{node_index, open_map, TestMap};
+
+    #[test]
+    fn sliced_requests_end_up_with_the_same_paths_within_the_frame_budget() {
+        let mut nodes = open_map();
+        for y in 2..14 {
+            nodes[node_index(7, y, 0)].obstacle = true;
+        }
+        let map = TestMap::from_nodes(nodes);
+        let TestMap { nodes, connectivity, .. } = &map;
+        let limits = SearchLimits::default();
+        let queries = [(node_index(1, 8, 0), node_index(14, 8, 0)),
+                       (node_index(0, 0, 0), node_index(15, 15, 0)),
+                       (node_index(3, 3, 0), node_index(3, 12, 0))];
+        let new_search = |(start, goal): (usize, usize)| map.sliced(start, goal, &limits);
+
+        let mut scheduler = PathScheduler::default();
+        let ids: Vec<RequestId> = queries.iter().map(|&query| scheduler.request(new_search(query))).collect();
+        let frame = TimeSlice { max_expansions: Some(10), max_time: None };
+        let mut frames = 0;
+        while scheduler.pending_count() > 0 {
+            scheduler.run(nodes, connectivity, &frame);
+            frames += 1;
+        }
+
+        let mut total_expansions = 0;
+        for (&id, &query) in ids.iter().zip(&queries) {
+            let mut search = new_search(query);
+            assert_eq!(scheduler.take_result(id), Some(search.finish(nodes, connectivity)));
+            total_expansions += search.expansions();
+        }
+        // no frame went over its 10 expansions
+        assert!(frames * 10 >= total_expansions, "{} frames for {} expansions", frames, total_expansions);
+        assert_eq!(scheduler.take_result(ids[0]), None);
+    }
+}
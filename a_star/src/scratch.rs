@@ -0,0 +1,132 @@
+/**
+What a search keeps about one state. A state the current search hasn't touched yet reads as `ScratchEntry::default()`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScratchEntry {
+    // the search the entry was last written by. Anything written by an older one is out of date.
+    generation: u32,
+    pub global_goal: i32,
+    // global_goal plus the heuristic, which is what the open set is ordered by
+    pub local_goal: i32,
+    pub parent: Option<usize>,
+    pub goal: bool,
+    pub excluded: bool,
+}
+
+impl Default for ScratchEntry {
+    fn default() -> Self {
+        ScratchEntry {
+            generation: 0,
+            global_goal: i32::MAX,
+            local_goal: i32::MAX,
+            parent: None,
+            goal: false,
+            excluded: false,
+        }
+    }
+}
+
+
+/**
+The scores of a search, kept apart from the map so the map never changes while we search it and any number of
+searches can share it. Starting over is O(1): rather than clearing every entry we count up the generation, and every
+entry stamped with an older generation counts as untouched. That way one scratch space can be handed from search to
+search without paying for the size of the map every time.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SearchScratch {
+    generation: u32,
+    entries: Vec<ScratchEntry>,
+}
+
+impl SearchScratch {
+    /**
+    Forgets everything the last search wrote and makes room for `state_count` states.
+     */
+    pub fn begin(&mut self, state_count: usize) {
+        if self.entries.len() < state_count {
+            self.entries.resize(state_count, ScratchEntry::default());
+        }
+
+        self.generation = self.generation.wrapping_add(1);
+        // once in four billion searches the generation wraps around, and an entry from the last time it had this value
+        // would look current again. So we clear them all for real, and skip 0, which is what a fresh entry has.
+        if self.generation == 0 {
+            for entry in &mut self.entries {
+                entry.generation = 0;
+            }
+            self.generation = 1;
+        }
+    }
+
+    /**
+    The entry of the state with `index`, as the current search left it.
+     */
+    pub fn get(&self, index: usize) -> ScratchEntry {
+        match self.entries.get(index) {
+            Some(entry) if entry.generation == self.generation => *entry,
+            _ => ScratchEntry::default(),
+        }
+    }
+
+    /**
+    The entry of the state with `index` to write to. The state has to be one of the ones `begin` made room for.
+     */
+    pub fn get_mut(&mut self, index: usize) -> &mut ScratchEntry {
+        let entry = &mut self.entries[index];
+        if entry.generation != self.generation {
+            *entry = ScratchEntry { generation: self.generation, ..ScratchEntry::default() };
+        }
+        entry
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::QueryConstraints;
+    use crate::limits::SearchLimits;
+    use crate::sliced::SlicedSearch;
+    use crate::space::GridSpace;
+    use crate::{node_index, open_map, TestMap};
+
+    #[test]
+    fn a_new_search_sees_nothing_the_last_one_wrote() {
+        let mut scratch = SearchScratch::default();
+        scratch.begin(4);
+        scratch.get_mut(2).global_goal = 7;
+        assert_eq!(scratch.get(2).global_goal, 7);
+        scratch.begin(4);
+        assert_eq!(scratch.get(2), ScratchEntry::default());
+
+        // not even when the generation wraps around to the one that wrote it
+        scratch.generation = u32::MAX;
+        scratch.get_mut(3).parent = Some(1);
+        scratch.begin(4);
+        assert_eq!(scratch.get(3), ScratchEntry::default());
+        scratch.generation = u32::MAX;
+        assert_eq!(scratch.get(3), ScratchEntry::default());
+    }
+
+    #[test]
+    fn a_reused_scratch_space_finds_the_same_paths() {
+        let mut nodes = open_map();
+        for x in 3..13 {
+            nodes[node_index(x, 7, 0)].obstacle = true;
+        }
+        let map = TestMap::from_nodes(nodes);
+        let TestMap { nodes, connectivity, components } = &map;
+        let (limits, constraints) = (SearchLimits::default(), QueryConstraints::default());
+        let space = GridSpace { nodes, connectivity, constraints: &constraints };
+
+        let mut scratch = SearchScratch::default();
+        for (start, goal) in [(node_index(8, 2, 0), node_index(8, 12, 0)), (node_index(1, 1, 0), node_index(14, 14, 0)),
+                              (node_index(8, 12, 0), node_index(8, 2, 0))] {
+            let fresh = map.search(start, goal);
+            let mut reused = SlicedSearch::reusing(&[start], &[goal], &space, components, &limits, scratch);
+            assert_eq!(reused.finish(nodes, connectivity), fresh);
+            scratch = reused.into_scratch();
+        }
+    }
+}
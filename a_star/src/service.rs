@@ -0,0 +1,277 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+
+use crate::components::Components;
+use crate::constraints::QueryConstraints;
+use crate::limits::SearchLimits;
+use crate::path::{Path, PathError};
+use crate::scratch::SearchScratch;
+use crate::sliced::SlicedSearch;
+use crate::space::GridSpace;
+use crate::{Connectivity, Node};
+
+
+/**
+A copy of the map the workers search on, so the app can keep editing its own nodes while they work. Every snapshot
+the service is given gets a higher version than the one before.
+ */
+struct GridSnapshot {
+    version: u64,
+    nodes: Vec<Node>,
+    connectivity: Connectivity,
+    components: Components,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct PathRequest {
+    start_index: usize,
+    goal_index: usize,
+    limits: SearchLimits,
+    constraints: QueryConstraints,
+}
+
+
+/**
+A result from a worker, together with the version of the snapshot it was worked out on.
+ */
+struct Solved {
+    version: u64,
+    result: Result<Path, PathError>,
+}
+
+
+struct Job {
+    request: PathRequest,
+    reply: Sender<Solved>,
+}
+
+
+/**
+What a `PathHandle` has to say when we poll it.
+ */
+#[derive(Debug, PartialEq, Eq)]
+pub enum PathPoll<'a> {
+    // the workers haven't got to the request yet, or are still on it
+    Pending,
+    Ready(&'a Result<Path, PathError>),
+    // the result was worked out on a snapshot of the map that has been replaced since
+    Stale(&'a Result<Path, PathError>),
+}
+
+
+/**
+Where the result of a request to a `PathService` turns up.
+ */
+pub struct PathHandle {
+    request: PathRequest,
+    receiver: Receiver<Solved>,
+    solved: Option<Solved>,
+    // set while a `recompute` is on its way, so a stale result isn't sent off again before the new one comes in
+    recomputing: bool,
+    current: Arc<RwLock<Arc<GridSnapshot>>>,
+}
+
+impl PathHandle {
+    /**
+    Checks whether the result has come in yet, without waiting for it.
+     */
+    pub fn poll(&mut self) -> PathPoll<'_> {
+        if let Ok(solved) = self.receiver.try_recv() {
+            self.solved = Some(solved);
+            self.recomputing = false;
+        }
+
+        let current_version = self.current.read().unwrap().version;
+        match &self.solved {
+            None => PathPoll::Pending,
+            Some(_) if self.recomputing => PathPoll::Pending,
+            Some(solved) if solved.version < current_version => PathPoll::Stale(&solved.result),
+            Some(solved) => PathPoll::Ready(&solved.result),
+        }
+    }
+
+    /**
+    The last result that came in, stale or not, as of the last `poll`.
+     */
+    pub fn result(&self) -> Option<&Result<Path, PathError>> {
+        self.solved.as_ref().map(|solved| &solved.result)
+    }
+}
+
+
+/**
+Solves path requests on a pool of worker threads, so big searches never hold up a frame. Requests go to the workers
+through a channel and each one solves against the newest snapshot of the map at the moment it picks the request up.
+When the map changes, `publish` hands the service a new snapshot. Results worked out on an older one show up as stale
+and can be sent off again with `recompute`.
+ */
+pub struct PathService {
+    // None once we are shutting down, which hangs up the channel and lets the workers finish
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    current: Arc<RwLock<Arc<GridSnapshot>>>,
+    // requests sent off that no worker has finished yet
+    pending: Arc<AtomicUsize>,
+}
+
+impl PathService {
+    /**
+    Starts `worker_count` workers, at least one, searching an empty map until the first `publish`.
+     */
+    pub fn new(worker_count: usize) -> PathService {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let current = Arc::new(RwLock::new(Arc::new(GridSnapshot {
+            version: 0,
+            nodes: Vec::new(),
+            connectivity: Connectivity { topology: crate::Topology::Bounded, portals: Vec::new() },
+            components: Components::default(),
+        })));
+        let pending = Arc::new(AtomicUsize::new(0));
+
+        let workers = (0..worker_count.max(1)).map(|_| {
+            let receiver = Arc::clone(&receiver);
+            let current = Arc::clone(&current);
+            let pending = Arc::clone(&pending);
+            std::thread::spawn(move || work(&receiver, &current, &pending))
+        }).collect();
+
+        PathService { sender: Some(sender), workers, current, pending }
+    }
+
+    /**
+    Makes a copy of the map for the workers to search from now on. Results worked out on the copy before become stale.
+     */
+    pub fn publish(&self, nodes: &[Node], connectivity: &Connectivity, components: &Components) {
+        let mut current = self.current.write().unwrap();
+        *current = Arc::new(GridSnapshot {
+            version: current.version + 1,
+            nodes: nodes.to_vec(),
+            connectivity: connectivity.clone(),
+            components: components.clone(),
+        });
+    }
+
+    /**
+    How many requests the workers haven't finished yet.
+     */
+    pub fn pending_count(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /**
+    Sends off a search from `start_index` to `goal_index`. The result turns up in the handle.
+     */
+    pub fn request(&self,
+                   start_index: usize,
+                   goal_index: usize,
+                   limits: &SearchLimits,
+                   constraints: &QueryConstraints) -> PathHandle {
+        let request = PathRequest { start_index, goal_index, limits: *limits, constraints: constraints.clone() };
+        let (reply, receiver) = channel();
+        self.send(Job { request: request.clone(), reply });
+        PathHandle { request, receiver, solved: None, recomputing: false, current: Arc::clone(&self.current) }
+    }
+
+    /**
+    Sends the request behind `handle` off again, to be solved on the newest snapshot. The old result stays in the
+    handle until the new one comes in, and until then polling the handle says it's pending.
+     */
+    pub fn recompute(&self, handle: &mut PathHandle) {
+        let (reply, receiver) = channel();
+        self.send(Job { request: handle.request.clone(), reply });
+        handle.receiver = receiver;
+        handle.recomputing = true;
+    }
+
+    fn send(&self, job: Job) {
+        if let Some(sender) = &self.sender {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            // the workers only hang up after we drop the sender, so this can't fail
+            sender.send(job).unwrap();
+        }
+    }
+}
+
+impl Drop for PathService {
+    fn drop(&mut self) {
+        // with the sender gone the workers run out of jobs, and we wait for them to finish the ones they are on
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+/**
+What every worker thread runs: take the next job, solve it on the newest snapshot, send the result back.
+ */
+fn work(receiver: &Mutex<Receiver<Job>>, current: &RwLock<Arc<GridSnapshot>>, pending: &AtomicUsize) {
+    // every worker has a scratch space of its own, which it hands from one search to the next. The snapshot itself is
+    // only ever read, by all the workers at once.
+    let mut scratch = SearchScratch::default();
+    loop {
+        // the lock is only held while we wait for the next job, not while we work on it
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        let snapshot = Arc::clone(&current.read().unwrap());
+        let PathRequest { start_index, goal_index, limits, constraints } = job.request;
+        let (nodes, connectivity) = (&snapshot.nodes, &snapshot.connectivity);
+        let space = GridSpace { nodes, connectivity, constraints: &constraints };
+        let mut search = SlicedSearch::reusing(&[start_index], &[goal_index], &space, &snapshot.components, &limits,
+                                               std::mem::take(&mut scratch));
+        let result = search.finish(nodes, connectivity);
+        scratch = search.into_scratch();
+
+        // nobody may be waiting any more, if the handle was dropped in the meantime
+        let _ = job.reply.send(Solved { version: snapshot.version, result });
+        pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node_index, TestMap};
+
+    /**
+    Polls `handle` until it has something other than pending to say, and returns whether that was a stale result.
+     */
+    fn wait_for(handle: &mut PathHandle) -> bool {
+        for _ in 0..5000 {
+            match handle.poll() {
+                PathPoll::Pending => std::thread::sleep(std::time::Duration::from_millis(1)),
+                PathPoll::Ready(_) => return false,
+                PathPoll::Stale(_) => return true,
+            }
+        }
+        panic!("the service never answered");
+    }
+
+    #[test]
+    fn one_answer_per_snapshot() {
+        let TestMap { nodes, connectivity, components } = TestMap::new(&[]);
+        let service = PathService::new(2);
+        service.publish(&nodes, &connectivity, &components);
+
+        let mut handle = service.request(node_index(0, 0, 0), node_index(15, 15, 0), &SearchLimits::default(),
+                                         &QueryConstraints::default());
+        assert!(!wait_for(&mut handle));
+
+        // a new snapshot makes the answer stale, and sending it off again once is enough. Polling while the new answer
+        // is on its way doesn't ask for it again.
+        service.publish(&nodes, &connectivity, &components);
+        assert!(matches!(handle.poll(), PathPoll::Stale(_)));
+        service.recompute(&mut handle);
+        assert!(!wait_for(&mut handle));
+        assert_eq!(handle.result().unwrap().as_ref().unwrap().nodes.last(), Some(&node_index(15, 15, 0)));
+    }
+}
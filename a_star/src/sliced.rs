@@ -0,0 +1,675 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::time::Instant;
+
+use crate::components::Components;
+use crate::constraints::QueryConstraints;
+use crate::limits::{SearchBudget, SearchLimits, TieBreaking, TimeSlice};
+use crate::path::{Path, PathError};
+use crate::sampling::SeededRng;
+use crate::scratch::SearchScratch;
+use crate::space::{GridSpace, SearchSpace};
+use crate::{Connectivity, Node};
+
+
+// with more goals than this we don't work out the distance to each of them for every state we look at, and search
+// without a heuristic instead
+const MAX_HEURISTIC_GOALS: usize = 16;
+
+
+/**
+Where a time sliced search is at after a call to `SlicedSearch::step`.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchStatus<T = usize> {
+    // the slice ran out before the search was done. Call `step` again to carry on.
+    Running,
+    Finished(Result<Path<T>, PathError>),
+}
+
+
+/**
+An A* search through any `SearchSpace` that can stop part way through and carry on later, so a big search can be
+spread over several frames instead of stalling one of them. It keeps its scores in a `SearchScratch` of its own, which
+means the space is only borrowed for the length of each `step` and never written to. The space must not change
+between steps though: when it does the search is out of date and should be thrown away and started again.
+
+A search can also start from several states at once and stop at whichever of several goals it reaches first, which
+gives the cheapest path from any of the starts to any of the goals.
+ */
+pub struct GraphSearch<T> {
+    // the goals the heuristic measures the distance to. They are left empty when there are too many goals, which makes
+    // the heuristic 0. Which states are goals at all is kept in the scratch space.
+    heuristic_goals: Vec<T>,
+    // moves the search must not make, by index. Empty unless `excluding` was called.
+    excluded_moves: HashSet<(usize, usize)>,
+    limits: SearchLimits,
+    budget: SearchBudget,
+
+    // the scores `a_star` used to keep on every node, by state index
+    scratch: SearchScratch,
+    // ordered by score, then by the tie-breaking key of the policy in `limits`, then by state number
+    open_set: BinaryHeap<Reverse<(i32, i64, usize)>>,
+    // the line from the first start to the first goal, for the cross product tie-breaking, and the random numbers for
+    // the random one
+    straight_line: Option<((f32, f32), (f32, f32))>,
+    tie_rng: SeededRng,
+
+    // the explored state with the lowest heuristic, and that heuristic. This is where a partial path ends.
+    closest: (i32, usize),
+    // set when the cost limit stops us from following a path, in which case we can't say the goal is unreachable
+    pruned: bool,
+    expansions: usize,
+    // once the search is done we hang on to the result, so stepping a finished search just hands it back again
+    result: Option<Result<Path<T>, PathError>>,
+}
+
+impl<T: Copy + Eq> GraphSearch<T> {
+    /**
+    Sets up a search through `space` from any of `starts` to any of `goals`, keeping its scores in `scratch`. Whatever
+    was in there is forgotten, so a scratch space taken back from a search with `into_scratch` can go straight into
+    the next one. With no starts or no goals the search finds nothing and comes back with `PathError::Unreachable`.
+     */
+    pub fn new<S: SearchSpace<State = T>>(starts: &[T],
+                                          goals: &[T],
+                                          space: &S,
+                                          limits: &SearchLimits,
+                                          mut scratch: SearchScratch) -> GraphSearch<T> {
+        scratch.begin(space.state_count());
+        let mut search = GraphSearch {
+            heuristic_goals: Vec::new(),
+            excluded_moves: HashSet::new(),
+            limits: *limits,
+            budget: limits.start(),
+            scratch,
+            open_set: BinaryHeap::new(),
+            straight_line: None,
+            tie_rng: SeededRng::new(0),
+            closest: (i32::MAX, 0),
+            pruned: false,
+            expansions: 0,
+            result: None,
+        };
+        match limits.tie_breaking {
+            TieBreaking::CrossProduct => {
+                let position = |state: Option<&T>| state.and_then(|&state| space.position(state));
+                search.straight_line = position(starts.first()).zip(position(goals.first()));
+            }
+            TieBreaking::Random(seed) => search.tie_rng = SeededRng::new(seed),
+            _ => {}
+        }
+
+        for &goal in goals {
+            search.scratch.get_mut(space.index(goal)).goal = true;
+        }
+        if goals.len() <= MAX_HEURISTIC_GOALS {
+            search.heuristic_goals = goals.to_vec();
+        }
+
+        for &start in starts {
+            let start_index = space.index(start);
+            let start_heuristic = search.heuristic(start, space);
+            let tie = search.tie_key(start, 0, start_heuristic, space);
+            let start = search.scratch.get_mut(start_index);
+            start.global_goal = 0;
+            start.local_goal = start_heuristic;
+            search.closest = search.closest.min((start_heuristic, start_index));
+            search.open_set.push(Reverse((start_heuristic, tie, start_index)));
+        }
+        search
+    }
+
+    /**
+    A search that is over before it started, because we already know it fails with `error`. It hangs on to `scratch`
+    for whoever takes it back, with nothing in it.
+     */
+    pub fn failed(error: PathError, mut scratch: SearchScratch) -> GraphSearch<T> {
+        let limits = SearchLimits::default();
+        scratch.begin(0);
+        GraphSearch {
+            heuristic_goals: Vec::new(),
+            excluded_moves: HashSet::new(),
+            limits,
+            budget: limits.start(),
+            scratch,
+            open_set: BinaryHeap::new(),
+            straight_line: None,
+            tie_rng: SeededRng::new(0),
+            closest: (i32::MAX, 0),
+            pruned: false,
+            expansions: 0,
+            result: Some(Err(error)),
+        }
+    }
+
+    /**
+    Keeps the search from passing through the states with the numbers in `indices` and from making any of the `moves`
+    between them, given as (from, to) pairs of numbers. The numbers are the ones `SearchSpace::index` gives. Excluding
+    a start only keeps the search from coming back to it.
+     */
+    pub fn excluding(mut self, indices: &[usize], moves: &[(usize, usize)]) -> GraphSearch<T> {
+        // a search that failed straight away has no room in its scratch space, and no use for exclusions either
+        if self.result.is_some() {
+            return self;
+        }
+        for &index in indices {
+            self.scratch.get_mut(index).excluded = true;
+        }
+        self.excluded_moves.extend(moves.iter().copied());
+        self
+    }
+
+    /**
+    How many states the search has taken off the open set so far, over all its steps.
+     */
+    pub fn expansions(&self) -> usize {
+        self.expansions
+    }
+
+    /**
+    Ends the search and hands back its scratch space, to look at the scores or to use for another search.
+     */
+    pub fn into_scratch(self) -> SearchScratch {
+        self.scratch
+    }
+
+    /**
+    Runs the search until it finishes or `slice` is used up, whichever comes first. The `space` has to be the same one
+    the search was started with.
+     */
+    pub fn step<S: SearchSpace<State = T>>(&mut self, space: &S, slice: &TimeSlice) -> SearchStatus<T> {
+        if let Some(result) = &self.result {
+            return SearchStatus::Finished(result.clone());
+        }
+
+        let started = Instant::now();
+        let slice_start_expansions = self.expansions;
+        self.budget.resume();
+
+        let result = loop {
+            if slice.used_up(started, self.expansions - slice_start_expansions) {
+                break None;
+            }
+
+            // In Rust, the std::collections::BinaryHeap is a max-heap by default, meaning it always pops the largest
+            // element first. However, in many algorithms like A*, you typically need a min-heap, which pops the
+            // smallest element first.
+            let (local_goal, _, current_index) = match self.open_set.pop() {
+                Some(Reverse(entry)) => entry,
+                None => break Some(self.finish_exhausted(space)),
+            };
+
+            // we don't update entries already in the open set. Instead we push the state again with its new score and
+            // skip the out of date entry when it comes off the heap.
+            let current = self.scratch.get(current_index);
+            if local_goal > current.local_goal {
+                continue;
+            }
+
+            if current.goal {
+                break Some(Ok(self.construct_path(current_index, None, space)));
+            }
+
+            if !self.budget.expand() {
+                break Some(self.partial_path(PathError::BudgetExceeded, space));
+            }
+            self.expansions += 1;
+
+            let current_heuristic = current.local_goal.saturating_sub(current.global_goal);
+            if current_heuristic < self.closest.0 {
+                self.closest = (current_heuristic, current_index);
+            }
+
+            for (neighbor, cost) in space.successors(space.state(current_index)) {
+                let neighbor_index = space.index(neighbor);
+                if self.is_excluded(current_index, neighbor_index) {
+                    continue;
+                }
+
+                let tentative_global_goal = current.global_goal + cost;
+
+                if tentative_global_goal < self.scratch.get(neighbor_index).global_goal {
+                    let heuristic = self.heuristic(neighbor, space);
+                    // the heuristic is i32::MAX when the goal is out of reach, so make sure we don't overflow
+                    let local_goal = tentative_global_goal.saturating_add(heuristic);
+
+                    // if even the best case through this neighbor costs more than we are allowed to spend, leave it be
+                    if !self.budget.affordable(local_goal) {
+                        self.pruned = true;
+                        continue;
+                    }
+
+                    let tie = self.tie_key(neighbor, tentative_global_goal, heuristic, space);
+                    let neighbor = self.scratch.get_mut(neighbor_index);
+                    neighbor.parent = Some(current_index);
+                    neighbor.global_goal = tentative_global_goal;
+                    neighbor.local_goal = local_goal;
+                    self.open_set.push(Reverse((local_goal, tie, neighbor_index)));
+                }
+            }
+        };
+
+        self.budget.pause();
+        match result {
+            Some(result) => {
+                self.result = Some(result.clone());
+                SearchStatus::Finished(result)
+            }
+            None => SearchStatus::Running,
+        }
+    }
+
+    /**
+    Runs the rest of the search in one go and returns its result.
+     */
+    pub fn finish<S: SearchSpace<State = T>>(&mut self, space: &S) -> Result<Path<T>, PathError> {
+        // the default slice has no limits, so one step runs the search to the end
+        match self.step(space, &TimeSlice::default()) {
+            SearchStatus::Finished(result) => result,
+            SearchStatus::Running => unreachable!("an unlimited slice always finishes the search"),
+        }
+    }
+
+    fn is_excluded(&self, from: usize, to: usize) -> bool {
+        self.scratch.get(to).excluded || self.excluded_moves.contains(&(from, to))
+    }
+
+    /**
+    The lowest of the heuristics to each goal, or 0 when there are too many goals to work that out.
+     */
+    fn heuristic<S: SearchSpace<State = T>>(&self, state: T, space: &S) -> i32 {
+        self.heuristic_goals.iter()
+            .map(|&goal| space.heuristic(state, goal))
+            .min()
+            .unwrap_or(0)
+    }
+
+    /**
+    What orders a state reached with `global_goal` and with `heuristic` left to go among the states with the same
+    score. Lower goes first.
+     */
+    fn tie_key<S: SearchSpace<State = T>>(&mut self, state: T, global_goal: i32, heuristic: i32, space: &S) -> i64 {
+        match self.limits.tie_breaking {
+            TieBreaking::Index => 0,
+            TieBreaking::HigherG => -(global_goal as i64),
+            TieBreaking::LowerH => heuristic as i64,
+            TieBreaking::CrossProduct => match (self.straight_line, space.position(state)) {
+                // how far off the line from the start to the goal the state is, give or take the length of the line
+                (Some(((start_x, start_y), (goal_x, goal_y))), Some((x, y))) => {
+                    let cross = (x - goal_x) * (start_y - goal_y) - (start_x - goal_x) * (y - goal_y);
+                    (cross.abs() * 1000.0) as i64
+                }
+                _ => 0,
+            },
+            TieBreaking::Random(_) => (self.tie_rng.next_u64() >> 1) as i64,
+        }
+    }
+
+    /**
+    The open set ran dry without reaching a goal, so the goal is out of reach, unless the cost limit kept us from
+    looking everywhere.
+     */
+    fn finish_exhausted<S: SearchSpace<State = T>>(&self, space: &S) -> Result<Path<T>, PathError> {
+        let reason = if self.pruned { PathError::BudgetExceeded } else { PathError::Unreachable };
+        self.partial_path(reason, space)
+    }
+
+    /**
+    What we hand back when we can't reach the goal: the path to the closest state if the limits allow partial paths,
+    otherwise just the reason.
+     */
+    fn partial_path<S: SearchSpace<State = T>>(&self, reason: PathError, space: &S) -> Result<Path<T>, PathError> {
+        if self.limits.allow_partial {
+            Ok(self.construct_path(self.closest.1, Some(reason), space))
+        } else {
+            Err(reason)
+        }
+    }
+
+    fn construct_path<S: SearchSpace<State = T>>(&self,
+                                                 end_index: usize,
+                                                 partial: Option<PathError>,
+                                                 space: &S) -> Path<T> {
+        let mut current_index = end_index;
+        let mut path = vec![space.state(current_index)];
+        while let Some(parent_index) = self.scratch.get(current_index).parent {
+            path.push(space.state(parent_index));
+            current_index = parent_index;
+        }
+        path.reverse();
+        Path { nodes: path, cost: self.scratch.get(end_index).global_goal, partial }
+    }
+}
+
+
+/**
+A `GraphSearch` on the grid, with the checks only the grid has on top: starts and goals that are off the map or
+blocked, and starts and goals in different components of the map. The nodes are only borrowed for the length of each
+`step`, so the search can be spread over several frames as long as the map doesn't change in between.
+ */
+pub struct SlicedSearch {
+    search: GraphSearch<usize>,
+    constraints: QueryConstraints,
+    // set when every goal is an obstacle, which we can only get close to. Running out of nodes then says so.
+    goals_blocked: bool,
+    // set when no goal shares a component with a start, so we only head for the node closest to the goals and the
+    // path we find is partial
+    disconnected: bool,
+}
+
+impl SlicedSearch {
+    /**
+    Sets up a search from `start_index` to `goal_index` that follows `constraints`. This does the cheap checks
+    straight away, so the search may already be finished before the first step.
+     */
+    pub fn new(start_index: usize,
+               goal_index: usize,
+               nodes: &[Node],
+               connectivity: &Connectivity,
+               components: &Components,
+               limits: &SearchLimits,
+               constraints: &QueryConstraints) -> SlicedSearch {
+        SlicedSearch::new_multi(&[start_index], &[goal_index], nodes, connectivity, components, limits, constraints)
+    }
+
+    /**
+    Sets up a search from any of `start_indices` to any of `goal_indices`. Starts and goals we can't use, because
+    they are off the map or blocked, are left out, and the search only fails for them when none are left. A goal in
+    one of the no-go zones of `constraints` counts as blocked.
+     */
+    pub fn new_multi(start_indices: &[usize],
+                     goal_indices: &[usize],
+                     nodes: &[Node],
+                     connectivity: &Connectivity,
+                     components: &Components,
+                     limits: &SearchLimits,
+                     constraints: &QueryConstraints) -> SlicedSearch {
+        let space = GridSpace { nodes, connectivity, constraints };
+        SlicedSearch::reusing(start_indices, goal_indices, &space, components, limits, SearchScratch::default())
+    }
+
+    /**
+    The same as `new_multi`, but keeps the scores in `scratch` rather than in a scratch space of its own. Handing the
+    scratch space from one search to the next saves making room for the whole map every time.
+     */
+    pub fn reusing(start_indices: &[usize],
+                   goal_indices: &[usize],
+                   space: &GridSpace,
+                   components: &Components,
+                   limits: &SearchLimits,
+                   scratch: SearchScratch) -> SlicedSearch {
+        let GridSpace { nodes, connectivity, constraints } = *space;
+        let failed = |error, scratch| SlicedSearch {
+            search: GraphSearch::failed(error, scratch),
+            constraints: constraints.clone(),
+            goals_blocked: false,
+            disconnected: false,
+        };
+
+        // we can't stand on a blocked goal, but we can still get as close to it as possible
+        let endpoints = usable_endpoints(start_indices, goal_indices, nodes, constraints, limits.allow_partial);
+        let (start_indices, goal_indices) = match endpoints {
+            Ok(endpoints) => endpoints,
+            Err(error) => return failed(error, scratch),
+        };
+
+        // if none of the starts share a region of the map with any of the goals we can stand on there's no path
+        // between them, and we don't want to search every node we can reach just to find that out. When we are happy
+        // with a partial path we don't have to either: the node a full search would get closest to the goals on is
+        // the closest one in the regions of the starts, so we search our way to that one instead.
+        let goals_blocked = goal_indices.iter().all(|&goal_index| !free(&nodes[goal_index], constraints));
+        let connected = !goals_blocked && start_indices.iter()
+            .any(|&start| goal_indices.iter().any(|&goal| components.same_component(start, goal)));
+        if !connected && !limits.allow_partial {
+            return failed(PathError::Unreachable, scratch);
+        }
+        let goal_indices = if connected {
+            goal_indices
+        } else {
+            vec![closest_reachable(&start_indices, &goal_indices, nodes, connectivity, components, constraints)]
+        };
+
+        SlicedSearch {
+            search: GraphSearch::new(&start_indices, &goal_indices, space, limits, scratch),
+            constraints: constraints.clone(),
+            goals_blocked,
+            disconnected: !connected,
+        }
+    }
+
+    /**
+    Keeps the search from passing through `nodes` and from making any of the `moves`, given as (from, to) pairs.
+    Excluding a start only keeps the search from coming back to it.
+     */
+    pub fn excluding(mut self, nodes: &[usize], moves: &[(usize, usize)]) -> SlicedSearch {
+        // on the grid the number of a state is the index of its node
+        self.search = self.search.excluding(nodes, moves);
+        self
+    }
+
+    /**
+    How many nodes the search has taken off the open set so far, over all its steps.
+     */
+    pub fn expansions(&self) -> usize {
+        self.search.expansions()
+    }
+
+    /**
+    Runs the search until it finishes or `slice` is used up, whichever comes first. The `nodes` and `connectivity`
+    have to be the same ones the search was started with.
+     */
+    pub fn step(&mut self, nodes: &[Node], connectivity: &Connectivity, slice: &TimeSlice) -> SearchStatus {
+        let space = GridSpace { nodes, connectivity, constraints: &self.constraints };
+        match self.search.step(&space, slice) {
+            SearchStatus::Finished(mut result) => {
+                if self.disconnected {
+                    result = short_of_goal(result);
+                }
+                if self.goals_blocked {
+                    result = goal_blocked(result);
+                }
+                SearchStatus::Finished(result)
+            }
+            status => status,
+        }
+    }
+
+    /**
+    Runs the rest of the search in one go and returns its result.
+     */
+    pub fn finish(&mut self, nodes: &[Node], connectivity: &Connectivity) -> Result<Path, PathError> {
+        // the default slice has no limits, so one step runs the search to the end
+        match self.step(nodes, connectivity, &TimeSlice::default()) {
+            SearchStatus::Finished(result) => result,
+            SearchStatus::Running => unreachable!("an unlimited slice always finishes the search"),
+        }
+    }
+
+    /**
+    Ends the search and hands back its scratch space, with the scores by node index.
+     */
+    pub fn into_scratch(self) -> SearchScratch {
+        self.search.into_scratch()
+    }
+}
+
+
+/**
+Whether a query following `constraints` can stand on `node`.
+ */
+fn free(node: &Node, constraints: &QueryConstraints) -> bool {
+    !node.obstacle && constraints.allows(node)
+}
+
+/**
+The free node in the components of `start_indices` that gets closest to any of `goal_indices`, going by the
+heuristic, the same way a search that runs out of nodes picks where its partial path ends. Nodes in the no-go zones
+of `constraints` are never picked, since the search can't get onto them.
+ */
+fn closest_reachable(start_indices: &[usize],
+                     goal_indices: &[usize],
+                     nodes: &[Node],
+                     connectivity: &Connectivity,
+                     components: &Components,
+                     constraints: &QueryConstraints) -> usize {
+    let labels: Vec<usize> = start_indices.iter().filter_map(|&start| components.label(start)).collect();
+    let closeness = |index: usize| {
+        goal_indices.iter().map(|&goal| nodes[index].heuristic(&nodes[goal], nodes, connectivity)).min()
+    };
+    (0..nodes.len())
+        .filter(|&index| components.label(index).is_some_and(|label| labels.contains(&label)))
+        .filter(|&index| constraints.allows(&nodes[index]))
+        .min_by_key(|&index| closeness(index))
+        .unwrap_or(start_indices[0])
+}
+
+/**
+A path to the node closest to goals we can't reach gets there fine, but it's still short of the goals.
+ */
+fn short_of_goal(result: Result<Path, PathError>) -> Result<Path, PathError> {
+    match result {
+        Ok(Path { nodes, cost, partial: None }) => Ok(Path { nodes, cost, partial: Some(PathError::Unreachable) }),
+        result => result,
+    }
+}
+
+/**
+With every goal blocked the search can never get to one, and that is the better reason to give for not getting there.
+ */
+fn goal_blocked(result: Result<Path, PathError>) -> Result<Path, PathError> {
+    match result {
+        Err(PathError::Unreachable) => Err(PathError::GoalBlocked),
+        Ok(Path { nodes, cost, partial: Some(PathError::Unreachable) }) => {
+            Ok(Path { nodes, cost, partial: Some(PathError::GoalBlocked) })
+        }
+        result => result,
+    }
+}
+
+
+/**
+Leaves out the starts and goals the search can't use. When that leaves none, we fail with the same errors
+`check_endpoints` gives: first anything off the map, then anything blocked. Goals in a no-go zone of `constraints`
+are as blocked as obstacles, while starts in one are fine, since the search can still step out. Blocked goals are kept
+when `allow_blocked_goals` is set.
+ */
+fn usable_endpoints(start_indices: &[usize],
+                    goal_indices: &[usize],
+                    nodes: &[Node],
+                    constraints: &QueryConstraints,
+                    allow_blocked_goals: bool) -> Result<(Vec<usize>, Vec<usize>), PathError> {
+    let on_map = |indices: &[usize]| -> Vec<usize> {
+        indices.iter().copied().filter(|&index| index < nodes.len()).collect()
+    };
+    let (start_indices, goal_indices) = (on_map(start_indices), on_map(goal_indices));
+    if start_indices.is_empty() {
+        return Err(PathError::StartOutOfBounds);
+    }
+    if goal_indices.is_empty() {
+        return Err(PathError::GoalOutOfBounds);
+    }
+
+    let start_indices: Vec<usize> = start_indices.into_iter().filter(|&index| !nodes[index].obstacle).collect();
+    let goal_indices = if allow_blocked_goals {
+        goal_indices
+    } else {
+        goal_indices.into_iter().filter(|&index| free(&nodes[index], constraints)).collect()
+    };
+    if start_indices.is_empty() {
+        return Err(PathError::StartBlocked);
+    }
+    if goal_indices.is_empty() {
+        return Err(PathError::GoalBlocked);
+    }
+
+    Ok((start_indices, goal_indices))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::Zone;
+    use crate::{node_index, TestMap, MAP_HEIGHT};
+
+    #[test]
+    fn a_goal_in_another_component_gets_a_partial_path_without_a_flood() {
+        // a wall from top to bottom at x = 8, with the goal on the far side of it
+        let map = TestMap::new(&["        #"; MAP_HEIGHT as usize]);
+        let TestMap { nodes, connectivity, .. } = &map;
+        let (start, goal) = (node_index(2, 3, 0), node_index(12, 10, 0));
+        let limits = SearchLimits { allow_partial: true, ..SearchLimits::default() };
+
+        // it gets as close to the goal as the wall lets it, going by the heuristic
+        let mut search = map.sliced(start, goal, &limits);
+        let path = search.finish(nodes, connectivity).unwrap();
+        assert_eq!(path.partial, Some(PathError::Unreachable));
+        assert_eq!((path.nodes.last(), path.cost), (Some(&node_index(7, 7, 0)), 5 + 4));
+
+        // which is where a search that looks at everything it can reach ends up too
+        let constraints = QueryConstraints::default();
+        let space = GridSpace { nodes, connectivity, constraints: &constraints };
+        let mut flood_search = GraphSearch::new(&[start], &[goal], &space, &limits, SearchScratch::default());
+        let flood = flood_search.finish(&space).unwrap();
+        assert_eq!((flood.nodes.last(), flood.cost), (path.nodes.last(), path.cost));
+        // but it heads for that node instead of going through the whole left half of the layer
+        assert!(search.expansions() * 2 < flood_search.expansions());
+
+        let mut search = map.sliced(start, goal, &SearchLimits::default());
+        assert_eq!(search.finish(nodes, connectivity), Err(PathError::Unreachable));
+        assert_eq!(search.expansions(), 0);
+    }
+
+    #[test]
+    fn goals_in_no_go_zones_are_blocked_before_the_search_starts() {
+        let map = TestMap::new(&["        #"; MAP_HEIGHT as usize]);
+        let TestMap { nodes, connectivity, components } = &map;
+        let (start, goal) = (node_index(2, 3, 0), node_index(12, 10, 0));
+        // the zone covers the goal, and the nodes against the wall that get closest to it
+        let no_go = vec![Zone::new(0, (6, 8), (15, 12))];
+        let constraints = QueryConstraints { no_go, ..QueryConstraints::default() };
+        let search = |goal: usize, limits: &SearchLimits| {
+            SlicedSearch::new(start, goal, nodes, connectivity, components, limits, &constraints)
+        };
+
+        let mut blocked = search(goal, &SearchLimits::default());
+        assert_eq!(blocked.finish(nodes, connectivity), Err(PathError::GoalBlocked));
+        assert_eq!(blocked.expansions(), 0);
+
+        // a partial path stops short of the zone instead of heading for a node in it
+        let mut partial = search(goal, &SearchLimits { allow_partial: true, ..SearchLimits::default() });
+        let path = partial.finish(nodes, connectivity).unwrap();
+        assert_eq!(path.partial, Some(PathError::GoalBlocked));
+        assert_eq!((path.nodes.last(), path.cost), (Some(&node_index(7, 7, 0)), 5 + 4));
+        assert!(path.nodes.iter().all(|&index| constraints.allows(&nodes[index])));
+    }
+
+    #[test]
+    fn every_tie_breaking_policy_finds_the_same_cost() {
+        // a block in the way on an open map, so lots of paths tie
+        let map = TestMap::new(&["",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "     #####",
+                                 "     #####",
+                                 "     #####",
+                                 "     #####",
+                                 "     #####"]);
+        let (start, goal) = (node_index(2, 2, 0), node_index(9, 12, 0));
+
+        let expansions: Vec<usize> = TieBreaking::ALL.iter()
+            .map(|&tie_breaking| {
+                let mut search = map.sliced(start, goal, &SearchLimits { tie_breaking, ..SearchLimits::default() });
+                assert_eq!(search.finish(&map.nodes, &map.connectivity).map(|path| path.cost), Ok(7 + 10));
+                search.expansions()
+            })
+            .collect();
+        let expansions = |policy: TieBreaking| expansions[TieBreaking::ALL.iter().position(|&p| p == policy).unwrap()];
+        // when the scores tie the state further from the start is the one closer to the goal, so higher g and lower h
+        // are the same order, and it gets to the goal sooner than going by the index
+        assert_eq!(expansions(TieBreaking::HigherG), expansions(TieBreaking::LowerH));
+        assert!(expansions(TieBreaking::HigherG) < expansions(TieBreaking::Index));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::constraints::QueryConstraints;
+use crate::{get_neighbors, Connectivity, Node};
+
+
+/**
+Anything `GraphSearch` can find its way through: a set of states, the moves between them and a guess at what getting
+from one to another costs. The search keeps its scores in vectors, so every state has to have a number of its own below
+`state_count`, which `index` gives and `state` turns back into the state.
+ */
+pub trait SearchSpace {
+    type State: Copy + Eq;
+
+    fn state_count(&self) -> usize;
+
+    fn index(&self, state: Self::State) -> usize;
+
+    fn state(&self, index: usize) -> Self::State;
+
+    /**
+    Every state we can move to from `state` together with what the move costs.
+     */
+    fn successors(&self, state: Self::State) -> Vec<(Self::State, i32)>;
+
+    /**
+    A guess at what it costs to get from `state` to `goal`. It must never be more than the real cost, or the search
+    can miss the cheapest path. i32::MAX means the goal can't be reached from `state` at all.
+     */
+    fn heuristic(&self, state: Self::State, goal: Self::State) -> i32;
+
+    /**
+    Where `state` is, for the cross product tie-breaking. Spaces without positions break those ties by state number.
+     */
+    fn position(&self, _state: Self::State) -> Option<(f32, f32)> {
+        None
+    }
+}
+
+
+/**
+The grid as a search space. The states are node indices, the moves are the ones `get_neighbors` gives that don't step
+onto an obstacle or break the `constraints`, and the heuristic is `Node::heuristic`.
+ */
+pub struct GridSpace<'a> {
+    pub nodes: &'a [Node],
+    pub connectivity: &'a Connectivity,
+    pub constraints: &'a QueryConstraints,
+}
+
+impl SearchSpace for GridSpace<'_> {
+    type State = usize;
+
+    fn state_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn index(&self, state: usize) -> usize {
+        state
+    }
+
+    fn state(&self, index: usize) -> usize {
+        index
+    }
+
+    fn successors(&self, state: usize) -> Vec<(usize, i32)> {
+        // grid steps cost what the terrain we step onto costs, portals set their own cost. The constraints of the query
+        // can make either dearer.
+        get_neighbors(state, self.nodes, self.connectivity)
+            .into_iter()
+            .filter(|&(neighbor_index, _)| {
+                !self.nodes[neighbor_index].obstacle && self.constraints.allows(&self.nodes[neighbor_index])
+            })
+            .map(|(neighbor_index, cost)| {
+                (neighbor_index, self.constraints.step_cost(cost, &self.nodes[neighbor_index]))
+            })
+            .collect()
+    }
+
+    fn heuristic(&self, state: usize, goal: usize) -> i32 {
+        self.nodes[state].heuristic(&self.nodes[goal], self.nodes, self.connectivity)
+    }
+
+    fn position(&self, state: usize) -> Option<(f32, f32)> {
+        Some((self.nodes[state].x as f32, self.nodes[state].y as f32))
+    }
+}
@@ -0,0 +1,51 @@
+use olc_pixel_game_engine::{Pixel, DARK_BLUE, DARK_CYAN, DARK_GREEN};
+
+
+/**
+What the ground of a node is like. Every step onto a node costs what its terrain costs, so the search goes around
+rough ground when that's cheaper. Plain ground costs 1 like every step used to.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    #[default]
+    Plain,
+    Forest,
+    Swamp,
+}
+
+impl Terrain {
+    pub const ALL: [Terrain; 3] = [Terrain::Plain, Terrain::Forest, Terrain::Swamp];
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /**
+    What a step onto a node with this terrain costs. Never less than 1, which keeps the heuristic a lower bound.
+     */
+    pub fn cost(&self) -> i32 {
+        match self {
+            Terrain::Plain => 1,
+            Terrain::Forest => 2,
+            Terrain::Swamp => 4,
+        }
+    }
+
+    /**
+    The color free nodes with this terrain are drawn in.
+     */
+    pub fn color(&self) -> Pixel {
+        match self {
+            Terrain::Plain => DARK_BLUE,
+            Terrain::Forest => DARK_GREEN,
+            Terrain::Swamp => DARK_CYAN,
+        }
+    }
+
+    /**
+    The next terrain in `ALL`, back to the first after the last.
+     */
+    pub fn next(&self) -> Terrain {
+        Terrain::ALL[(self.index() + 1) % Terrain::ALL.len()]
+    }
+}
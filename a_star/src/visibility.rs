@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+
+use crate::geometry::{edge_cost, heuristic_cost, path_length};
+use crate::limits::SearchLimits;
+use crate::path::PathError;
+use crate::scratch::SearchScratch;
+use crate::sliced::GraphSearch;
+use crate::space::SearchSpace;
+use crate::{node_index, Node, MAP_HEIGHT, MAP_WIDTH};
+
+
+/**
+The shortest any-angle path through a `VisibilityGraph`: the start, the corners it goes around and the goal, and how
+long it is in nodes.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct VisibilityPath {
+    pub points: Vec<(f32, f32)>,
+    pub length: f32,
+}
+
+
+/**
+One layer of the map seen as polygons instead of nodes. The obstacle nodes that touch along a side are merged into
+polygons, and every convex corner of a polygon can see the corners it has a clear straight line to. The shortest path
+between two points only ever bends around convex corners, so searching these lines of sight gives the truly shortest
+path, at any angle rather than along the grid.
+
+Positions are in nodes with the middle of node (0, 0) at (0, 0), the same as the poses of the hybrid search, so a node
+covers the square half a node either side of its position. Walls, one-way edges, terrain and portals don't count here,
+only obstacles.
+ */
+pub struct VisibilityGraph {
+    pub layer: i32,
+    // the outlines of the merged obstacles, each going around with the obstacle on its right
+    pub polygons: Vec<Vec<(f32, f32)>>,
+    // the convex corners, and for each of them the corners it can see together with how far away they are
+    pub corners: Vec<(f32, f32)>,
+    pub edges: Vec<Vec<(usize, i32)>>,
+    // a copy of the obstacles on the layer, so the graph can check lines of sight without the map
+    obstacles: ObstacleMap,
+}
+
+impl VisibilityGraph {
+    pub fn build(layer: i32, nodes: &[Node]) -> VisibilityGraph {
+        let mut graph = VisibilityGraph {
+            layer,
+            polygons: Vec::new(),
+            corners: Vec::new(),
+            edges: Vec::new(),
+            obstacles: ObstacleMap::new(layer, nodes),
+        };
+
+        graph.polygons = graph.trace_polygons();
+
+        // a lattice point between four nodes is a convex corner when exactly one of them is an obstacle. Off the map
+        // counts as an obstacle, since we never have to go around the edge of the map.
+        for corner_y in 0..=MAP_HEIGHT {
+            for corner_x in 0..=MAP_WIDTH {
+                let around = [(-1, -1), (0, -1), (-1, 0), (0, 0)].iter()
+                    .filter(|&&(dx, dy)| graph.obstacles.is_blocked(corner_x + dx, corner_y + dy))
+                    .count();
+                if around == 1 {
+                    graph.corners.push((corner_x as f32 - 0.5, corner_y as f32 - 0.5));
+                }
+            }
+        }
+
+        graph.edges = vec![Vec::new(); graph.corners.len()];
+        for from in 0..graph.corners.len() {
+            for to in from + 1..graph.corners.len() {
+                let (a, b) = (graph.corners[from], graph.corners[to]);
+                if graph.obstacles.can_see(a, b) {
+                    let cost = edge_cost(a, b);
+                    graph.edges[from].push((to, cost));
+                    graph.edges[to].push((from, cost));
+                }
+            }
+        }
+        graph
+    }
+
+    /**
+    The shortest path from `start` to `goal`, both of which have to be in free nodes on the layer of the graph.
+     */
+    pub fn find_path(&self, start: (f32, f32), goal: (f32, f32), limits: &SearchLimits)
+                     -> Result<VisibilityPath, PathError> {
+        self.obstacles.check_endpoints(start, goal)?;
+
+        let query = Query::new(self, start, goal);
+        let (start_index, goal_index) = (self.corners.len(), self.corners.len() + 1);
+        // a path that stops at some corner short of the goal isn't much use, so we never settle for one
+        let limits = SearchLimits { allow_partial: false, ..*limits };
+        let path = GraphSearch::new(&[start_index], &[goal_index], &query, &limits, SearchScratch::default())
+            .finish(&query)?;
+
+        let points: Vec<(f32, f32)> = path.nodes.iter().map(|&index| query.position(index)).collect();
+        let length = path_length(&points);
+        Ok(VisibilityPath { points, length })
+    }
+
+    /**
+    Follows the sides of the obstacle nodes that face a free node, or the edge of the map, into closed outlines. Sides
+    that carry on in a straight line are joined into one.
+     */
+    fn trace_polygons(&self) -> Vec<Vec<(f32, f32)>> {
+        // every side goes clockwise around its node, between lattice points, from where it starts to where it ends
+        let mut sides: HashMap<(i32, i32), Vec<(i32, i32)>> = HashMap::new();
+        let outside = |x: i32, y: i32| !(0..MAP_WIDTH).contains(&x) || !(0..MAP_HEIGHT).contains(&y);
+        let facing_free = |x: i32, y: i32| outside(x, y) || !self.obstacles.is_blocked(x, y);
+
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                if !self.obstacles.is_blocked(x, y) {
+                    continue;
+                }
+                let mut add = |from: (i32, i32), to: (i32, i32)| sides.entry(from).or_default().push(to);
+                if facing_free(x, y - 1) {
+                    add((x, y), (x + 1, y));
+                }
+                if facing_free(x + 1, y) {
+                    add((x + 1, y), (x + 1, y + 1));
+                }
+                if facing_free(x, y + 1) {
+                    add((x + 1, y + 1), (x, y + 1));
+                }
+                if facing_free(x - 1, y) {
+                    add((x, y + 1), (x, y));
+                }
+            }
+        }
+
+        let mut polygons = Vec::new();
+        while let Some(&start) = sides.keys().next() {
+            let mut outline = vec![start];
+            let mut at = start;
+            loop {
+                let ends = sides.get_mut(&at).unwrap();
+                let next = ends.pop().unwrap();
+                if ends.is_empty() {
+                    sides.remove(&at);
+                }
+                if next == start {
+                    break;
+                }
+                outline.push(next);
+                at = next;
+            }
+
+            // keep only the points where the outline turns
+            let count = outline.len();
+            let turns: Vec<(f32, f32)> = (0..count)
+                .filter(|&i| {
+                    let (previous, point) = (outline[(i + count - 1) % count], outline[i]);
+                    let next = outline[(i + 1) % count];
+                    (point.0 - previous.0, point.1 - previous.1) != (next.0 - point.0, next.1 - point.1)
+                })
+                .map(|i| (outline[i].0 as f32 - 0.5, outline[i].1 as f32 - 0.5))
+                .collect();
+            polygons.push(turns);
+        }
+        polygons
+    }
+}
+
+
+/**
+Which nodes on one layer of the map are obstacles, for checking straight lines between positions against them. Off
+the map counts as an obstacle too.
+ */
+#[derive(Clone, Debug)]
+pub struct ObstacleMap {
+    blocked: Vec<bool>,
+}
+
+impl ObstacleMap {
+    pub fn new(layer: i32, nodes: &[Node]) -> ObstacleMap {
+        let blocked = (0..MAP_HEIGHT)
+            .flat_map(|y| (0..MAP_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| nodes[node_index(x, y, layer)].obstacle)
+            .collect();
+        ObstacleMap { blocked }
+    }
+
+    /**
+    Whether the straight line from `a` to `b` stays out of every obstacle. Running along the side of an obstacle is
+    fine, but squeezing through the point where two obstacles touch diagonally isn't.
+     */
+    pub fn can_see(&self, a: (f32, f32), b: (f32, f32)) -> bool {
+        let min_x = (a.0.min(b.0) + 0.5).floor() as i32;
+        let max_x = (a.0.max(b.0) + 0.5).ceil() as i32;
+        let min_y = (a.1.min(b.1) + 0.5).floor() as i32;
+        let max_y = (a.1.max(b.1) + 0.5).ceil() as i32;
+
+        // one node more on every side, for lines that run along the side of a node
+        for y in (min_y - 1).max(0)..(max_y + 1).min(MAP_HEIGHT) {
+            for x in (min_x - 1).max(0)..(max_x + 1).min(MAP_WIDTH) {
+                if self.is_blocked(x, y) && self.crosses_node(a, b, x, y) {
+                    return false;
+                }
+            }
+        }
+
+        // the lattice points where two obstacles touch corner to corner
+        for corner_y in min_y.max(1)..=max_y.min(MAP_HEIGHT - 1) {
+            for corner_x in min_x.max(1)..=max_x.min(MAP_WIDTH - 1) {
+                let pinched = (self.is_blocked(corner_x - 1, corner_y - 1) && self.is_blocked(corner_x, corner_y)
+                    && !self.is_blocked(corner_x, corner_y - 1) && !self.is_blocked(corner_x - 1, corner_y))
+                    || (self.is_blocked(corner_x, corner_y - 1) && self.is_blocked(corner_x - 1, corner_y)
+                    && !self.is_blocked(corner_x - 1, corner_y - 1) && !self.is_blocked(corner_x, corner_y));
+                if pinched && passes_through(a, b, (corner_x as f32 - 0.5, corner_y as f32 - 0.5)) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /**
+    Whether the line from `a` to `b` goes through the inside of the obstacle node at (x, y). The sides it shares with
+    other obstacles count as inside too, since they're in the middle of the polygon, but the sides facing free nodes
+    don't. We clip the line against the square one axis at a time and see if anything is left.
+     */
+    fn crosses_node(&self, a: (f32, f32), b: (f32, f32), x: i32, y: i32) -> bool {
+        // a little slack keeps lines that run right along an outside edge, or just touch a corner, from counting
+        const EPSILON: f32 = 1e-4;
+        let slack = |dx: i32, dy: i32| if self.is_blocked(x + dx, y + dy) { EPSILON } else { -EPSILON };
+        let (mut enter, mut exit) = (0.0f32, 1.0f32);
+
+        let axes = [(a.0, b.0, x as f32 - 0.5 - slack(-1, 0), x as f32 + 0.5 + slack(1, 0)),
+                    (a.1, b.1, y as f32 - 0.5 - slack(0, -1), y as f32 + 0.5 + slack(0, 1))];
+        for (from, to, low, high) in axes {
+            let delta = to - from;
+            if delta.abs() < f32::EPSILON {
+                if from <= low || from >= high {
+                    return false;
+                }
+                continue;
+            }
+            let (t0, t1) = ((low - from) / delta, (high - from) / delta);
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+        enter < exit
+    }
+
+    pub fn is_blocked(&self, x: i32, y: i32) -> bool {
+        if !(0..MAP_WIDTH).contains(&x) || !(0..MAP_HEIGHT).contains(&y) {
+            return true;
+        }
+        self.blocked[(y * MAP_WIDTH + x) as usize]
+    }
+
+    /**
+    The node a position is in, or None when it's off the map.
+     */
+    pub fn node_at(&self, (x, y): (f32, f32)) -> Option<(i32, i32)> {
+        let (x, y) = ((x + 0.5).floor() as i32, (y + 0.5).floor() as i32);
+        if (0..MAP_WIDTH).contains(&x) && (0..MAP_HEIGHT).contains(&y) { Some((x, y)) } else { None }
+    }
+
+    /**
+    Makes sure a start and goal are on the map and outside every obstacle, and says which one isn't otherwise.
+     */
+    pub fn check_endpoints(&self, start: (f32, f32), goal: (f32, f32)) -> Result<(), PathError> {
+        match self.node_at(start) {
+            None => return Err(PathError::StartOutOfBounds),
+            Some((x, y)) if self.is_blocked(x, y) => return Err(PathError::StartBlocked),
+            _ => {}
+        }
+        match self.node_at(goal) {
+            None => Err(PathError::GoalOutOfBounds),
+            Some((x, y)) if self.is_blocked(x, y) => Err(PathError::GoalBlocked),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+    Whether a position is on the map and outside every obstacle.
+     */
+    pub fn is_free(&self, point: (f32, f32)) -> bool {
+        matches!(self.node_at(point), Some((x, y)) if !self.is_blocked(x, y))
+    }
+}
+
+
+/**
+The visibility graph with a start and a goal added for one query. Their states come after the corners: the start is
+`corners.len()` and the goal the one after it.
+ */
+struct Query<'a> {
+    graph: &'a VisibilityGraph,
+    start: (f32, f32),
+    goal: (f32, f32),
+    // the corners the start can see, and the corners the goal can see with the cost of getting from them to the goal
+    from_start: Vec<(usize, i32)>,
+    to_goal: HashMap<usize, i32>,
+}
+
+impl<'a> Query<'a> {
+    fn new(graph: &'a VisibilityGraph, start: (f32, f32), goal: (f32, f32)) -> Query<'a> {
+        let visible_from = |point: (f32, f32)| -> Vec<(usize, i32)> {
+            (0..graph.corners.len())
+                .filter(|&corner| graph.obstacles.can_see(point, graph.corners[corner]))
+                .map(|corner| (corner, edge_cost(point, graph.corners[corner])))
+                .collect()
+        };
+        let mut from_start = visible_from(start);
+        if graph.obstacles.can_see(start, goal) {
+            from_start.push((graph.corners.len() + 1, edge_cost(start, goal)));
+        }
+        Query { graph, start, goal, from_start, to_goal: visible_from(goal).into_iter().collect() }
+    }
+
+    fn position(&self, index: usize) -> (f32, f32) {
+        match index.checked_sub(self.graph.corners.len()) {
+            None => self.graph.corners[index],
+            Some(0) => self.start,
+            Some(_) => self.goal,
+        }
+    }
+}
+
+impl SearchSpace for Query<'_> {
+    type State = usize;
+
+    fn state_count(&self) -> usize {
+        self.graph.corners.len() + 2
+    }
+
+    fn index(&self, state: usize) -> usize {
+        state
+    }
+
+    fn state(&self, index: usize) -> usize {
+        index
+    }
+
+    fn successors(&self, state: usize) -> Vec<(usize, i32)> {
+        let corner_count = self.graph.corners.len();
+        if state == corner_count {
+            return self.from_start.clone();
+        }
+        if state > corner_count {
+            return Vec::new();
+        }
+
+        let mut successors = self.graph.edges[state].clone();
+        if let Some(&cost) = self.to_goal.get(&state) {
+            successors.push((corner_count + 1, cost));
+        }
+        successors
+    }
+
+    fn heuristic(&self, state: usize, goal: usize) -> i32 {
+        heuristic_cost(self.position(state), self.position(goal))
+    }
+}
+
+
+/**
+Whether `point` lies on the line from `a` to `b` somewhere between its ends.
+ */
+fn passes_through(a: (f32, f32), b: (f32, f32), point: (f32, f32)) -> bool {
+    const EPSILON: f32 = 1e-4;
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (px, py) = (point.0 - a.0, point.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    if length_squared < EPSILON {
+        return false;
+    }
+    let t = (px * dx + py * dy) / length_squared;
+    let off_line = (px * dy - py * dx).abs() / length_squared.sqrt();
+    off_line < EPSILON && t > EPSILON && t < 1.0 - EPSILON
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::distance;
+    use crate::path::PathError;
+    use crate::{TestMap, MAP_HEIGHT};
+
+    #[test]
+    fn paths_bend_around_corners_and_are_never_longer_than_the_grid_path() {
+        // a wall from the top down to row 11, with the start and goal on either side of it
+        let map = TestMap::new(&["       #"; 12]);
+        let graph = VisibilityGraph::build(0, &map.nodes);
+        let limits = SearchLimits::default();
+        let (start, goal) = ((2.0, 3.0), (13.0, 3.0));
+
+        // around the two bottom corners of the wall and straight on from there
+        let path = graph.find_path(start, goal, &limits).unwrap();
+        assert_eq!(path.points, [start, (6.5, 11.5), (7.5, 11.5), goal]);
+        assert!((path.length - (92.5f32.sqrt() + 1.0 + 102.5f32.sqrt())).abs() < 1e-3);
+        assert!(path.points.windows(2).all(|step| graph.obstacles.can_see(step[0], step[1])));
+        let grid_path = map.search(node_index(2, 3, 0), node_index(13, 3, 0)).unwrap();
+        assert!(path.length < grid_path.cost as f32);
+
+        // with nothing in the way it's the straight line
+        let path = graph.find_path(start, (5.0, 9.0), &limits).unwrap();
+        assert_eq!(path.points, [start, (5.0, 9.0)]);
+        assert!((path.length - distance(start, (5.0, 9.0))).abs() < 1e-3);
+
+        assert_eq!(graph.find_path(start, (7.0, 0.0), &limits), Err(PathError::GoalBlocked));
+        let walled_in = TestMap::new(&["###", "#.#", "###"]);
+        let graph = VisibilityGraph::build(0, &walled_in.nodes);
+        assert_eq!(graph.find_path((1.0, 1.0), (9.0, (MAP_HEIGHT - 1) as f32), &limits),
+                   Err(PathError::Unreachable));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::components::Components;
+use crate::constraints::QueryConstraints;
+use crate::limits::SearchLimits;
+use crate::path::{Path, PathError};
+use crate::sliced::SlicedSearch;
+use crate::{Connectivity, Node};
+
+
+/**
+A route through a list of stops, made of one path per leg between two stops that follow each other. If a leg only
+gets partway to its stop the route ends with that leg, and `partial` says why.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub legs: Vec<Path>,
+    pub cost: i32,
+    pub partial: Option<PathError>,
+}
+
+
+/**
+Finds the route that visits `stops` in the order given, from the first to the last. Every leg is searched on its own
+with the same `limits` and `constraints`.
+ */
+pub fn route_through(stops: &[usize],
+                     nodes: &[Node],
+                     connectivity: &Connectivity,
+                     components: &Components,
+                     limits: &SearchLimits,
+                     constraints: &QueryConstraints) -> Result<Route, PathError> {
+    let mut route = Route { legs: Vec::new(), cost: 0, partial: None };
+
+    for leg in stops.windows(2) {
+        let path = SlicedSearch::new(leg[0], leg[1], nodes, connectivity, components, limits, constraints)
+            .finish(nodes, connectivity)?;
+        route.cost += path.cost;
+        route.partial = path.partial;
+        route.legs.push(path);
+
+        // there's no point going on to the next stop from somewhere we didn't mean to end up
+        if route.partial.is_some() {
+            break;
+        }
+    }
+
+    Ok(route)
+}
+
+
+/**
+Picks the order to visit `waypoints` in on the way from `start` to `goal` so the whole route is as short as we can
+easily make it. This is a travelling salesman problem, so instead of trying every order we take the nearest waypoint
+each time and then improve on that with 2-opt. The distances come from a full search between every pair of stops, and
+they don't have to be the same both ways, since teleporters only go one way. They follow `constraints`, so the order
+suits the route we will actually take.
+ */
+pub fn visiting_order(start: usize,
+                      waypoints: &[usize],
+                      goal: usize,
+                      nodes: &[Node],
+                      connectivity: &Connectivity,
+                      components: &Components,
+                      constraints: &QueryConstraints) -> Result<Vec<usize>, PathError> {
+    // stop 0 is the start, the waypoints follow and the goal is the last stop
+    let stops: Vec<usize> = std::iter::once(start)
+        .chain(waypoints.iter().copied())
+        .chain(std::iter::once(goal))
+        .collect();
+    let distances = pairwise_distances(&stops, nodes, connectivity, components, constraints);
+    let goal_stop = stops.len() - 1;
+
+    // nearest neighbor: from wherever we are, go to the closest waypoint we haven't been to yet
+    let mut order = vec![0];
+    let mut left: Vec<usize> = (1..goal_stop).collect();
+    while !left.is_empty() {
+        let current = order[order.len() - 1];
+        let nearest = (0..left.len()).min_by_key(|&i| distances[current][left[i]]).unwrap();
+        order.push(left.swap_remove(nearest));
+    }
+    order.push(goal_stop);
+
+    // 2-opt: keep reversing a stretch of the waypoints while that makes the route shorter. The start and the goal stay
+    // where they are. Reversing a stretch turns around every leg inside it, which changes their cost when the
+    // distances aren't the same both ways, so we compare whole routes.
+    let mut best_cost = route_cost(&order, &distances);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in 1..goal_stop {
+            for j in i + 1..goal_stop {
+                order[i..=j].reverse();
+                let cost = route_cost(&order, &distances);
+                if cost < best_cost {
+                    best_cost = cost;
+                    improved = true;
+                } else {
+                    order[i..=j].reverse();
+                }
+            }
+        }
+    }
+
+    if best_cost == i32::MAX {
+        return Err(PathError::Unreachable);
+    }
+    Ok(order[1..goal_stop].iter().map(|&stop| stops[stop]).collect())
+}
+
+
+/**
+The cost of the cheapest path from every stop to every other stop, i32::MAX where there is none.
+ */
+fn pairwise_distances(stops: &[usize],
+                      nodes: &[Node],
+                      connectivity: &Connectivity,
+                      components: &Components,
+                      constraints: &QueryConstraints) -> Vec<Vec<i32>> {
+    // no limits and no partial paths, we want the real distances
+    let limits = SearchLimits::default();
+
+    stops.iter().map(|&from| {
+        stops.iter().map(|&to| {
+            let mut search = SlicedSearch::new(from, to, nodes, connectivity, components, &limits, constraints);
+            match search.finish(nodes, connectivity) {
+                Ok(path) => path.cost,
+                Err(_) => i32::MAX,
+            }
+        }).collect()
+    }).collect()
+}
+
+
+fn route_cost(order: &[usize], distances: &[Vec<i32>]) -> i32 {
+    order.windows(2).fold(0, |cost: i32, leg| cost.saturating_add(distances[leg[0]][leg[1]]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node_index, TestMap};
+
+    #[test]
+    fn the_route_visits_every_waypoint_and_the_order_is_no_worse() {
+        let map = TestMap::new(&[]);
+        let TestMap { nodes, connectivity, components } = &map;
+        let (limits, constraints) = (SearchLimits::default(), QueryConstraints::default());
+        // waypoints along the diagonal, given in an order that goes back and forth along it
+        let (start, goal) = (node_index(0, 0, 0), node_index(15, 15, 0));
+        let waypoints = [node_index(10, 10, 0), node_index(2, 2, 0), node_index(12, 12, 0), node_index(5, 5, 0)];
+
+        let order = visiting_order(start, &waypoints, goal, nodes, connectivity, components, &constraints).unwrap();
+        assert_eq!(order, [waypoints[1], waypoints[3], waypoints[0], waypoints[2]]);
+
+        let route = |waypoints: &[usize]| {
+            let stops: Vec<usize> = std::iter::once(start)
+                .chain(waypoints.iter().copied())
+                .chain(std::iter::once(goal))
+                .collect();
+            let route = route_through(&stops, nodes, connectivity, components, &limits, &constraints).unwrap();
+            // every leg starts where the one before it ended, at the next stop
+            for (leg, stop) in route.legs.iter().zip(stops.windows(2)) {
+                assert_eq!((leg.nodes[0], leg.nodes[leg.nodes.len() - 1]), (stop[0], stop[1]));
+            }
+            assert_eq!(route.legs.len(), stops.len() - 1);
+            assert_eq!(route.cost, route.legs.iter().map(|leg| leg.cost).sum::<i32>());
+            route.cost
+        };
+        // in order it's no longer than going straight to the goal, and the order we were given doubles back twice
+        assert_eq!(route(&order), 30);
+        assert_eq!(route(&waypoints), 20 + 16 + 20 + 14 + 20);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::components::Components;
+use crate::constraints::QueryConstraints;
+use crate::limits::SearchLimits;
+use crate::path::{Path, PathError};
+use crate::scratch::SearchScratch;
+use crate::sliced::SlicedSearch;
+use crate::space::{GridSpace, SearchSpace};
+use crate::{Connectivity, Node};
+
+
+/**
+Finds up to `k` different loopless paths from `start_index` to `goal_index`, cheapest first, using Yen's algorithm.
+The first is the path `a_star` would find. Every one after that leaves one of the paths found so far at some node, the
+spur node, and takes the cheapest way from there to the goal that doesn't use a move the paths sharing its way up to
+the spur node already took, and doesn't go back through any node before it. The cheapest of all those candidates is
+the next path. Fewer than `k` paths come back when there aren't that many. Every path follows `constraints`, like the
+path the query itself finds.
+ */
+pub fn k_shortest_paths(start_index: usize,
+                        goal_index: usize,
+                        k: usize,
+                        nodes: &[Node],
+                        connectivity: &Connectivity,
+                        components: &Components,
+                        constraints: &QueryConstraints) -> Result<Vec<Path>, PathError> {
+    // the alternatives are only any good if they really get to the goal, so no limits and no partial paths
+    let limits = SearchLimits::default();
+    // we run a lot of searches, so they all share one scratch space
+    let mut scratch = SearchScratch::default();
+    let mut search = |from: usize, excluded_nodes: &[usize], excluded_moves: &[(usize, usize)]| {
+        let space = GridSpace { nodes, connectivity, constraints };
+        let mut search = SlicedSearch::reusing(&[from], &[goal_index], &space, components, &limits,
+                                               std::mem::take(&mut scratch))
+            .excluding(excluded_nodes, excluded_moves);
+        let result = search.finish(nodes, connectivity);
+        scratch = search.into_scratch();
+        result
+    };
+
+    let mut paths = vec![search(start_index, &[], &[])?];
+    let mut candidates: Vec<Path> = Vec::new();
+
+    while paths.len() < k {
+        let last = &paths[paths.len() - 1].nodes;
+
+        for spur_position in 0..last.len() - 1 {
+            let spur_index = last[spur_position];
+            let root = &last[..=spur_position];
+
+            // the paths that got to the spur node the same way as this one can't leave it the same way again
+            let excluded_moves: Vec<(usize, usize)> = paths.iter()
+                .filter(|path| path.nodes.len() > spur_position + 1 && path.nodes[..=spur_position] == *root)
+                .map(|path| (spur_index, path.nodes[spur_position + 1]))
+                .collect();
+
+            if let Ok(spur_path) = search(spur_index, &root[..spur_position], &excluded_moves) {
+                let mut candidate_nodes = root[..spur_position].to_vec();
+                candidate_nodes.extend(&spur_path.nodes);
+                let candidate = Path {
+                    nodes: candidate_nodes,
+                    cost: walk_cost(root, nodes, connectivity, constraints) + spur_path.cost,
+                    partial: None,
+                };
+
+                if !candidates.contains(&candidate) && !paths.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+
+        // the cheapest candidate is the next path. Between equally cheap ones we take the one with the fewest steps.
+        let cheapest = (0..candidates.len()).min_by_key(|&i| (candidates[i].cost, candidates[i].nodes.len()));
+        match cheapest {
+            Some(i) => paths.push(candidates.swap_remove(i)),
+            None => break,
+        }
+    }
+
+    Ok(paths)
+}
+
+
+/**
+What it costs to walk from the first to the last of `steps`, taking the cheapest move between each pair of them, with
+the moves priced the way `constraints` say.
+ */
+fn walk_cost(steps: &[usize], nodes: &[Node], connectivity: &Connectivity, constraints: &QueryConstraints) -> i32 {
+    let space = GridSpace { nodes, connectivity, constraints };
+    steps.windows(2).map(|step| {
+        space.successors(step[0])
+            .into_iter()
+            .filter(|&(neighbor_index, _)| neighbor_index == step[1])
+            .map(|(_, cost)| cost)
+            .min()
+            .expect("the steps of a path are always neighbors")
+    }).sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::Zone;
+    use crate::{node_index, TestMap};
+
+    #[test]
+    fn paths_are_loopless_distinct_and_cheapest_first() {
+        // a patch of forest right on the straight line, so only that line costs less than going around it
+        let map = TestMap::new(&["",
+                                 "",
+                                 "",
+                                 "",
+                                 "",
+                                 "      f"]);
+        let TestMap { nodes, connectivity, components } = &map;
+        let constraints = QueryConstraints::default();
+        let (start, goal) = (node_index(2, 5, 0), node_index(10, 5, 0));
+
+        let paths = k_shortest_paths(start, goal, 8, nodes, connectivity, components, &constraints).unwrap();
+        assert_eq!(paths.len(), 8);
+        assert_eq!(paths[0], map.search(start, goal).unwrap());
+        assert_eq!((paths[0].nodes.len(), paths[0].cost), (9, 7 + 2));
+        // every other one steps a row over to get past the forest, for two steps more
+        assert!(paths[1..].iter().all(|path| path.cost == 10));
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!((path.nodes[0], path.nodes[path.nodes.len() - 1]), (start, goal));
+            let mut visited = path.nodes.clone();
+            visited.sort();
+            visited.dedup();
+            assert_eq!(visited.len(), path.nodes.len(), "path {} goes through a node twice", i);
+            assert_eq!(path.cost, walk_cost(&path.nodes, nodes, connectivity, &constraints));
+            assert!(!paths[..i].contains(path));
+        }
+    }
+
+    #[test]
+    fn alternatives_follow_the_constraints() {
+        let map = TestMap::new(&[]);
+        let TestMap { nodes, connectivity, components } = &map;
+        // a no-go zone right across the middle of the straight line, with room to go around it either side
+        let no_go = vec![Zone::new(0, (7, 5), (8, 10))];
+        let constraints = QueryConstraints { no_go, ..QueryConstraints::default() };
+
+        let paths = k_shortest_paths(node_index(2, 8, 0), node_index(13, 8, 0), 5, nodes, connectivity, components,
+                                     &constraints).unwrap();
+        assert_eq!(paths.len(), 5);
+        // the cheapest ones go round the bottom of the zone, 3 rows down and back up
+        assert_eq!(paths[0].cost, 11 + 2 * 3);
+        for path in &paths {
+            assert!(path.nodes.iter().all(|&index| constraints.allows(&nodes[index])));
+        }
+    }
+}